@@ -1,4 +1,4 @@
-use winit::event::ElementState;
+use winit::event::{ElementState, MouseButton};
 use winit::keyboard::KeyCode;
 use winit::dpi::PhysicalPosition;
 
@@ -6,6 +6,9 @@ use std::collections::HashSet;
 
 pub struct InputState {
     pressed_keys: HashSet<KeyCode>,
+    pressed_buttons: HashSet<MouseButton>,
+    /// Buttons pressed since the end of the last frame.
+    clicked_buttons: HashSet<MouseButton>,
     mouse_delta: (f64, f64),
     mouse_position: PhysicalPosition<f64>,
 }
@@ -20,6 +23,8 @@ impl InputState {
     pub fn new() -> Self {
         Self {
             pressed_keys: HashSet::new(),
+            pressed_buttons: HashSet::new(),
+            clicked_buttons: HashSet::new(),
             mouse_delta: (0.0, 0.0),
             mouse_position: PhysicalPosition::new(0.0, 0.0),
         }
@@ -32,6 +37,18 @@ impl InputState {
         };
     }
 
+    pub fn on_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                self.pressed_buttons.insert(button);
+                self.clicked_buttons.insert(button);
+            }
+            ElementState::Released => {
+                self.pressed_buttons.remove(&button);
+            }
+        }
+    }
+
     pub fn on_mouse_move(&mut self, delta: (f64, f64)) {
         let (dx, dy) = self.mouse_delta;
         self.mouse_delta = (dx + delta.0, dy + delta.1);
@@ -43,12 +60,23 @@ impl InputState {
 
     pub fn on_frame_end(&mut self) {
         self.mouse_delta = (0.0, 0.0);
+        self.clicked_buttons.clear();
     }
 
     pub fn is_key_pressed(&self, code: KeyCode) -> bool {
         self.pressed_keys.contains(&code)
     }
 
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_buttons.contains(&button)
+    }
+
+    /// Whether the button was pressed during this frame, even if it has been
+    /// released again already.
+    pub fn was_button_clicked(&self, button: MouseButton) -> bool {
+        self.clicked_buttons.contains(&button)
+    }
+
     pub fn mdelta(&self) -> (f64, f64) {
         self.mouse_delta
     }
@@ -0,0 +1,55 @@
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable with the log filter, in `RUST_LOG` syntax, for
+/// example `debug` or `shallow_stone=trace,wgpu_core=warn`.
+pub const FILTER_VAR: &str = "SHALLOW_STONE_LOG";
+/// Environment variable with a path to write a Chrome trace to, to be
+/// opened in `chrome://tracing` or Perfetto.
+pub const TRACE_VAR: &str = "SHALLOW_STONE_TRACE";
+
+/// Used when [`FILTER_VAR`] is unset. wgpu logs a lot at `info`.
+const DEFAULT_FILTER: &str = "info,wgpu_core=warn,wgpu_hal=warn,naga=warn";
+
+/// Writes the Chrome trace, if any, when dropped. Keep it alive until the
+/// program exits.
+pub struct LogGuard {
+    _chrome: Option<tracing_chrome::FlushGuard>,
+}
+
+/// Log to stderr, filtered by [`FILTER_VAR`], and record spans to a Chrome
+/// trace if [`TRACE_VAR`] is set.
+pub fn init() -> LogGuard {
+    let filter =
+        EnvFilter::try_from_env(FILTER_VAR).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    let (chrome, guard) = match std::env::var_os(TRACE_VAR) {
+        Some(path) => {
+            let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build();
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(chrome)
+        .init();
+
+    LogGuard { _chrome: guard }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_filter_parses() {
+        assert!(EnvFilter::try_new(DEFAULT_FILTER).is_ok());
+    }
+}
@@ -1,24 +1,28 @@
 #![allow(unused)]
 
 pub mod input;
+pub mod logging;
 pub mod render;
 pub mod world;
 
 pub mod macros;
 
 use winit::keyboard::{Key, KeyCode, PhysicalKey};
+use world::registry::BlockRegistry;
+use world::save::WorldSave;
 use world::World;
 
-use std::time::Instant;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use winit::application::ApplicationHandler;
 use winit::dpi::{PhysicalPosition, PhysicalSize};
-use winit::event::{DeviceEvent, ElementState, KeyEvent, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, KeyEvent, MouseButton, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::window::{CursorGrabMode, WindowId};
 
 use input::InputState;
-use render::GfxContext;
+use render::{screenshot, GfxContext};
 
 use pollster::block_on;
 
@@ -36,18 +40,26 @@ struct InitializedApp {
     mouse_grabbed: bool,
 
     last_update: std::time::Instant,
+    /// Time not yet simulated, less than one step after `update`.
+    unsimulated: Duration,
 
     pub input: InputState,
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        println!("Resumed");
+        tracing::info!("Resumed");
 
-        drop(self.state.take());
+        self.close();
 
-        let mut state = block_on(InitializedApp::create(event_loop))
-            .expect("Could not create a render context");
+        let mut state = match block_on(InitializedApp::create(event_loop)) {
+            Ok(state) => state,
+            Err(err) => {
+                tracing::error!("Could not start: {err:#}");
+                event_loop.exit();
+                return;
+            }
+        };
 
         state.initialize();
 
@@ -69,6 +81,9 @@ impl ApplicationHandler for App {
                 WindowEvent::KeyboardInput { event, .. } => {
                     state.on_keyboard_key(event, event_loop);
                 }
+                WindowEvent::MouseInput { button, state: button_state, .. } => {
+                    state.on_mouse_button(button, button_state);
+                }
                 WindowEvent::CursorMoved {
                     device_id,
                     position,
@@ -93,32 +108,82 @@ impl ApplicationHandler for App {
         }
     }
 
-    fn about_to_wait(&mut self, _loop: &ActiveEventLoop) {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         if let Some(state) = &mut self.state {
+            let _frame = tracing::info_span!("frame").entered();
             state.update();
-            state.render();
+            if let Err(err) = state.render() {
+                tracing::error!("Could not render: {err:#}");
+                event_loop.exit();
+                return;
+            }
             state.input.on_frame_end();
         }
     }
 
     fn suspended(&mut self, _loop: &ActiveEventLoop) {
-        println!("Suspended");
+        tracing::info!("Suspended");
         // Replace render context with None, dropping the current one
-        self.state.take();
+        self.close();
+    }
+
+    fn exiting(&mut self, _loop: &ActiveEventLoop) {
+        self.close();
+    }
+}
+
+impl App {
+    /// Save the world and drop the current state, if any.
+    fn close(&mut self) {
+        if let Some(mut state) = self.state.take() {
+            if let Err(err) = state.world.save() {
+                tracing::error!("Could not save the world: {err:#}");
+            }
+        }
     }
 }
 
 impl InitializedApp {
+    /// Length of a simulation step, independent of the frame rate.
+    const TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+    /// Longest frame simulated in full. Longer stalls, like dragging the
+    /// window, are skipped instead of caught up with.
+    const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+    /// Keys selecting the block to place, in registry order.
+    const BLOCK_KEYS: [KeyCode; 9] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+
     async fn create(evento_loop: &ActiveEventLoop) -> Result<Self> {
         let gfx = GfxContext::create(evento_loop).await?;
 
-        let world = World::new(&gfx);
+        let seed = std::env::var("SHALLOW_STONE_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or(World::DEFAULT_SEED);
+
+        let registry = BlockRegistry::load_default().context("Load block definitions")?;
+
+        let save_dir = std::env::var("SHALLOW_STONE_SAVE")
+            .unwrap_or_else(|_| WorldSave::DEFAULT_DIR.to_owned());
+        let save = WorldSave::open(save_dir).context("Open world save")?;
+
+        let world = World::new(&gfx, seed, registry, save).context("Load world")?;
 
         Ok(Self {
             gfx,
             world,
             mouse_grabbed: false,
             last_update: Instant::now(),
+            unsimulated: Duration::ZERO,
             input: InputState::new(),
         })
     }
@@ -132,24 +197,48 @@ impl InitializedApp {
     }
 
     pub fn update(&mut self) {
+        let _span = tracing::info_span!("update").entered();
+
         let now = Instant::now();
         let duration = now.duration_since(self.last_update);
+        self.last_update = now;
 
-        let delta_time = duration.as_secs_f32();
-
-        self.world.update(delta_time, &self.input);
+        self.unsimulated += duration.min(Self::MAX_FRAME_TIME);
+        while self.unsimulated >= Self::TIMESTEP {
+            self.world.update(Self::TIMESTEP.as_secs_f32(), &self.input);
+            self.unsimulated -= Self::TIMESTEP;
+        }
 
-        self.last_update = now;
+        let alpha = self.unsimulated.as_secs_f32() / Self::TIMESTEP.as_secs_f32();
+        self.world.update_frame(&self.input, alpha);
     }
 
-    pub fn render(&mut self) {
-        self.gfx.render(&mut self.world);
+    /// Draw a frame. Errors the renderer can't recover from are returned.
+    pub fn render(&mut self) -> Result<()> {
+        self.gfx.render(&mut self.world)
     }
 
     pub fn on_keyboard_key(&mut self, event: KeyEvent, event_loop: &ActiveEventLoop) {
         match event {
             key_press!(Escape) => event_loop.exit(),
             key_press!(KeyG) => self.grab_mouse(),
+            key_press!(KeyM) => self.world.cycle_meshing_mode(),
+            key_press!(KeyK) => self.world.toggle_ambient_occlusion(),
+            key_press!(Equal) => self.world.change_render_distance(1),
+            key_press!(Minus) => self.world.change_render_distance(-1),
+            key_press!(KeyO) => self.world.toggle_orthographic(),
+            key_press!(KeyF) => self.world.toggle_movement_mode(),
+            key_press!(F2) => self.take_screenshot(),
+            key_press!(F3) => self.gfx.toggle_debug_overlay(),
+            KeyEvent {
+                physical_key: PhysicalKey::Code(code),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            } if Self::BLOCK_KEYS.contains(&code) => {
+                let index = Self::BLOCK_KEYS.iter().position(|key| *key == code).unwrap();
+                self.world.select_block_index(index);
+            }
             KeyEvent {
                 physical_key: PhysicalKey::Code(code),
                 state,
@@ -162,6 +251,13 @@ impl InitializedApp {
         }
     }
 
+    pub fn on_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        // Clicks that aren't aimed at the world, like focusing the window, don't count
+        if self.mouse_grabbed || state == ElementState::Released {
+            self.input.on_mouse_button(button, state);
+        }
+    }
+
     pub fn on_mouse_move(&mut self, delta: (f64, f64)) {
         if self.mouse_grabbed {
             self.input.on_mouse_move(delta);
@@ -177,6 +273,26 @@ impl InitializedApp {
         self.gfx.update_projection_matrix_buffer(&self.world.camera);
     }
 
+    /// Save a frame as a PNG, at a multiple of the window size while Shift
+    /// is held.
+    fn take_screenshot(&mut self) {
+        let high_res = self.input.is_key_pressed(KeyCode::ShiftLeft)
+            || self.input.is_key_pressed(KeyCode::ShiftRight);
+        let frame = match high_res {
+            true => self
+                .gfx
+                .capture_frame_scaled(&mut self.world, screenshot::HIGH_RES_SCALE),
+            false => self.gfx.capture_frame(&mut self.world),
+        };
+
+        let dir = std::env::var("SHALLOW_STONE_SCREENSHOTS")
+            .unwrap_or_else(|_| screenshot::DEFAULT_DIR.to_owned());
+        match frame.and_then(|frame| screenshot::save(&frame, Path::new(&dir))) {
+            Ok(path) => tracing::info!("Saved screenshot to {}", path.display()),
+            Err(err) => tracing::error!("Could not take a screenshot: {err:#}"),
+        }
+    }
+
     fn grab_mouse(&mut self) {
         let grab = !self.mouse_grabbed;
 
@@ -185,14 +301,19 @@ impl InitializedApp {
             false => winit::window::CursorGrabMode::None,
         };
 
-        if self.gfx.window.set_cursor_grab(next).is_ok() {
-            self.gfx.window.set_cursor_visible(!grab);
+        let Some(window) = self.gfx.window() else {
+            return;
+        };
+        if window.set_cursor_grab(next).is_ok() {
+            window.set_cursor_visible(!grab);
             self.mouse_grabbed = grab;
         }
     }
 }
 
 fn main() {
+    let _log = logging::init();
+
     let event_loop = EventLoop::new().unwrap();
 
     event_loop.set_control_flow(ControlFlow::Poll);
@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
 use winit::{
     dpi::PhysicalSize,
@@ -8,26 +8,71 @@ use winit::{
 
 use wgpu::{include_wgsl, util::DeviceExt};
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use crate::{
+    render::frame::{self, Frame, FrameReadback},
+    render::overlay::{DebugInfo, DebugOverlay},
     render::texture::Texture,
+    world::chunks::culling::GpuCulling,
     world::{camera::Camera, World},
 };
 
+/// What frames are drawn into.
+enum RenderTarget {
+    Window {
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+    },
+    /// Headless rendering, frames stay in the texture until read back with
+    /// [`GfxContext::read_frame`].
+    Offscreen(wgpu::Texture),
+}
+
+/// The texture a frame is drawn into.
+enum FrameTexture<'a> {
+    /// Has to be presented once drawn.
+    Surface(wgpu::SurfaceTexture),
+    Offscreen(&'a wgpu::Texture),
+}
+
+impl FrameTexture<'_> {
+    fn texture(&self) -> &wgpu::Texture {
+        match self {
+            FrameTexture::Surface(output) => &output.texture,
+            FrameTexture::Offscreen(texture) => texture,
+        }
+    }
+
+    fn present(self) {
+        if let FrameTexture::Surface(output) = self {
+            output.present();
+        }
+    }
+}
+
 pub struct GfxContext {
-    pub window: Arc<Window>,
-    pub surface: wgpu::Surface<'static>,
+    /// Kept to create a new device if the current one is lost.
+    instance: wgpu::Instance,
+    target: RenderTarget,
 
+    /// Format and size of the frames. Headless contexts keep one too, so
+    /// pipelines and the depth texture are created the same way for both.
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
 
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    pub adapter_info: wgpu::AdapterInfo,
+    /// Set by the device lost callback, checked before every frame.
+    device_lost: Arc<AtomicBool>,
 
     pub global_shader_bindings: GlobalShaderBindings,
 
     depth_texture: super::texture::Texture,
+    overlay: DebugOverlay,
 }
 
 impl GfxContext {
@@ -41,24 +86,18 @@ impl GfxContext {
             .create_surface(window.clone())
             .context("Create WGPU surface")?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                compatible_surface: Some(&surface),
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                ..Default::default()
-            })
+        let adapter = Self::request_adapter(&instance, Some(&surface))
             .await
-            .expect("Could not find compatible adapter");
-
-        dbg!(&adapter.get_info());
+            .context("No adapter compatible with the window")?;
 
-        let (device, queue) = Self::create_device(&adapter).await?;
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let (device, queue) = Self::create_device(&adapter, &device_lost).await?;
 
-        dbg!(device.limits());
+        tracing::debug!(limits = ?device.limits(), "Created device");
 
         let surface_caps = surface.get_capabilities(&adapter);
 
-        dbg!(&surface_caps);
+        tracing::debug!(capabilities = ?surface_caps, "Surface");
 
         let surface_format = surface_caps
             .formats
@@ -68,7 +107,9 @@ impl GfxContext {
             .unwrap_or(surface_caps.formats[0]);
 
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // Copied from for screenshots, where supported
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -78,29 +119,124 @@ impl GfxContext {
             desired_maximum_frame_latency: 2,
         };
 
-        dbg!(&config);
+        tracing::debug!(?config, "Configuring surface");
 
         surface.configure(&device, &config);
 
+        Ok(Self::from_parts(
+            instance,
+            RenderTarget::Window { window, surface },
+            config,
+            device,
+            queue,
+            adapter.get_info(),
+            device_lost,
+        ))
+    }
+
+    /// Format of the frames of headless contexts.
+    pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Create a context rendering into an offscreen texture instead of a
+    /// window, for tests and tools. Software adapters like llvmpipe and
+    /// lavapipe work; if no other adapter is found the fallback adapter is
+    /// used.
+    pub async fn create_headless(width: u32, height: u32) -> Result<GfxContext> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::all()),
+            ..Default::default()
+        });
+
+        let adapter = Self::request_adapter(&instance, None)
+            .await
+            .context("No adapter for headless rendering")?;
+
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let (device, queue) = Self::create_device(&adapter, &device_lost).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: Self::OFFSCREEN_FORMAT,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let texture = Self::create_offscreen_texture(&device, &config);
+
+        Ok(Self::from_parts(
+            instance,
+            RenderTarget::Offscreen(texture),
+            config,
+            device,
+            queue,
+            adapter.get_info(),
+            device_lost,
+        ))
+    }
+
+    fn from_parts(
+        instance: wgpu::Instance,
+        target: RenderTarget,
+        config: wgpu::SurfaceConfiguration,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        adapter_info: wgpu::AdapterInfo,
+        device_lost: Arc<AtomicBool>,
+    ) -> Self {
         let global_shader_bindings = GlobalShaderBindings::init(&device);
         let depth_texture = Texture::create_depth_texture(&device, &config, "depth texture");
+        let overlay = DebugOverlay::new(&device, &queue, config.format);
 
-        Ok(Self {
-            window,
-            surface,
+        Self {
+            instance,
+            target,
 
+            size: PhysicalSize::new(config.width, config.height),
             config,
-            size,
 
             device,
             queue,
+            adapter_info,
+            device_lost,
 
             global_shader_bindings,
 
             depth_texture,
+            overlay,
+        }
+    }
+
+    fn create_offscreen_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen frame"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            view_formats: &[],
         })
     }
 
+    /// The window drawn into, `None` for headless contexts.
+    pub fn window(&self) -> Option<&Window> {
+        match &self.target {
+            RenderTarget::Window { window, .. } => Some(window),
+            RenderTarget::Offscreen(_) => None,
+        }
+    }
+
     fn create_window(event_loop: &ActiveEventLoop) -> Result<Window> {
         let window_attributes =
             WindowAttributes::default().with_inner_size(PhysicalSize::new(1280, 720));
@@ -118,17 +254,69 @@ impl GfxContext {
         })
     }
 
-    async fn create_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+    /// Any adapter able to draw to `surface`, or to an offscreen texture
+    /// without one. Falls back to the software fallback adapter, if there
+    /// is one.
+    async fn request_adapter(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface<'_>>,
+    ) -> Option<wgpu::Adapter> {
+        for force_fallback_adapter in [false, true] {
+            let adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    compatible_surface: surface,
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    force_fallback_adapter,
+                })
+                .await;
+            if let Some(adapter) = adapter {
+                let info = adapter.get_info();
+                tracing::info!(name = info.name, backend = ?info.backend, "Using adapter");
+                return Some(adapter);
+            }
+        }
+        None
+    }
+
+    /// Create the device, setting `lost` once it is lost.
+    async fn create_device(
+        adapter: &wgpu::Adapter,
+        lost: &Arc<AtomicBool>,
+    ) -> Result<(wgpu::Device, wgpu::Queue)> {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: Some("device"),
+                    // Used for GPU-driven chunk drawing when available
+                    required_features: adapter.features() & GpuCulling::FEATURES,
                     ..Default::default()
                 },
                 None,
             )
             .await
             .context("Create WGPU device")?;
+
+        let flag = lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device, or a replaced callback, isn't a loss
+            if matches!(
+                reason,
+                wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::Destroyed
+            ) {
+                tracing::error!(?reason, "GPU device lost: {message}");
+                flag.store(true, Ordering::Release);
+            }
+        });
+
+        // Work submitted between losing the device and noticing it fails
+        // validation. Those errors are expected, anything else still panics.
+        let flag = lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if !flag.load(Ordering::Acquire) {
+                panic!("wgpu error: {error}");
+            }
+        }));
+
         Ok((device, queue))
     }
 
@@ -137,23 +325,168 @@ impl GfxContext {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Window { surface, .. } => {
+                    surface.configure(&self.device, &self.config)
+                }
+                RenderTarget::Offscreen(texture) => {
+                    *texture = Self::create_offscreen_texture(&self.device, &self.config)
+                }
+            }
             self.depth_texture =
                 Texture::create_depth_texture(&self.device, &self.config, "depth texture");
         }
     }
 
-    pub fn render(&self, world: &mut World) -> Result<()> {
+    /// Draw and present a frame. Lost and outdated surfaces are
+    /// reconfigured and a lost device is replaced, recreating the world's
+    /// GPU resources; frames that can't be drawn right away are skipped.
+    pub fn render(&mut self, world: &mut World) -> Result<()> {
+        if self.is_device_lost() {
+            self.recover_device(world)?;
+        }
+
+        self.overlay.record_frame(Instant::now());
+        if self.overlay.is_visible() {
+            let info = DebugInfo::new(world, &self.adapter_info);
+            let size = glam::Vec2::new(self.config.width as f32, self.config.height as f32);
+            self.overlay.prepare(&self.device, &self.queue, &info, size);
+        }
+
+        let Some(frame) = self.next_frame_texture()? else {
+            return Ok(());
+        };
+        self.draw(world, frame.texture(), &self.depth_texture, false)?;
+        frame.present();
+        Ok(())
+    }
+
+    /// Whether the device was lost. The next [`GfxContext::render`]
+    /// replaces it.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
+    }
+
+    /// Replace the lost device with a new one and create every GPU resource
+    /// again, the world's from the blocks it keeps on the CPU.
+    fn recover_device(&mut self, world: &mut World) -> Result<()> {
+        let _span = tracing::info_span!("recover_device").entered();
+        let surface = match &self.target {
+            RenderTarget::Window { surface, .. } => Some(surface),
+            RenderTarget::Offscreen(_) => None,
+        };
+        let adapter = pollster::block_on(Self::request_adapter(&self.instance, surface))
+            .context("No adapter to replace the lost device")?;
+
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let (device, queue) = pollster::block_on(Self::create_device(&adapter, &device_lost))?;
+        self.device = device;
+        self.queue = queue;
+        self.adapter_info = adapter.get_info();
+        self.device_lost = device_lost;
+
+        match &mut self.target {
+            RenderTarget::Window { surface, .. } => {
+                surface.configure(&self.device, &self.config)
+            }
+            RenderTarget::Offscreen(texture) => {
+                *texture = Self::create_offscreen_texture(&self.device, &self.config)
+            }
+        }
+        self.global_shader_bindings = GlobalShaderBindings::init(&self.device);
+        self.depth_texture =
+            Texture::create_depth_texture(&self.device, &self.config, "depth texture");
+        self.overlay
+            .recreate_gpu_resources(&self.device, &self.queue, self.config.format);
+
+        world.recreate_gpu_resources(self);
+        Ok(())
+    }
+
+    /// Show or hide the debug overlay, from the next frame on.
+    pub fn toggle_debug_overlay(&mut self) {
+        self.overlay.toggle();
+    }
+
+    /// Draw a frame and copy it into memory as it is shown in the window,
+    /// or for headless contexts, in the offscreen texture.
+    pub fn capture_frame(&self, world: &mut World) -> Result<Frame> {
+        if !self.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            // The surface can't be copied from, draw the same frame offscreen
+            return self.capture_frame_scaled(world, 1);
+        }
+
+        let Some(frame) = self.next_frame_texture()? else {
+            return self.capture_frame_scaled(world, 1);
+        };
+        let captured = self.draw(world, frame.texture(), &self.depth_texture, true)?;
+        frame.present();
+        captured.context("Frame was not captured")
+    }
+
+    /// Draw a frame `scale` times the size of the window into an offscreen
+    /// texture and copy it into memory, for high resolution screenshots. The
+    /// scale is lowered if the frame would be larger than the adapter allows.
+    pub fn capture_frame_scaled(&self, world: &mut World, scale: u32) -> Result<Frame> {
+        let largest = self.config.width.max(self.config.height);
+        let scale = scale
+            .min(self.device.limits().max_texture_dimension_2d / largest)
+            .max(1);
+
+        let mut config = self.config.clone();
+        config.width *= scale;
+        config.height *= scale;
+        config.usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC;
+
+        let texture = Self::create_offscreen_texture(&self.device, &config);
+        let depth_texture =
+            Texture::create_depth_texture(&self.device, &config, "screenshot depth texture");
+        let captured = self.draw(world, &texture, &depth_texture, true)?;
+        captured.context("Frame was not captured")
+    }
+
+    /// Copy the last rendered frame of a headless context into memory.
+    pub fn read_frame(&self) -> Result<Frame> {
+        match &self.target {
+            RenderTarget::Offscreen(texture) => {
+                frame::read_texture(&self.device, &self.queue, texture)
+            }
+            RenderTarget::Window { .. } => bail!("Only headless contexts can read frames back"),
+        }
+    }
+
+    /// The texture to draw the next frame into, `None` if the frame should
+    /// be skipped.
+    fn next_frame_texture(&self) -> Result<Option<FrameTexture<'_>>> {
+        match &self.target {
+            RenderTarget::Window { surface, .. } => {
+                let texture = acquire_frame(
+                    || surface.get_current_texture(),
+                    || surface.configure(&self.device, &self.config),
+                )?;
+                Ok(texture.map(FrameTexture::Surface))
+            }
+            RenderTarget::Offscreen(texture) => Ok(Some(FrameTexture::Offscreen(texture))),
+        }
+    }
+
+    /// Draw the world into `texture`, copying the frame back into memory if
+    /// `capture` is set.
+    fn draw(
+        &self,
+        world: &mut World,
+        texture: &wgpu::Texture,
+        depth_texture: &Texture,
+        capture: bool,
+    ) -> Result<Option<Frame>> {
         world
             .camera
             .write_view_matrix_buffer(&self.queue, &self.global_shader_bindings.view_matrix_buffer);
+        self.update_projection_matrix_buffer(&world.camera);
 
-        world.prepare_render(self);
+        tracing::info_span!("prepare_render").in_scope(|| world.prepare_render(self));
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder = self
             .device
@@ -161,17 +494,29 @@ impl GfxContext {
                 label: Some("render encoder"),
             });
 
-        self.render_pass(&view, &mut encoder, world);
+        let render_span = tracing::info_span!("render_pass").entered();
+        world.encode_compute(&mut encoder);
+        self.render_pass(&view, &depth_texture.view, &mut encoder, world);
+        if self.overlay.is_visible() {
+            self.overlay_pass(&view, &mut encoder);
+        }
+
+        let readback = capture
+            .then(|| FrameReadback::copy_from(&self.device, &mut encoder, texture))
+            .transpose()?;
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        world.frame_submitted();
+        render_span.exit();
 
-        Ok(())
+        let _span = capture.then(|| tracing::info_span!("read_frame").entered());
+        readback.map(|readback| readback.read(&self.device)).transpose()
     }
 
     fn render_pass(
         &self,
         view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
         world: &World,
     ) {
@@ -191,9 +536,9 @@ impl GfxContext {
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
+                view: depth_view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    load: wgpu::LoadOp::Clear(Texture::DEPTH_CLEAR),
                     store: wgpu::StoreOp::Store,
                 }),
                 stencil_ops: None,
@@ -207,6 +552,26 @@ impl GfxContext {
         world.render(&mut pass);
     }
 
+    /// Draw the debug overlay on top of the frame.
+    fn overlay_pass(&self, view: &wgpu::TextureView, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("debug overlay pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        self.overlay.render(&mut pass);
+    }
+
     pub fn update_projection_matrix_buffer(&self, camera: &Camera) {
         let aspect = self.window_aspect_ratio();
         camera.write_projection_matrix_buffer(
@@ -216,11 +581,40 @@ impl GfxContext {
         );
     }
 
-    fn window_aspect_ratio(&self) -> f32 {
+    pub fn window_aspect_ratio(&self) -> f32 {
         self.config.width as f32 / self.config.height as f32
     }
 }
 
+/// Get the next frame from a surface with `acquire`. A lost or outdated
+/// surface is reconfigured with `reconfigure` and tried once more; if that
+/// fails too, or acquiring the frame times out, the frame is skipped and
+/// `None` returned. Running out of memory is an error.
+fn acquire_frame<T>(
+    mut acquire: impl FnMut() -> Result<T, wgpu::SurfaceError>,
+    mut reconfigure: impl FnMut(),
+) -> Result<Option<T>> {
+    let mut reconfigured = false;
+    loop {
+        match acquire() {
+            Ok(frame) => return Ok(Some(frame)),
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) if !reconfigured => {
+                reconfigure();
+                reconfigured = true;
+            }
+            // Can stay outdated for a while, for example during a resize
+            Err(err @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                tracing::warn!("Skipping a frame: {err}");
+                return Ok(None);
+            }
+            Err(wgpu::SurfaceError::Timeout) => return Ok(None),
+            Err(err @ wgpu::SurfaceError::OutOfMemory) => {
+                return Err(err).context("Get the next frame")
+            }
+        }
+    }
+}
+
 pub struct GlobalShaderBindings {
     pub layout: wgpu::BindGroupLayout,
     pub group: wgpu::BindGroup,
@@ -300,3 +694,63 @@ impl GlobalShaderBindings {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    /// Acquire results from `errors` in order, then frames.
+    fn acquire_with(errors: &[wgpu::SurfaceError]) -> (Result<Option<u32>>, usize, usize) {
+        let attempts = Cell::new(0);
+        let reconfigured = Cell::new(0);
+        let result = acquire_frame(
+            || {
+                let attempt = attempts.get();
+                attempts.set(attempt + 1);
+                errors.get(attempt).cloned().map_or(Ok(7), Err)
+            },
+            || reconfigured.set(reconfigured.get() + 1),
+        );
+        (result, attempts.get(), reconfigured.get())
+    }
+
+    #[test]
+    fn frames_are_acquired_directly() {
+        let (result, attempts, reconfigured) = acquire_with(&[]);
+        assert_eq!(result.unwrap(), Some(7));
+        assert_eq!((attempts, reconfigured), (1, 0));
+    }
+
+    #[test]
+    fn lost_and_outdated_surfaces_are_reconfigured() {
+        for error in [wgpu::SurfaceError::Lost, wgpu::SurfaceError::Outdated] {
+            let (result, attempts, reconfigured) = acquire_with(&[error]);
+            assert_eq!(result.unwrap(), Some(7));
+            assert_eq!((attempts, reconfigured), (2, 1));
+        }
+    }
+
+    #[test]
+    fn frames_are_skipped_if_reconfiguring_does_not_help() {
+        let errors = [wgpu::SurfaceError::Outdated, wgpu::SurfaceError::Lost];
+        let (result, attempts, reconfigured) = acquire_with(&errors);
+        assert_eq!(result.unwrap(), None);
+        assert_eq!((attempts, reconfigured), (2, 1));
+    }
+
+    #[test]
+    fn timeouts_skip_the_frame() {
+        let (result, attempts, reconfigured) = acquire_with(&[wgpu::SurfaceError::Timeout]);
+        assert_eq!(result.unwrap(), None);
+        assert_eq!((attempts, reconfigured), (1, 0));
+    }
+
+    #[test]
+    fn running_out_of_memory_is_an_error() {
+        let (result, _, reconfigured) = acquire_with(&[wgpu::SurfaceError::OutOfMemory]);
+        assert!(result.is_err());
+        assert_eq!(reconfigured, 0);
+    }
+}
@@ -0,0 +1,307 @@
+use anyhow::{bail, Context, Result};
+
+use std::path::Path;
+
+/// A rendered frame read back from the GPU, as tightly packed RGBA8 rows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32, rgba: Vec<u8>) -> Self {
+        debug_assert_eq!(rgba.len(), (width * height * 4) as usize);
+        Self {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        self.rgba[i..i + 4].try_into().unwrap()
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        let file =
+            std::fs::File::create(path).with_context(|| format!("Create {}", path.display()))?;
+
+        let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().context("Write PNG header")?;
+        writer.write_image_data(&self.rgba).context("Encode PNG")?;
+        writer.finish().context("Finish PNG")
+    }
+
+    /// Load a PNG written by [`Frame::save_png`].
+    pub fn load_png(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path).with_context(|| format!("Open {}", path.display()))?;
+
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().context("Read PNG header")?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).context("Decode PNG")?;
+        if info.color_type != png::ColorType::Rgba {
+            bail!("{} is {:?}, expected RGBA", path.display(), info.color_type);
+        }
+
+        buffer.truncate(info.buffer_size());
+        Ok(Self::new(info.width, info.height, buffer))
+    }
+
+    /// Compare with `expected` pixel by pixel. Pixels whose channels all
+    /// differ by at most `tolerance` count as equal, so small rasterization
+    /// and filtering differences between adapters don't fail a comparison.
+    pub fn compare(&self, expected: &Frame, tolerance: u8) -> FrameDiff {
+        if (self.width, self.height) != (expected.width, expected.height) {
+            return FrameDiff {
+                differing_pixels: self.pixel_count().max(expected.pixel_count()),
+                total_pixels: self.pixel_count().max(expected.pixel_count()),
+                max_difference: u8::MAX,
+            };
+        }
+
+        let mut diff = FrameDiff {
+            differing_pixels: 0,
+            total_pixels: self.pixel_count(),
+            max_difference: 0,
+        };
+        for (a, b) in self.rgba.chunks_exact(4).zip(expected.rgba.chunks_exact(4)) {
+            let difference = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+            diff.max_difference = diff.max_difference.max(difference);
+            if difference > tolerance {
+                diff.differing_pixels += 1;
+            }
+        }
+        diff
+    }
+
+    /// Image showing where the frame differs from `expected`: differing
+    /// pixels in red, the rest as a faded copy of `expected`.
+    pub fn diff_image(&self, expected: &Frame, tolerance: u8) -> Frame {
+        let rgba = self
+            .rgba
+            .chunks_exact(4)
+            .zip(expected.rgba.chunks_exact(4))
+            .flat_map(|(a, b)| {
+                let difference = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap();
+                if difference > tolerance {
+                    [255, 0, 0, 255]
+                } else {
+                    let gray = ((b[0] as u32 + b[1] as u32 + b[2] as u32) / 12) as u8;
+                    [gray, gray, gray, 255]
+                }
+            })
+            .collect();
+        Frame::new(expected.width, expected.height, rgba)
+    }
+
+    fn pixel_count(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+}
+
+/// How much two frames differ, see [`Frame::compare`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDiff {
+    pub differing_pixels: usize,
+    pub total_pixels: usize,
+    /// Largest difference of any channel of any pixel.
+    pub max_difference: u8,
+}
+
+impl FrameDiff {
+    /// Fraction of the pixels that differ, 0 to 1.
+    pub fn differing_fraction(&self) -> f64 {
+        self.differing_pixels as f64 / self.total_pixels.max(1) as f64
+    }
+}
+
+/// Bytes per row of a texture copy into a buffer, which wgpu requires to be
+/// a multiple of [`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`].
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let unpadded = width * 4;
+    unpadded.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+/// Drop the padding at the end of every row of a texture copy.
+pub fn unpad_rows(padded: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let row = (width * 4) as usize;
+    let padded_row = padded_bytes_per_row(width) as usize;
+    padded
+        .chunks(padded_row)
+        .take(height as usize)
+        .flat_map(|padded| &padded[..row])
+        .copied()
+        .collect()
+}
+
+/// A copy of a texture on its way to CPU memory. Recording the copy and
+/// reading it are separate so surface textures can be copied in the same
+/// submission that draws them, before they are presented.
+pub struct FrameReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl FrameReadback {
+    /// Record a copy of `texture`, which needs `COPY_SRC` usage and four
+    /// bytes per pixel.
+    pub fn copy_from(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Result<Self> {
+        let (width, height, format) = (texture.width(), texture.height(), texture.format());
+        if format.block_copy_size(None) != Some(4) {
+            bail!("Can't read back {format:?} textures");
+        }
+
+        let bytes_per_row = padded_bytes_per_row(width);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame readback buffer"),
+            size: bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            format,
+        })
+    }
+
+    /// Wait for the copy, which must have been submitted, and convert it to
+    /// RGBA.
+    pub fn read(self, device: &wgpu::Device) -> Result<Frame> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = self.buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .context("Readback buffer was dropped")?
+            .context("Map readback buffer")?;
+
+        let mut rgba = unpad_rows(&slice.get_mapped_range(), self.width, self.height);
+        self.buffer.unmap();
+
+        if matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            bgra_to_rgba(&mut rgba);
+        }
+        Ok(Frame::new(self.width, self.height, rgba))
+    }
+}
+
+/// Copy a texture into CPU memory, blocking until the GPU is done.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<Frame> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("frame readback encoder"),
+    });
+    let readback = FrameReadback::copy_from(device, &mut encoder, texture)?;
+    queue.submit(std::iter::once(encoder.finish()));
+    readback.read(device)
+}
+
+/// Swap the red and blue channels of every pixel.
+fn bgra_to_rgba(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::world::save::tests::TempDir;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> Frame {
+        Frame::new(width, height, color.repeat((width * height) as usize))
+    }
+
+    #[test]
+    fn rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+        assert_eq!(padded_bytes_per_row(1), 256);
+
+        // Three pixels wide, two rows: 12 bytes of pixels then padding
+        let mut padded = vec![0; 2 * 256];
+        padded[..12].fill(1);
+        padded[256..268].fill(2);
+        let rgba = unpad_rows(&padded, 3, 2);
+        assert_eq!(rgba.len(), 24);
+        assert_eq!(&rgba[..12], &[1; 12]);
+        assert_eq!(&rgba[12..], &[2; 12]);
+    }
+
+    #[test]
+    fn bgra_is_swizzled() {
+        let mut pixels = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        bgra_to_rgba(&mut pixels);
+        assert_eq!(pixels, vec![3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn comparison_tolerates_small_differences() {
+        let expected = solid(4, 4, [100, 100, 100, 255]);
+        let mut frame = solid(4, 4, [103, 98, 100, 255]);
+        assert_eq!(frame.compare(&expected, 3).differing_pixels, 0);
+        assert_eq!(frame.compare(&expected, 3).max_difference, 3);
+
+        frame.rgba[0] = 200;
+        let diff = frame.compare(&expected, 3);
+        assert_eq!(diff.differing_pixels, 1);
+        assert_eq!(diff.differing_fraction(), 1.0 / 16.0);
+        assert_eq!(frame.diff_image(&expected, 3).pixel(0, 0), [255, 0, 0, 255]);
+        assert_ne!(frame.diff_image(&expected, 3).pixel(1, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn frames_of_different_sizes_differ_everywhere() {
+        let diff = solid(4, 4, [0; 4]).compare(&solid(4, 2, [0; 4]), 255);
+        assert_eq!(diff.differing_fraction(), 1.0);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let dir = TempDir::new("frame_png_round_trip");
+        let path = dir.path().join("frame.png");
+        let frame = Frame::new(3, 2, (0..24).collect());
+        frame.save_png(&path).unwrap();
+        assert_eq!(Frame::load_png(&path).unwrap(), frame);
+    }
+}
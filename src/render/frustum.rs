@@ -0,0 +1,137 @@
+use glam::{Mat4, Vec3, Vec4};
+
+/// A plane `normal · p + distance = 0`. Points with a positive signed
+/// distance are in front of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Plane from the `(a, b, c, d)` coefficients of `ax + by + cz + d = 0`,
+    /// normalized so signed distances are in world units.
+    ///
+    /// Without a normal, as for the far plane of an infinite projection, the
+    /// plane has every point in front of it or none.
+    fn from_coefficients(coefficients: Vec4) -> Self {
+        let normal = coefficients.truncate();
+        let length = normal.length();
+        if length < 1e-6 {
+            return Self {
+                normal: Vec3::ZERO,
+                distance: if coefficients.w >= 0.0 { f32::MAX } else { f32::MIN },
+            };
+        }
+
+        Self {
+            normal: normal / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// The six planes bounding what a camera sees, all facing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of a `projection * view` matrix, with clip space
+    /// depth from 0 to 1 as wgpu uses. For reversed depth the near and far
+    /// planes swap places.
+    pub fn from_matrix(view_projection: Mat4) -> Self {
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+
+        // A point is inside when -w <= x <= w, -w <= y <= w and 0 <= z <= w
+        Self {
+            planes: [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+                .map(Plane::from_coefficients),
+        }
+    }
+
+    /// Whether any part of the box may be visible. Boxes close to a corner of
+    /// the frustum can be reported visible when they're not.
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal is the last one to leave
+            let corner = Vec3::select(plane.normal.cmpge(Vec3::ZERO), max, min);
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Frustum {
+        let view = Mat4::look_to_lh(Vec3::ZERO, Vec3::Z, Vec3::Y);
+        let projection = Mat4::perspective_lh(90.0f32.to_radians(), 1.0, 0.1, 100.0);
+        Frustum::from_matrix(projection * view)
+    }
+
+    #[test]
+    fn planes_of_a_perspective_camera() {
+        let frustum = camera();
+        let [left, right, bottom, top, near, far] = frustum.planes;
+
+        // 90 degrees field of view: the side planes are at 45 degrees
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(left.normal.abs_diff_eq(Vec3::new(diagonal, 0.0, diagonal), 1e-5));
+        assert!(right.normal.abs_diff_eq(Vec3::new(-diagonal, 0.0, diagonal), 1e-5));
+        assert!(bottom.normal.abs_diff_eq(Vec3::new(0.0, diagonal, diagonal), 1e-5));
+        assert!(top.normal.abs_diff_eq(Vec3::new(0.0, -diagonal, diagonal), 1e-5));
+
+        assert!(near.normal.abs_diff_eq(Vec3::Z, 1e-5));
+        assert!((near.distance + 0.1).abs() < 1e-4);
+        assert!(far.normal.abs_diff_eq(Vec3::NEG_Z, 1e-5));
+        assert!((far.distance - 100.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn planes_follow_the_view() {
+        let position = Vec3::new(10.0, 5.0, -3.0);
+        let view = Mat4::look_to_lh(position, Vec3::X, Vec3::Y);
+        let projection = Mat4::perspective_lh(60.0f32.to_radians(), 16.0 / 9.0, 0.1, 50.0);
+        let frustum = Frustum::from_matrix(projection * view);
+
+        let ahead = position + Vec3::X * 20.0;
+        assert!(frustum.planes.iter().all(|p| p.signed_distance(ahead) > 0.0));
+        let behind = position - Vec3::X * 20.0;
+        assert!(frustum.planes[4].signed_distance(behind) < 0.0);
+    }
+
+    #[test]
+    fn infinite_reverse_projection_has_no_far_plane() {
+        let view = Mat4::look_to_lh(Vec3::ZERO, Vec3::Z, Vec3::Y);
+        let projection = Mat4::perspective_infinite_reverse_lh(90.0f32.to_radians(), 1.0, 0.1);
+        let frustum = Frustum::from_matrix(projection * view);
+
+        assert!(frustum.planes.iter().all(|p| p.normal.is_finite() && !p.distance.is_nan()));
+        assert!(frustum.intersects_aabb(Vec3::new(0.0, 0.0, 1e6), Vec3::new(1.0, 1.0, 1e6 + 1.0)));
+        assert!(!frustum.intersects_aabb(Vec3::new(0.0, 0.0, -2.0), Vec3::new(1.0, 1.0, -1.0)));
+    }
+
+    #[test]
+    fn aabb_intersection() {
+        let frustum = camera();
+        let unit = Vec3::ONE;
+
+        // In front, behind, past the far plane and to the side
+        assert!(frustum.intersects_aabb(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, 10.0) + unit));
+        assert!(!frustum.intersects_aabb(Vec3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, -10.0) + unit));
+        assert!(!frustum.intersects_aabb(Vec3::new(0.0, 0.0, 150.0), Vec3::new(0.0, 0.0, 150.0) + unit));
+        assert!(!frustum.intersects_aabb(Vec3::new(20.0, 0.0, 5.0), Vec3::new(20.0, 0.0, 5.0) + unit));
+
+        // Straddling a side plane, and containing the whole camera
+        assert!(frustum.intersects_aabb(Vec3::new(9.0, 0.0, 10.0), Vec3::new(11.0, 1.0, 11.0)));
+        assert!(frustum.intersects_aabb(Vec3::splat(-32.0), Vec3::splat(32.0)));
+    }
+}
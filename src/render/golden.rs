@@ -0,0 +1,213 @@
+//! Golden-image tests: fixed scenes rendered headless and compared with the
+//! reference images in `tests/golden`.
+//!
+//! They need an adapter, so they only run with the `gpu-tests` feature,
+//! `cargo test --features gpu-tests`, and fail if there is none. Set
+//! `SHALLOW_STONE_UPDATE_GOLDEN=1` to write the rendered frames as the new
+//! reference images, after checking that the changes are intended. Frames
+//! that don't match are written to `target/golden` with an image marking
+//! the differences.
+
+use super::frame::Frame;
+use super::GfxContext;
+
+use crate::world::registry::BlockRegistry;
+use crate::world::save::{tests::TempDir, WorldSave};
+use crate::world::World;
+
+use glam::{IVec3, Vec3};
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 180;
+
+/// Largest difference of a channel still counted as the same pixel.
+const TOLERANCE: u8 = 8;
+/// Fraction of the pixels allowed to differ, for edges rasterized slightly
+/// differently by other adapters.
+const MAX_DIFFERING: f64 = 0.005;
+
+/// How long to wait for the chunks in range to load.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A headless context and a new world with a small render distance.
+/// Panics if there is no adapter to render with.
+fn scene(name: &str) -> (GfxContext, World, TempDir) {
+    // Shown with the output of failing tests
+    let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+
+    let gfx = pollster::block_on(GfxContext::create_headless(WIDTH, HEIGHT))
+        .unwrap_or_else(|err| panic!("No adapter for {name}: {err:#}"));
+
+    let dir = TempDir::new(&format!("golden_{name}"));
+    let save = WorldSave::open(dir.path()).unwrap();
+    let registry = BlockRegistry::load_default().unwrap();
+    let mut world = World::new(&gfx, World::DEFAULT_SEED, registry, save).unwrap();
+    world.change_render_distance(-32);
+
+    (gfx, world, dir)
+}
+
+/// Render frames until every chunk in range is loaded, then read the last
+/// one back.
+fn render_loaded(gfx: &mut GfxContext, world: &mut World) -> Frame {
+    let start = Instant::now();
+    loop {
+        gfx.render(world).unwrap();
+        if world.pending_chunk_count() == 0 {
+            break;
+        }
+        assert!(start.elapsed() < LOAD_TIMEOUT, "chunks took too long to load");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    // Edits are meshed while preparing the next frame
+    gfx.render(world).unwrap();
+    gfx.read_frame().unwrap()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn assert_matches_golden(name: &str, frame: &Frame) {
+    let path = golden_dir().join(format!("{name}.png"));
+
+    if std::env::var_os("SHALLOW_STONE_UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        frame.save_png(&path).unwrap();
+        tracing::info!("Wrote {}", path.display());
+        return;
+    }
+
+    let expected = Frame::load_png(&path).unwrap_or_else(|err| {
+        panic!("{err:#}, run with SHALLOW_STONE_UPDATE_GOLDEN=1 to create it")
+    });
+    let diff = frame.compare(&expected, TOLERANCE);
+    if diff.differing_fraction() > MAX_DIFFERING {
+        let out = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden");
+        std::fs::create_dir_all(&out).unwrap();
+        frame.save_png(&out.join(format!("{name}.png"))).unwrap();
+        if (frame.width, frame.height) == (expected.width, expected.height) {
+            let diff_image = frame.diff_image(&expected, TOLERANCE);
+            diff_image.save_png(&out.join(format!("{name}.diff.png"))).unwrap();
+        }
+        panic!("{name} differs from {}: {diff:?}, see {}", path.display(), out.display());
+    }
+}
+
+#[test]
+#[cfg_attr(not(feature = "gpu-tests"), ignore = "needs an adapter, enable gpu-tests")]
+fn terrain_overview() {
+    let (mut gfx, mut world, _dir) = scene("terrain_overview");
+    // From the spawn point, above the terrain
+    world.camera.set_orientation(0.4, 0.7);
+
+    let frame = render_loaded(&mut gfx, &mut world);
+    assert_matches_golden("terrain_overview", &frame);
+}
+
+/// Opaque, cutout, translucent and glowing blocks high above the terrain,
+/// against the sky.
+#[test]
+#[cfg_attr(not(feature = "gpu-tests"), ignore = "needs an adapter, enable gpu-tests")]
+fn block_layers() {
+    let (mut gfx, mut world, _dir) = scene("block_layers");
+    let floor = 240;
+    world.camera.set_position(Vec3::new(0.5, floor as f32 + 4.0, -6.0));
+    world.camera.set_orientation(0.35, 0.0);
+    render_loaded(&mut gfx, &mut world);
+
+    let id = |name| world.registry().id(name).unwrap();
+    let (stone, leaves, glass, water, lamp) =
+        (id("stone"), id("leaves"), id("glass"), id("water"), id("lamp"));
+    let mut blocks = vec![];
+    for x in -4..=4 {
+        for z in 0..=6 {
+            blocks.push((IVec3::new(x, floor, z), stone));
+        }
+    }
+    blocks.extend([
+        (IVec3::new(-2, floor + 1, 4), leaves),
+        (IVec3::new(-2, floor + 2, 4), leaves),
+        (IVec3::new(2, floor + 1, 4), lamp),
+        (IVec3::new(0, floor + 1, 5), water),
+    ]);
+    for x in -3..=3 {
+        for y in 1..=3 {
+            blocks.push((IVec3::new(x, floor + y, 2), glass));
+        }
+    }
+    for (pos, block) in blocks {
+        assert!(world.set_block(pos, block), "{pos} is not loaded");
+    }
+
+    let frame = render_loaded(&mut gfx, &mut world);
+    assert_matches_golden("block_layers", &frame);
+}
+
+#[test]
+#[cfg_attr(not(feature = "gpu-tests"), ignore = "needs an adapter, enable gpu-tests")]
+fn scaled_captures_match_the_frame() {
+    let (mut gfx, mut world, _dir) = scene("scaled_captures");
+    world.camera.set_orientation(0.4, 0.7);
+    let frame = render_loaded(&mut gfx, &mut world);
+
+    assert_eq!(gfx.capture_frame(&mut world).unwrap(), frame);
+
+    let scaled = gfx.capture_frame_scaled(&mut world, 2).unwrap();
+    assert_eq!((scaled.width, scaled.height), (WIDTH * 2, HEIGHT * 2));
+    // The sky in the corner and the ground in the middle look the same
+    for (x, y) in [(2, 2), (WIDTH / 2, HEIGHT - 10)] {
+        let expected = frame.pixel(x, y);
+        let pixel = scaled.pixel(x * 2, y * 2);
+        assert!(
+            expected.iter().zip(pixel).all(|(a, b)| a.abs_diff(b) <= 24),
+            "{expected:?} != {pixel:?} at {x}, {y}"
+        );
+    }
+}
+
+#[test]
+#[cfg_attr(not(feature = "gpu-tests"), ignore = "needs an adapter, enable gpu-tests")]
+fn device_loss_is_recovered() {
+    let (mut gfx, mut world, _dir) = scene("device_loss");
+    world.camera.set_orientation(0.4, 0.7);
+    let before = render_loaded(&mut gfx, &mut world);
+
+    gfx.device.destroy();
+    gfx.device.poll(wgpu::Maintain::Poll);
+    assert!(gfx.is_device_lost());
+
+    // Chunks are meshed and uploaded again to the new device
+    let after = render_loaded(&mut gfx, &mut world);
+    assert!(!gfx.is_device_lost());
+    let diff = after.compare(&before, TOLERANCE);
+    assert_eq!(diff.differing_pixels, 0, "{diff:?}");
+}
+
+/// The overlay shows different numbers every run, so it is only checked to
+/// be drawn over the top left of the frame, and gone once hidden again.
+#[test]
+#[cfg_attr(not(feature = "gpu-tests"), ignore = "needs an adapter, enable gpu-tests")]
+fn debug_overlay_is_drawn_on_top() {
+    let (mut gfx, mut world, _dir) = scene("debug_overlay");
+    world.camera.set_orientation(0.4, 0.7);
+    let without = render_loaded(&mut gfx, &mut world);
+
+    gfx.toggle_debug_overlay();
+    gfx.render(&mut world).unwrap();
+    let with = gfx.read_frame().unwrap();
+    let corner = |frame: &Frame| {
+        let pixels = (0..8).flat_map(|y| (0..WIDTH / 2).map(move |x| (x, y + 4)));
+        pixels.map(|(x, y)| frame.pixel(x, y)).collect::<Vec<_>>()
+    };
+    assert_ne!(corner(&with), corner(&without));
+
+    gfx.toggle_debug_overlay();
+    gfx.render(&mut world).unwrap();
+    let diff = gfx.read_frame().unwrap().compare(&without, TOLERANCE);
+    assert_eq!(diff.differing_pixels, 0, "{diff:?}");
+}
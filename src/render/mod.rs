@@ -1,4 +1,12 @@
 pub mod context;
+pub mod frame;
+pub mod frustum;
+pub mod overlay;
+pub mod screenshot;
+pub mod text;
 pub mod texture;
 
+#[cfg(test)]
+mod golden;
+
 pub use context::GfxContext;
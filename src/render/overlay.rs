@@ -0,0 +1,374 @@
+//! Debug overlay toggled with F3: frame time and frame rate graphs, the
+//! camera position and facing, chunk counts and the adapter in use.
+
+use glam::{IVec3, Vec2, Vec3};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::text::{TextBatch, TextRenderer};
+
+use crate::world::chunks::coords::{world_to_chunk, world_to_local};
+use crate::world::chunks::ChunkStats;
+use crate::world::World;
+
+/// Time between the last rendered frames, oldest first.
+#[derive(Default)]
+pub struct FrameTimes {
+    times: VecDeque<Duration>,
+    last_frame: Option<Instant>,
+}
+
+impl FrameTimes {
+    /// Frames kept, one bar of the graphs each.
+    pub const CAPACITY: usize = 200;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note that a frame started at `now`.
+    pub fn record(&mut self, now: Instant) {
+        if let Some(last) = self.last_frame.replace(now) {
+            if self.times.len() == Self::CAPACITY {
+                self.times.pop_front();
+            }
+            self.times.push_back(now.saturating_duration_since(last));
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.times.iter().copied()
+    }
+
+    pub fn latest(&self) -> Option<Duration> {
+        self.times.back().copied()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.iter().max().unwrap_or_default()
+    }
+
+    /// Frames per second over all the recorded frames.
+    pub fn average_fps(&self) -> f32 {
+        let total: Duration = self.iter().sum();
+        match total.is_zero() {
+            true => 0.0,
+            false => self.times.len() as f32 / total.as_secs_f32(),
+        }
+    }
+}
+
+/// What the overlay shows about the world and the adapter.
+pub struct DebugInfo<'a> {
+    pub adapter: &'a wgpu::AdapterInfo,
+    pub position: Vec3,
+    /// Unit vector the camera looks along.
+    pub direction: Vec3,
+    pub chunks: ChunkStats,
+}
+
+impl<'a> DebugInfo<'a> {
+    pub fn new(world: &World, adapter: &'a wgpu::AdapterInfo) -> Self {
+        Self {
+            adapter,
+            position: world.camera.position(),
+            direction: world.camera.view_direction(),
+            chunks: world.chunk_stats(),
+        }
+    }
+
+    /// The lines of text of the overlay, top to bottom.
+    pub fn lines(&self, frame_times: &FrameTimes) -> Vec<String> {
+        let frame_ms = frame_times.latest().unwrap_or_default().as_secs_f32() * 1000.0;
+        let max_ms = frame_times.max().as_secs_f32() * 1000.0;
+
+        let p = self.position;
+        let block = p.floor().as_ivec3();
+        let (chunk, local) = (world_to_chunk(block), world_to_local(block));
+
+        let d = self.direction;
+        let heading = d.x.atan2(d.z).to_degrees();
+        let elevation = d.y.clamp(-1.0, 1.0).asin().to_degrees();
+
+        let chunks = &self.chunks;
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+        vec![
+            format!(
+                "{:.0} fps, {frame_ms:.1} ms, max {max_ms:.1} ms",
+                frame_times.average_fps()
+            ),
+            format!("Position: {:.2} / {:.2} / {:.2}", p.x, p.y, p.z),
+            format!("Chunk: {} in {}", fmt_ivec3(local.as_ivec3()), fmt_ivec3(chunk)),
+            format!(
+                "Facing: {} (heading {heading:.1}, elevation {elevation:.1})",
+                facing(d)
+            ),
+            format!(
+                "Chunks: {} loaded, {} pending, {} visible, {} culled",
+                chunks.loaded, chunks.pending, chunks.culling.drawn, chunks.culling.culled
+            ),
+            format!(
+                "Chunk buffers: {:.1} of {:.1} MiB used",
+                mib(chunks.gpu_used),
+                mib(chunks.gpu_allocated)
+            ),
+            format!("Adapter: {} ({:?})", self.adapter.name, self.adapter.backend),
+        ]
+    }
+}
+
+/// The horizontal axis closest to `direction`.
+fn facing(direction: Vec3) -> &'static str {
+    match (direction.x.abs() > direction.z.abs(), direction.x >= 0.0, direction.z >= 0.0) {
+        (true, true, _) => "+X",
+        (true, false, _) => "-X",
+        (false, _, true) => "+Z",
+        (false, _, false) => "-Z",
+    }
+}
+
+fn fmt_ivec3(v: IVec3) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+/// Shows [`DebugInfo`] and graphs of the [`FrameTimes`] on top of the world.
+pub struct DebugOverlay {
+    visible: bool,
+    frame_times: FrameTimes,
+    batch: TextBatch,
+    text: TextRenderer,
+}
+
+impl DebugOverlay {
+    const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+    const BACKGROUND: [u8; 4] = [0, 0, 0, 144];
+    const GOOD: [u8; 4] = [64, 224, 64, 255];
+    const SLOW: [u8; 4] = [240, 200, 32, 255];
+    const BAD: [u8; 4] = [240, 48, 48, 255];
+    const GUIDE: [u8; 4] = [255, 255, 255, 96];
+
+    /// Frame times at the top of the frame time graph.
+    const GRAPH_MAX_FRAME_TIME: Duration = Duration::from_millis(50);
+    /// Frame times up to this are drawn green, up to twice this yellow.
+    const TARGET_FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+    /// Height of the graphs, in font pixels.
+    const GRAPH_HEIGHT: f32 = 40.0;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        Self {
+            visible: false,
+            frame_times: FrameTimes::new(),
+            batch: TextBatch::new(),
+            text: TextRenderer::new(device, queue, format),
+        }
+    }
+
+    /// Create the GPU resources again on a new device, after the old one
+    /// was lost.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+    ) {
+        self.text = TextRenderer::new(device, queue, format);
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Note that a frame started at `now`. Frames are recorded while the
+    /// overlay is hidden too, so the graphs are full when it's shown.
+    pub fn record_frame(&mut self, now: Instant) {
+        self.frame_times.record(now);
+    }
+
+    /// Lay out the overlay for a screen `screen_size` pixels large.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        info: &DebugInfo,
+        screen_size: Vec2,
+    ) {
+        // Whole font pixels, larger on larger screens
+        let scale = (screen_size.y / 360.0).floor().max(1.0);
+        let margin = 4.0 * scale;
+        let line_height = 10.0 * scale;
+
+        self.batch.clear();
+
+        let mut position = Vec2::splat(margin);
+        for line in info.lines(&self.frame_times) {
+            self.label(position, scale, &line);
+            position.y += line_height;
+        }
+
+        let graph_height = Self::GRAPH_HEIGHT * scale;
+        let graph_top = screen_size.y - margin - graph_height;
+        let frame_time_graph = Vec2::new(margin, graph_top);
+        self.frame_time_graph(frame_time_graph, scale);
+        let bars_width = FrameTimes::CAPACITY as f32 * scale;
+        self.fps_graph(frame_time_graph + Vec2::X * (bars_width + 4.0 * margin), scale);
+
+        self.text.prepare(device, queue, &self.batch, screen_size);
+    }
+
+    /// Text on a dark background, so it's readable over any terrain.
+    fn label(&mut self, position: Vec2, scale: f32, text: &str) {
+        let size = TextBatch::text_size(text, scale);
+        self.batch.rect(position - scale, size + 2.0 * scale, Self::BACKGROUND);
+        self.batch.text(position, scale, Self::TEXT_COLOR, text);
+    }
+
+    /// Bars of the frame times, newest on the right, with lines at the
+    /// target frame time and twice that.
+    fn frame_time_graph(&mut self, min: Vec2, scale: f32) {
+        let max = Self::GRAPH_MAX_FRAME_TIME.as_secs_f32();
+        let times: Vec<_> = self.frame_times.iter().collect();
+        let bars = times.iter().map(|time| {
+            let color = match *time {
+                t if t <= Self::TARGET_FRAME_TIME => Self::GOOD,
+                t if t <= 2 * Self::TARGET_FRAME_TIME => Self::SLOW,
+                _ => Self::BAD,
+            };
+            (time.as_secs_f32() / max, color)
+        });
+        let guides = [1, 2].map(|n| (n * Self::TARGET_FRAME_TIME).as_secs_f32() / max);
+        let title = format!("Frame time, 0 to {} ms", Self::GRAPH_MAX_FRAME_TIME.as_millis());
+        self.graph(min, scale, &title, bars, &guides);
+    }
+
+    /// Bars of the frame rate of every frame, scaled to the highest one
+    /// rounded up to a multiple of 60.
+    fn fps_graph(&mut self, min: Vec2, scale: f32) {
+        let fps: Vec<_> = self
+            .frame_times
+            .iter()
+            .map(|time| 1.0 / time.as_secs_f32().max(1e-4))
+            .collect();
+        let top = (fps.iter().copied().fold(0.0f32, f32::max) / 60.0).ceil().max(1.0) * 60.0;
+
+        let target = 1.0 / Self::TARGET_FRAME_TIME.as_secs_f32();
+        let bars = fps.iter().map(|fps| {
+            let color = match *fps {
+                fps if fps >= target * 0.99 => Self::GOOD,
+                fps if fps >= target / 2.0 * 0.99 => Self::SLOW,
+                _ => Self::BAD,
+            };
+            (fps / top, color)
+        });
+        let guides = [target / top, target / 2.0 / top];
+        self.graph(min, scale, &format!("FPS, 0 to {top:.0}"), bars, &guides);
+    }
+
+    /// A bar graph with its top left corner at `min`, one font pixel per
+    /// bar. Bar heights and guide lines are fractions of the graph height.
+    fn graph(
+        &mut self,
+        min: Vec2,
+        scale: f32,
+        title: &str,
+        bars: impl Iterator<Item = (f32, [u8; 4])>,
+        guides: &[f32],
+    ) {
+        let height = Self::GRAPH_HEIGHT * scale;
+        let width = FrameTimes::CAPACITY as f32 * scale;
+        self.batch.rect(min, Vec2::new(width, height), Self::BACKGROUND);
+        self.label(min - Vec2::Y * 10.0 * scale, scale, title);
+
+        for (i, (fraction, color)) in bars.enumerate() {
+            let bar_height = fraction.clamp(0.0, 1.0) * height;
+            let x = min.x + i as f32 * scale;
+            let bar_min = Vec2::new(x, min.y + height - bar_height);
+            self.batch.rect(bar_min, Vec2::new(scale, bar_height), color);
+        }
+
+        for &guide in guides.iter().filter(|guide| **guide <= 1.0) {
+            let y = min.y + height - guide * height;
+            self.batch.rect(Vec2::new(min.x, y), Vec2::new(width, scale), Self::GUIDE);
+        }
+    }
+
+    /// Draw the last prepared overlay.
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
+        self.text.render(render_pass);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::world::chunks::CullingStats;
+
+    #[test]
+    fn frame_times_keep_the_latest_frames() {
+        let mut frame_times = FrameTimes::new();
+        let start = Instant::now();
+        frame_times.record(start);
+        assert_eq!(frame_times.latest(), None);
+        assert_eq!(frame_times.average_fps(), 0.0);
+
+        for i in 1..=FrameTimes::CAPACITY as u64 + 10 {
+            frame_times.record(start + Duration::from_millis(i * 20));
+        }
+        assert_eq!(frame_times.iter().count(), FrameTimes::CAPACITY);
+        assert_eq!(frame_times.latest(), Some(Duration::from_millis(20)));
+        assert!((frame_times.average_fps() - 50.0).abs() < 0.01);
+
+        frame_times.record(start + Duration::from_millis(100_000));
+        assert!(frame_times.max() > Duration::from_secs(90));
+    }
+
+    #[test]
+    fn facing_is_the_closest_axis() {
+        assert_eq!(facing(Vec3::Z), "+Z");
+        assert_eq!(facing(Vec3::new(-0.8, 0.0, 0.6)), "-X");
+        assert_eq!(facing(Vec3::new(0.1, -0.9, -0.2)), "-Z");
+        assert_eq!(facing(Vec3::new(0.7, 0.7, 0.1)), "+X");
+    }
+
+    #[test]
+    fn lines_describe_the_camera_and_chunks() {
+        let adapter = wgpu::AdapterInfo {
+            name: "Test Adapter".to_owned(),
+            vendor: 0,
+            device: 0,
+            device_type: wgpu::DeviceType::Cpu,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend: wgpu::Backend::Vulkan,
+        };
+        let info = DebugInfo {
+            adapter: &adapter,
+            position: Vec3::new(-0.5, 70.25, 33.0),
+            direction: Vec3::X,
+            chunks: ChunkStats {
+                loaded: 12,
+                pending: 3,
+                culling: CullingStats {
+                    drawn: 8,
+                    culled: 4,
+                },
+                gpu_used: 1024 * 1024,
+                gpu_allocated: 4 * 1024 * 1024,
+            },
+        };
+
+        let lines = info.lines(&FrameTimes::new());
+        assert_eq!(lines[1], "Position: -0.50 / 70.25 / 33.00");
+        assert_eq!(lines[2], "Chunk: 31 6 1 in -1 2 1");
+        assert_eq!(lines[3], "Facing: +X (heading 90.0, elevation 0.0)");
+        assert_eq!(lines[4], "Chunks: 12 loaded, 3 pending, 8 visible, 4 culled");
+        assert_eq!(lines[5], "Chunk buffers: 1.0 of 4.0 MiB used");
+        assert_eq!(lines[6], "Adapter: Test Adapter (Vulkan)");
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::frame::Frame;
+
+/// Where screenshots are written, unless `SHALLOW_STONE_SCREENSHOTS` is set.
+pub const DEFAULT_DIR: &str = "screenshots";
+
+/// Times the window size high resolution screenshots are rendered at.
+pub const HIGH_RES_SCALE: u32 = 4;
+
+/// Write `frame` to a PNG in `dir` named after the current time, returning
+/// its path.
+pub fn save(frame: &Frame, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Create {}", dir.display()))?;
+
+    let path = dir.join(file_name(SystemTime::now()));
+    frame.save_png(&path)?;
+    Ok(path)
+}
+
+/// `screenshot-YYYY-MM-DD_HH-MM-SS.mmm.png`, in UTC.
+pub fn file_name(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    format!(
+        "screenshot-{year:04}-{month:02}-{day:02}_{hour:02}-{minute:02}-{second:02}.{:03}.png",
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the date `days` days after 1970-01-01, from
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn dates_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn file_names_are_timestamped() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(file_name(time), "screenshot-2023-11-14_22-13-20.123.png");
+    }
+}
@@ -0,0 +1,350 @@
+pub mod font;
+
+use glam::Vec2;
+
+use wgpu::util::DeviceExt;
+
+use font::{GLYPH_COUNT, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// A corner of a glyph or rectangle, in pixels from the top left of the
+/// screen.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TextVertex {
+    pub position: [f32; 2],
+    /// Texel of the font atlas.
+    pub uv: [f32; 2],
+    pub color: [u8; 4],
+}
+
+impl TextVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Unorm8x4,
+    ];
+
+    pub fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<TextVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// Text and solid rectangles to draw in one go with a [`TextRenderer`], as
+/// a list of triangles.
+#[derive(Default)]
+pub struct TextBatch {
+    vertices: Vec<TextVertex>,
+}
+
+impl TextBatch {
+    /// Pixels from one glyph to the next, at scale 1.
+    pub const ADVANCE: u32 = GLYPH_WIDTH + 1;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn vertices(&self) -> &[TextVertex] {
+        &self.vertices
+    }
+
+    /// Size of `text` in pixels, with every font pixel `scale` pixels wide.
+    pub fn text_size(text: &str, scale: f32) -> Vec2 {
+        let width = (text.chars().count() as u32 * Self::ADVANCE).saturating_sub(1);
+        Vec2::new(width as f32, GLYPH_HEIGHT as f32) * scale
+    }
+
+    /// Draw one line of `text` with its top left corner at `position`. Each
+    /// font pixel is `scale` pixels wide, which should be a whole number to
+    /// keep the glyphs sharp.
+    pub fn text(&mut self, position: Vec2, scale: f32, color: [u8; 4], text: &str) {
+        let size = Vec2::new(GLYPH_WIDTH as f32, GLYPH_HEIGHT as f32) * scale;
+        for (i, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            let min = position + Vec2::X * (i as u32 * Self::ADVANCE) as f32 * scale;
+            self.quad(min, size, font::glyph_index(c), color);
+        }
+    }
+
+    /// A solid rectangle with its top left corner at `min`.
+    pub fn rect(&mut self, min: Vec2, size: Vec2, color: [u8; 4]) {
+        self.quad(min, size, font::SOLID, color);
+    }
+
+    fn quad(&mut self, min: Vec2, size: Vec2, glyph: u32, color: [u8; 4]) {
+        let uv_min = Vec2::new((glyph * GLYPH_WIDTH) as f32, 0.0);
+        let uv_size = Vec2::new(GLYPH_WIDTH as f32, GLYPH_HEIGHT as f32);
+
+        let corner = |x: f32, y: f32| TextVertex {
+            position: (min + size * Vec2::new(x, y)).into(),
+            uv: (uv_min + uv_size * Vec2::new(x, y)).into(),
+            color,
+        };
+        let (tl, tr, bl, br) =
+            (corner(0.0, 0.0), corner(1.0, 0.0), corner(0.0, 1.0), corner(1.0, 1.0));
+        self.vertices.extend([tl, bl, br, tl, br, tr]);
+    }
+}
+
+/// Draws [`TextBatch`]es on top of a frame with the built in bitmap font.
+pub struct TextRenderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    screen_buffer: wgpu::Buffer,
+
+    vertices: wgpu::Buffer,
+    vertex_count: u32,
+}
+
+impl TextRenderer {
+    const INITIAL_VERTICES: u64 = 1 << 14;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("./text.wgsl"));
+
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("text screen size buffer"),
+            contents: bytemuck::cast_slice(&[1.0f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let atlas = create_font_atlas(device, queue);
+        let atlas_view = atlas.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("font atlas sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("text bind group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: screen_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("text pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("text pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[TextVertex::layout()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // Drawn in a pass of its own, over everything
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            screen_buffer,
+            vertices: Self::create_vertex_buffer(device, Self::INITIAL_VERTICES),
+            vertex_count: 0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, vertices: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("text vertex buffer"),
+            size: vertices * size_of::<TextVertex>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Upload `batch` to be drawn by [`TextRenderer::render`], positioned on
+    /// a screen `screen_size` pixels large.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        batch: &TextBatch,
+        screen_size: Vec2,
+    ) {
+        let vertices = batch.vertices();
+        let bytes = size_of_val(vertices) as u64;
+        if bytes > self.vertices.size() {
+            let capacity = (vertices.len() as u64).next_power_of_two();
+            self.vertices = Self::create_vertex_buffer(device, capacity);
+        }
+
+        queue.write_buffer(&self.vertices, 0, bytemuck::cast_slice(vertices));
+        let screen = [screen_size.x, screen_size.y, 0.0, 0.0];
+        queue.write_buffer(&self.screen_buffer, 0, bytemuck::cast_slice(&screen));
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    /// Draw the last prepared batch.
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertices.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+/// Coverage of every glyph side by side in one row, in atlas order.
+fn font_atlas_pixels() -> Vec<u8> {
+    let width = GLYPH_COUNT * GLYPH_WIDTH;
+    (0..GLYPH_HEIGHT)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| match font::is_set(x / GLYPH_WIDTH, x % GLYPH_WIDTH, y) {
+            true => 255,
+            false => 0,
+        })
+        .collect()
+}
+
+fn create_font_atlas(device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::Texture {
+    let size = wgpu::Extent3d {
+        width: GLYPH_COUNT * GLYPH_WIDTH,
+        height: GLYPH_HEIGHT,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("font atlas"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    queue.write_texture(
+        texture.as_image_copy(),
+        &font_atlas_pixels(),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(size.width),
+            rows_per_image: Some(size.height),
+        },
+        size,
+    );
+    texture
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_laid_out_left_to_right() {
+        let mut batch = TextBatch::new();
+        batch.text(Vec2::new(10.0, 20.0), 2.0, [255; 4], "a b");
+
+        // Spaces aren't drawn
+        let vertices = batch.vertices();
+        assert_eq!(vertices.len(), 12);
+        assert_eq!(vertices[0].position, [10.0, 20.0]);
+        assert_eq!(vertices[6].position, [10.0 + 4.0 * TextBatch::ADVANCE as f32, 20.0]);
+
+        let (min, max) = vertices.iter().fold((Vec2::MAX, Vec2::MIN), |(min, max), v| {
+            (min.min(v.position.into()), max.max(v.position.into()))
+        });
+        assert_eq!(max - min, TextBatch::text_size("a b", 2.0));
+    }
+
+    #[test]
+    fn glyphs_sample_their_atlas_cell() {
+        let mut batch = TextBatch::new();
+        batch.text(Vec2::ZERO, 1.0, [255; 4], "A");
+        batch.rect(Vec2::ZERO, Vec2::ONE, [255; 4]);
+
+        let cell_uvs = |quad: &[TextVertex]| {
+            quad.iter().fold((f32::MAX, f32::MIN), |(min, max), v| {
+                (min.min(v.uv[0]), max.max(v.uv[0]))
+            })
+        };
+        let a = (font::glyph_index('A') * GLYPH_WIDTH) as f32;
+        assert_eq!(cell_uvs(&batch.vertices()[..6]), (a, a + GLYPH_WIDTH as f32));
+        let solid = (font::SOLID * GLYPH_WIDTH) as f32;
+        assert_eq!(cell_uvs(&batch.vertices()[6..]), (solid, solid + GLYPH_WIDTH as f32));
+
+        let pixels = font_atlas_pixels();
+        assert_eq!(pixels.len(), (GLYPH_COUNT * GLYPH_WIDTH * GLYPH_HEIGHT) as usize);
+        assert_eq!(*pixels.last().unwrap(), 255);
+    }
+}
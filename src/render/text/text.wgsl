@@ -0,0 +1,35 @@
+// Size of the screen in pixels, in xy
+@group(0) @binding(0) var<uniform> screen: vec4<f32>;
+// Coverage of every glyph, side by side
+@group(0) @binding(1) var font_atlas: texture_2d<f32>;
+@group(0) @binding(2) var font_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // In texels of the font atlas
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+) -> VertexOutput {
+    // Pixels from the top left to clip space
+    let clip = position / screen.xy * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(clip, 0.0, 1.0);
+    out.uv = uv;
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.uv / vec2<f32>(textureDimensions(font_atlas));
+    let coverage = textureSample(font_atlas, font_sampler, uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
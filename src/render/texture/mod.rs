@@ -1,3 +1,6 @@
+use anyhow::{bail, Context, Result};
+
+use std::path::Path;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -7,6 +10,10 @@ pub struct Texture {
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// Depth is reversed, 1 at the near plane and 0 at the far plane or at
+    /// infinity, which spreads float precision evenly over the distance.
+    pub const DEPTH_CLEAR: f32 = 0.0;
+    pub const DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::Greater;
 
     pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
         let size = wgpu::Extent3d {
@@ -46,3 +53,309 @@ impl Texture {
         Self { texture, view, sampler }
     }
 }
+
+/// Square RGBA8 image with its mip chain, largest level first.
+pub struct MipChain {
+    pub size: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl MipChain {
+    /// Build the mip chain of a square, power of two sized sRGB image by
+    /// averaging 2×2 blocks of pixels. Colours are averaged in linear space
+    /// and weighted by alpha, so transparent pixels don't darken the edges of
+    /// cut-out textures like leaves.
+    pub fn generate(size: u32, rgba: Vec<u8>) -> Self {
+        debug_assert!(size.is_power_of_two());
+        debug_assert_eq!(rgba.len(), (size * size * 4) as usize);
+
+        let mut levels = vec![rgba];
+        let mut level_size = size;
+        while level_size > 1 {
+            let previous = levels.last().unwrap();
+            let next_size = level_size / 2;
+            let mut next = vec![0; (next_size * next_size * 4) as usize];
+
+            for y in 0..next_size {
+                for x in 0..next_size {
+                    let mut color = [0.0; 3];
+                    let mut alpha = 0.0;
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let i = (((y * 2 + dy) * level_size + x * 2 + dx) * 4) as usize;
+                        let pixel = &previous[i..i + 4];
+                        let weight = pixel[3] as f32 / 255.0;
+                        for c in 0..3 {
+                            color[c] += srgb_to_linear(pixel[c]) * weight;
+                        }
+                        alpha += weight;
+                    }
+
+                    let i = ((y * next_size + x) * 4) as usize;
+                    if alpha > 0.0 {
+                        for c in 0..3 {
+                            next[i + c] = linear_to_srgb(color[c] / alpha);
+                        }
+                    }
+                    next[i + 3] = (alpha / 4.0 * 255.0).round() as u8;
+                }
+            }
+
+            levels.push(next);
+            level_size = next_size;
+        }
+
+        Self { size, levels }
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl Texture {
+    /// Side length of every block texture, in pixels.
+    pub const BLOCK_TEXTURE_SIZE: u32 = 16;
+    pub const BLOCK_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Load `<dir>/<name>.png` for every name into the layers of a 2D texture
+    /// array, in order. Textures that are missing or can't be decoded are
+    /// replaced by a magenta and black checkerboard.
+    pub fn load_block_texture_array(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dir: &Path,
+        names: &[String],
+    ) -> Self {
+        let size = Self::BLOCK_TEXTURE_SIZE;
+
+        let mut layers: Vec<MipChain> = names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name).with_extension("png");
+                let rgba = load_png(&path, size).unwrap_or_else(|err| {
+                    tracing::warn!("Using placeholder for texture {name:?}: {err:#}");
+                    checkerboard(size)
+                });
+                MipChain::generate(size, rgba)
+            })
+            .collect();
+
+        // A texture array view needs at least one layer
+        if layers.is_empty() {
+            layers.push(MipChain::generate(size, checkerboard(size)));
+        }
+
+        let mip_level_count = layers[0].mip_level_count();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("block texture array"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers.len() as u32,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::BLOCK_TEXTURE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, chain) in layers.iter().enumerate() {
+            for (level, pixels) in chain.levels.iter().enumerate() {
+                let level_size = (size >> level).max(1);
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &texture,
+                        mip_level: level as u32,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: layer as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    pixels,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * level_size),
+                        rows_per_image: Some(level_size),
+                    },
+                    wgpu::Extent3d {
+                        width: level_size,
+                        height: level_size,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("block texture array view"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("block texture sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+/// Decode a PNG into RGBA8 pixels, requiring it to be `size` × `size`.
+pub fn load_png(path: &Path, size: u32) -> Result<Vec<u8>> {
+    let file = std::fs::File::open(path).with_context(|| format!("Open {}", path.display()))?;
+
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().context("Read PNG header")?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).context("Decode PNG")?;
+
+    if info.width != size || info.height != size {
+        bail!(
+            "{} is {}×{}, expected {size}×{size}",
+            path.display(),
+            info.width,
+            info.height
+        );
+    }
+
+    let pixels = &buffer[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => bail!("Indexed PNG was not expanded"),
+    };
+
+    Ok(rgba)
+}
+
+/// Magenta and black checkerboard of 2×2 pixel squares marking a texture
+/// that failed to load.
+pub fn checkerboard(size: u32) -> Vec<u8> {
+    (0..size * size)
+        .flat_map(|i| {
+            let (x, y) = (i % size, i / size);
+            if (x / 2 + y / 2) % 2 == 0 {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_halves_down_to_one_pixel() {
+        let chain = MipChain::generate(16, checkerboard(16));
+        assert_eq!(chain.mip_level_count(), 5);
+        for (level, pixels) in chain.levels.iter().enumerate() {
+            let size = 16 >> level;
+            assert_eq!(pixels.len(), size * size * 4);
+        }
+    }
+
+    #[test]
+    fn mips_average_pixels() {
+        // 2×2: white, black, red, transparent
+        let rgba = vec![
+            255, 255, 255, 255, 0, 0, 0, 255, //
+            255, 0, 0, 255, 0, 0, 0, 0,
+        ];
+        let chain = MipChain::generate(2, rgba);
+        // Linear red is (1 + 0 + 1) / 3 and green and blue 1 / 3 of the three
+        // opaque pixels, ignoring the transparent one
+        assert_eq!(chain.levels[1], vec![213, 156, 156, 191]);
+    }
+
+    #[test]
+    fn transparent_pixels_dont_darken_mips() {
+        // 2×2: green, then three transparent black pixels like those around
+        // the edges of leaves
+        let rgba = vec![
+            0, 200, 0, 255, 0, 0, 0, 0, //
+            0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let chain = MipChain::generate(2, rgba);
+        assert_eq!(chain.levels[1], vec![0, 200, 0, 64]);
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn checkerboard_alternates() {
+        let board = checkerboard(4);
+        let pixel = |x: usize, y: usize| &board[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), [255, 0, 255, 255]);
+        assert_eq!(pixel(1, 1), [255, 0, 255, 255]);
+        assert_eq!(pixel(2, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(2, 2), [255, 0, 255, 255]);
+    }
+
+    #[test]
+    fn missing_png_is_an_error() {
+        assert!(load_png(Path::new("does/not/exist.png"), 16).is_err());
+    }
+
+    #[test]
+    fn block_textures_decode() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/textures");
+        let registry = crate::world::registry::BlockRegistry::builtin();
+        for name in registry.texture_names() {
+            let rgba = load_png(&dir.join(name).with_extension("png"), Texture::BLOCK_TEXTURE_SIZE)
+                .unwrap_or_else(|err| panic!("{name}: {err:#}"));
+            assert_eq!(rgba.len(), 16 * 16 * 4);
+        }
+    }
+}
@@ -4,30 +4,136 @@ use winit::keyboard::KeyCode;
 use glam::{Mat4, Quat, Vec3};
 
 use crate::input::InputState;
+use crate::render::frustum::Frustum;
 
 use std::f32::consts::{FRAC_PI_2, PI};
 
+/// Tunable camera controls.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraSettings {
+    /// Flying speed, in blocks per second.
+    pub speed: f32,
+    /// Radians turned per pixel of mouse movement.
+    pub sensitivity: f32,
+    /// Speed multiplier while sprinting.
+    pub sprint_multiplier: f32,
+    /// How fast the camera gets up to speed, in blocks per second squared.
+    /// `None` reaches full speed instantly.
+    pub acceleration: Option<f32>,
+    /// Exponential slow down rate once no movement keys are held, per
+    /// second. Only used with `acceleration`.
+    pub damping: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            speed: 10.0,
+            sensitivity: 0.001,
+            sprint_multiplier: 3.0,
+            acceleration: Some(80.0),
+            damping: 10.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectionMode {
+    Perspective {
+        /// Vertical field of view, in radians.
+        fov_y: f32,
+    },
+    /// Parallel projection, for map screenshots.
+    Orthographic {
+        /// Blocks visible from the bottom to the top of the screen.
+        height: f32,
+    },
+}
+
+/// Camera projection. Every projection maps depth reversed, see
+/// [`Texture::DEPTH_COMPARE`](crate::render::texture::Texture::DEPTH_COMPARE).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    pub mode: ProjectionMode,
+    pub near: f32,
+    /// Ignored by perspective projections with `infinite_far`.
+    pub far: f32,
+    pub infinite_far: bool,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            mode: ProjectionMode::Perspective {
+                fov_y: 80.0f32.to_radians(),
+            },
+            near: 0.1,
+            far: 100.0,
+            infinite_far: true,
+        }
+    }
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        // Passing far as near and near as far reverses the depth
+        match self.mode {
+            ProjectionMode::Perspective { fov_y } if self.infinite_far => {
+                Mat4::perspective_infinite_reverse_lh(fov_y, aspect, self.near)
+            }
+            ProjectionMode::Perspective { fov_y } => {
+                Mat4::perspective_lh(fov_y, aspect, self.far, self.near)
+            }
+            ProjectionMode::Orthographic { height } => {
+                let (w, h) = (height * aspect / 2.0, height / 2.0);
+                Mat4::orthographic_lh(-w, w, -h, h, self.far, self.near)
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Camera {
     position: Vec3,
+    /// Position at the previous simulation step, for interpolation.
+    previous_position: Vec3,
+    /// How far between the previous and current step the frame being drawn
+    /// is, from 0 to 1.
+    interpolation: f32,
+    velocity: Vec3,
 
     pitch: f32, // Up/Down, x-axis rotation
     yaw: f32,   // Left/Right, y-axis rotation
 
-    projection: Mat4,
+    pub settings: CameraSettings,
+
+    pub projection: Projection,
+    /// Perspective mode to go back to when leaving the orthographic one.
+    perspective: Option<ProjectionMode>,
+    /// Whether the zoom key is held, narrowing the field of view.
+    zoomed: bool,
 }
 
 impl Camera {
-    pub fn update(&mut self, _delta: f32, input: &InputState) {
-        const SENSITIVITY: f32 = 0.001;
-        const SPEED: f32 = 0.01;
+    pub const ZOOM_KEY: KeyCode = KeyCode::KeyC;
+    /// How much the zoom key magnifies.
+    pub const ZOOM: f32 = 4.0;
 
-        let (dx, dy) = input.mdelta();
+    /// Turn the camera by the mouse movement of this frame. Called once per
+    /// rendered frame, since mouse movement is a distance, not a speed.
+    pub fn look(&mut self, input: &InputState) {
+        self.zoomed = input.is_key_pressed(Self::ZOOM_KEY);
 
-        self.pitch =
-            (self.pitch + (dy as f32) * SENSITIVITY).clamp(-FRAC_PI_2 + 0.1, FRAC_PI_2 - 0.1);
-        self.yaw += (dx as f32) * SENSITIVITY;
+        let (dx, dy) = input.mdelta();
+        let sensitivity = self.settings.sensitivity / self.zoom();
+        self.set_orientation(
+            self.pitch + dy as f32 * sensitivity,
+            self.yaw + dx as f32 * sensitivity,
+        );
+    }
 
+    /// Move the camera over `delta` seconds of simulation.
+    pub fn update(&mut self, delta: f32, input: &InputState) {
         let view_dir = self.view_dir();
         let right_dir = Quat::from_rotation_y(self.yaw) * Vec3::X;
 
@@ -53,17 +159,79 @@ impl Camera {
         }
 
         let speed_mul = if input.is_key_pressed(KeyCode::ShiftLeft) {
-            3.0
+            self.settings.sprint_multiplier
         } else {
             1.0
         };
 
-        self.position += move_dir.normalize_or_zero() * speed_mul * SPEED;
+        let target = move_dir.normalize_or_zero() * self.settings.speed * speed_mul;
+        self.velocity = match self.settings.acceleration {
+            None => target,
+            Some(_) if target == Vec3::ZERO => {
+                let velocity = self.velocity * (-self.settings.damping * delta).exp();
+                if velocity.length_squared() < 1e-4 {
+                    Vec3::ZERO
+                } else {
+                    velocity
+                }
+            }
+            Some(acceleration) => {
+                let change = target - self.velocity;
+                self.velocity + change.clamp_length_max(acceleration * delta)
+            }
+        };
+
+        self.previous_position = self.position;
+        self.position += self.velocity * delta;
+    }
+
+    /// Set how far the frame being drawn is between the last two simulation
+    /// steps, from 0 to 1.
+    pub fn set_interpolation(&mut self, alpha: f32) {
+        self.interpolation = alpha.clamp(0.0, 1.0);
+    }
+
+    /// Position the frame is drawn from, between the last two steps.
+    pub fn render_position(&self) -> Vec3 {
+        self.previous_position
+            .lerp(self.position, self.interpolation)
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+        self.previous_position = position;
+    }
+
+    /// Move to `position` as one simulation step, so frames in between are
+    /// interpolated. For when something else, like a player, moves the
+    /// camera.
+    pub fn follow(&mut self, position: Vec3) {
+        self.previous_position = self.position;
+        self.position = position;
+        self.velocity = Vec3::ZERO;
+    }
+
+    /// Pitch and yaw, in radians.
+    pub fn orientation(&self) -> (f32, f32) {
+        (self.pitch, self.yaw)
+    }
+
+    pub fn set_orientation(&mut self, pitch: f32, yaw: f32) {
+        self.pitch = pitch.clamp(-FRAC_PI_2 + 0.1, FRAC_PI_2 - 0.1);
+        self.yaw = yaw;
     }
 
     pub fn write_view_matrix_buffer(&self, queue: &wgpu::Queue, buffer: &wgpu::Buffer) {
         // FIXME: Write buffer only when changed
-        let matrix = Mat4::look_to_lh(self.position, self.view_dir(), Vec3::Y);
+        let matrix = self.view_matrix();
         queue.write_buffer(
             buffer,
             0 as wgpu::BufferAddress,
@@ -71,12 +239,25 @@ impl Camera {
         );
     }
 
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_to_lh(self.render_position(), self.view_dir(), Vec3::Y)
+    }
+
+    pub fn frustum(&self, aspect: f32) -> Frustum {
+        Frustum::from_matrix(self.projection_matrix(aspect) * self.view_matrix())
+    }
+
+    /// Unit vector the camera looks along.
+    pub fn view_direction(&self) -> Vec3 {
+        self.view_dir()
+    }
+
     fn view_dir(&self) -> Vec3 {
         Quat::from_euler(glam::EulerRot::YXZ, self.yaw, self.pitch, 0.0) * Vec3::Z
     }
 
     pub fn write_projection_matrix_buffer(&self, queue: &wgpu::Queue, buffer: &wgpu::Buffer, aspect: f32) {
-        let projection = Self::projection(aspect);
+        let projection = self.projection_matrix(aspect);
         queue.write_buffer(
             buffer,
             0 as wgpu::BufferAddress,
@@ -84,8 +265,198 @@ impl Camera {
         );
     }
 
-    fn projection(aspect: f32) -> Mat4 {
-        Mat4::perspective_lh(80.0f32.to_radians(), aspect, 0.1, 100.0)
+    fn zoom(&self) -> f32 {
+        if self.zoomed {
+            Self::ZOOM
+        } else {
+            1.0
+        }
     }
 
+    /// The projection with the zoom key applied.
+    pub fn effective_projection(&self) -> Projection {
+        let mode = match self.projection.mode {
+            ProjectionMode::Perspective { fov_y } => ProjectionMode::Perspective {
+                fov_y: fov_y / self.zoom(),
+            },
+            ProjectionMode::Orthographic { height } => ProjectionMode::Orthographic {
+                height: height / self.zoom(),
+            },
+        };
+        Projection {
+            mode,
+            ..self.projection
+        }
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+        self.effective_projection().matrix(aspect)
+    }
+
+    /// Switch between perspective and an orthographic projection showing
+    /// `height` blocks. Switching back restores the previous field of view.
+    pub fn toggle_orthographic(&mut self, height: f32) {
+        self.projection.mode = match self.projection.mode {
+            perspective @ ProjectionMode::Perspective { .. } => {
+                self.perspective = Some(perspective);
+                ProjectionMode::Orthographic { height }
+            }
+            ProjectionMode::Orthographic { .. } => self
+                .perspective
+                .take()
+                .unwrap_or(Projection::default().mode),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use winit::event::ElementState;
+
+    fn holding(keys: &[KeyCode]) -> InputState {
+        let mut input = InputState::new();
+        for key in keys {
+            input.on_keyboard_key(*key, ElementState::Pressed);
+        }
+        input
+    }
+
+    fn fly(camera: &mut Camera, input: &InputState, seconds: f32, steps: u32) {
+        for _ in 0..steps {
+            camera.update(seconds / steps as f32, input);
+        }
+    }
+
+    #[test]
+    fn distance_does_not_depend_on_the_step_rate() {
+        let input = holding(&[KeyCode::KeyW]);
+        let mut slow = Camera::default();
+        let mut fast = Camera::default();
+        slow.settings.acceleration = None;
+        fast.settings.acceleration = None;
+
+        fly(&mut slow, &input, 1.0, 30);
+        fly(&mut fast, &input, 1.0, 240);
+
+        assert!((slow.position().length() - 10.0).abs() < 1e-3);
+        assert!(slow.position().abs_diff_eq(fast.position(), 1e-3));
+    }
+
+    #[test]
+    fn sprinting_multiplies_speed() {
+        let mut camera = Camera::default();
+        camera.settings.acceleration = None;
+        fly(&mut camera, &holding(&[KeyCode::KeyW, KeyCode::ShiftLeft]), 1.0, 60);
+        assert!((camera.position().length() - 30.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn accelerates_then_damps_to_a_stop() {
+        let mut camera = Camera::default();
+        let acceleration = camera.settings.acceleration.unwrap();
+
+        camera.update(0.01, &holding(&[KeyCode::KeyD]));
+        assert!((camera.velocity().length() - acceleration * 0.01).abs() < 1e-4);
+
+        fly(&mut camera, &holding(&[KeyCode::KeyD]), 1.0, 60);
+        assert!((camera.velocity().length() - camera.settings.speed).abs() < 1e-4);
+
+        let before = camera.position();
+        fly(&mut camera, &InputState::new(), 2.0, 120);
+        assert_eq!(camera.velocity(), Vec3::ZERO);
+        // Damping keeps it sliding for a bit, but not further than at speed
+        let slide = (camera.position() - before).length();
+        assert!(slide > 0.1 && slide < camera.settings.speed);
+    }
+
+    fn depth(projection: &Projection, distance: f32) -> f32 {
+        let clip = projection.matrix(1.0) * Vec3::new(0.0, 0.0, distance).extend(1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn projections_reverse_depth() {
+        let mut projection = Projection::default();
+        assert!((depth(&projection, projection.near) - 1.0).abs() < 1e-5);
+        assert!(depth(&projection, 1e6) > 0.0 && depth(&projection, 1e6) < 1e-6);
+        assert!(depth(&projection, 10.0) > depth(&projection, 20.0));
+
+        projection.infinite_far = false;
+        projection.far = 500.0;
+        assert!((depth(&projection, projection.near) - 1.0).abs() < 1e-5);
+        assert!(depth(&projection, 500.0).abs() < 1e-5);
+
+        projection.mode = ProjectionMode::Orthographic { height: 64.0 };
+        assert!((depth(&projection, projection.near) - 1.0).abs() < 1e-5);
+        assert!(depth(&projection, 500.0).abs() < 1e-5);
+        assert!((depth(&projection, 250.05) - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn orthographic_keeps_sizes_with_distance() {
+        let mut camera = Camera::default();
+        camera.toggle_orthographic(64.0);
+        let projection = camera.projection_matrix(2.0);
+
+        for distance in [1.0, 50.0] {
+            let top = projection * Vec3::new(0.0, 32.0, distance).extend(1.0);
+            let right = projection * Vec3::new(64.0, 0.0, distance).extend(1.0);
+            assert!((top.y / top.w - 1.0).abs() < 1e-5);
+            assert!((right.x / right.w - 1.0).abs() < 1e-5);
+        }
+
+        camera.toggle_orthographic(64.0);
+        assert_eq!(camera.projection, Projection::default());
+    }
+
+    #[test]
+    fn leaving_orthographic_restores_the_field_of_view() {
+        let mut camera = Camera::default();
+        let narrow = ProjectionMode::Perspective {
+            fov_y: 50.0f32.to_radians(),
+        };
+        camera.projection.mode = narrow;
+
+        // Zooming while orthographic doesn't change the restored view
+        camera.toggle_orthographic(64.0);
+        camera.look(&holding(&[Camera::ZOOM_KEY]));
+        camera.toggle_orthographic(64.0);
+        assert_eq!(camera.projection.mode, narrow);
+
+        camera.look(&InputState::new());
+        camera.toggle_orthographic(32.0);
+        camera.toggle_orthographic(32.0);
+        assert_eq!(camera.effective_projection().mode, narrow);
+    }
+
+    #[test]
+    fn zoom_narrows_the_field_of_view() {
+        let mut camera = Camera::default();
+        camera.look(&holding(&[Camera::ZOOM_KEY]));
+
+        let ProjectionMode::Perspective { fov_y } = camera.effective_projection().mode else {
+            panic!("expected a perspective projection");
+        };
+        let ProjectionMode::Perspective { fov_y: unzoomed } = camera.projection.mode else {
+            panic!("expected a perspective projection");
+        };
+        assert!((fov_y * Camera::ZOOM - unzoomed).abs() < 1e-6);
+
+        camera.look(&InputState::new());
+        assert_eq!(camera.effective_projection(), camera.projection);
+    }
+
+    #[test]
+    fn render_position_interpolates_between_steps() {
+        let mut camera = Camera::default();
+        camera.settings.acceleration = None;
+        camera.update(0.1, &holding(&[KeyCode::Space]));
+
+        camera.set_interpolation(0.5);
+        assert!(camera.render_position().abs_diff_eq(Vec3::new(0.0, 0.5, 0.0), 1e-5));
+        camera.set_interpolation(1.0);
+        assert_eq!(camera.render_position(), camera.position());
+    }
 }
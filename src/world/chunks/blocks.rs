@@ -0,0 +1,62 @@
+use glam::UVec3;
+
+use super::coords::{local_index, CHUNK_VOLUME};
+
+pub type BlockId = u16;
+
+pub const AIR: BlockId = 0;
+
+/// Dense grid of block ids for a single chunk.
+#[derive(Clone)]
+pub struct ChunkBlocks {
+    blocks: Box<[BlockId]>,
+}
+
+impl Default for ChunkBlocks {
+    fn default() -> Self {
+        Self::filled(AIR)
+    }
+}
+
+impl ChunkBlocks {
+    pub fn filled(block: BlockId) -> Self {
+        Self {
+            blocks: vec![block; CHUNK_VOLUME].into_boxed_slice(),
+        }
+    }
+
+    pub fn get(&self, local: UVec3) -> BlockId {
+        self.blocks[local_index(local)]
+    }
+
+    pub fn set(&mut self, local: UVec3, block: BlockId) {
+        self.blocks[local_index(local)] = block;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|b| *b == AIR)
+    }
+
+    pub fn as_slice(&self) -> &[BlockId] {
+        &self.blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_get() {
+        let mut blocks = ChunkBlocks::default();
+        assert!(blocks.is_empty());
+
+        blocks.set(UVec3::new(1, 2, 3), 7);
+        blocks.set(UVec3::new(31, 31, 31), 9);
+
+        assert_eq!(blocks.get(UVec3::new(1, 2, 3)), 7);
+        assert_eq!(blocks.get(UVec3::new(31, 31, 31)), 9);
+        assert_eq!(blocks.get(UVec3::new(3, 2, 1)), AIR);
+        assert!(!blocks.is_empty());
+    }
+}
@@ -0,0 +1,461 @@
+use glam::IVec3;
+
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use super::chunk::ChunkVertex;
+use super::coords::chunk_origin;
+use super::mesher::{self, ChunkMesh};
+use crate::world::registry::RenderLayer;
+
+/// First-fit allocator of ranges in a buffer. Units are up to the caller.
+pub struct RangeAllocator {
+    capacity: u32,
+    /// Free ranges, sorted and never touching each other.
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> Self {
+        let mut allocator = Self {
+            capacity: 0,
+            free: vec![],
+        };
+        allocator.grow(capacity);
+        allocator
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn free_size(&self) -> u32 {
+        self.free.iter().map(|range| range.len() as u32).sum()
+    }
+
+    pub fn allocate(&mut self, size: u32) -> Option<Range<u32>> {
+        debug_assert!(size > 0);
+        let i = self.free.iter().position(|range| range.len() as u32 >= size)?;
+
+        let start = self.free[i].start;
+        self.free[i].start += size;
+        if self.free[i].is_empty() {
+            self.free.remove(i);
+        }
+        Some(start..start + size)
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        debug_assert!(range.end <= self.capacity);
+        let i = self.free.partition_point(|free| free.start < range.start);
+        debug_assert!(i == 0 || self.free[i - 1].end <= range.start, "double free");
+
+        let merges_before = i > 0 && self.free[i - 1].end == range.start;
+        let merges_after = i < self.free.len() && self.free[i].start == range.end;
+
+        match (merges_before, merges_after) {
+            (true, true) => {
+                self.free[i - 1].end = self.free[i].end;
+                self.free.remove(i);
+            }
+            (true, false) => self.free[i - 1].end = range.end,
+            (false, true) => self.free[i].start = range.start,
+            (false, false) => self.free.insert(i, range),
+        }
+    }
+
+    /// Extend the capacity, adding the new space at the end as free.
+    pub fn grow(&mut self, capacity: u32) {
+        debug_assert!(capacity >= self.capacity);
+        let old = self.capacity;
+        self.capacity = capacity;
+        if capacity > old {
+            self.free(old..capacity);
+        }
+    }
+}
+
+/// Per-chunk data read by the chunk shaders, indexed by slot.
+///
+/// Must match `ChunkSlot` in chunk.wgsl and chunk_cull.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChunkSlot {
+    pub origin: [i32; 3],
+    pub first_quad: u32,
+    /// Zero for unused slots.
+    pub opaque_quads: u32,
+    /// Cutout quads, following the opaque ones.
+    pub cutout_quads: u32,
+    /// Translucent quads, last.
+    pub translucent_quads: u32,
+    _padding: u32,
+}
+
+/// Where a chunk mesh lives in the [`ChunkBuffers`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkAllocation {
+    pub slot: u32,
+    pub quads: Range<u32>,
+    /// Quads in each [`RenderLayer`], in layer order.
+    pub layer_quads: [u32; 3],
+}
+
+impl ChunkAllocation {
+    pub fn quad_count(&self) -> u32 {
+        self.quads.len() as u32
+    }
+
+    pub fn base_vertex(&self) -> i32 {
+        (self.quads.start * 4) as i32
+    }
+
+    /// Quads of `layer`, relative to the start of the allocation.
+    pub fn layer_range(&self, layer: RenderLayer) -> Range<u32> {
+        mesher::layer_range(self.layer_quads, layer)
+    }
+}
+
+/// Vertex data of every loaded chunk, suballocated from one vertex buffer,
+/// and a table of chunk slots in a storage buffer.
+///
+/// Chunks are drawn with the slot as instance index, which the vertex shader
+/// uses to find the chunk origin. The buffers grow when they run out of
+/// space, which copies the old contents on the GPU.
+pub struct ChunkBuffers {
+    vertices: wgpu::Buffer,
+    quads: RangeAllocator,
+
+    slots: wgpu::Buffer,
+    /// Indirect draw commands written by the culling pass, one per slot for
+    /// opaque quads followed by one per slot for cutout quads.
+    draws: wgpu::Buffer,
+    slot_capacity: u32,
+    /// Slots below `slot_count` that are unused.
+    free_slots: BTreeSet<u32>,
+    slot_count: u32,
+
+    pub layout: wgpu::BindGroupLayout,
+    pub group: wgpu::BindGroup,
+    /// Incremented every time the slot buffers are recreated, so bind groups
+    /// using them know to be rebuilt.
+    generation: u32,
+}
+
+impl ChunkBuffers {
+    const INITIAL_QUADS: u32 = 1 << 18;
+    const INITIAL_SLOTS: u32 = 1 << 10;
+
+    const QUAD_SIZE: u64 = 4 * size_of::<ChunkVertex>() as u64;
+    const SLOT_SIZE: u64 = size_of::<ChunkSlot>() as u64;
+    pub const DRAW_SIZE: u64 = size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+    /// Layers with indirect draws. Translucent quads are drawn chunk by
+    /// chunk, sorted back to front.
+    pub const DRAWN_LAYERS: [RenderLayer; 2] = [RenderLayer::Opaque, RenderLayer::Cutout];
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("chunk slots bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let vertices = Self::create_vertex_buffer(device, Self::INITIAL_QUADS);
+        let (slots, draws) = Self::create_slot_buffers(device, Self::INITIAL_SLOTS);
+        let group = Self::create_bind_group(device, &layout, &slots);
+
+        Self {
+            vertices,
+            quads: RangeAllocator::new(Self::INITIAL_QUADS),
+
+            slots,
+            draws,
+            slot_capacity: Self::INITIAL_SLOTS,
+            free_slots: BTreeSet::new(),
+            slot_count: 0,
+
+            layout,
+            group,
+            generation: 0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, quads: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk vertex buffer"),
+            size: quads as u64 * Self::QUAD_SIZE,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_slot_buffers(device: &wgpu::Device, slots: u32) -> (wgpu::Buffer, wgpu::Buffer) {
+        let slot_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk slot buffer"),
+            size: slots as u64 * Self::SLOT_SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let draw_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk draw buffer"),
+            size: slots as u64 * Self::DRAW_SIZE * Self::DRAWN_LAYERS.len() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        (slot_buffer, draw_buffer)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        slots: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("chunk slots bind group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: slots.as_entire_binding(),
+            }],
+        })
+    }
+
+    pub fn vertices(&self) -> &wgpu::Buffer {
+        &self.vertices
+    }
+
+    pub fn slots(&self) -> &wgpu::Buffer {
+        &self.slots
+    }
+
+    pub fn draws(&self) -> &wgpu::Buffer {
+        &self.draws
+    }
+
+    /// Offset of the first indirect draw of `layer` in [`Self::draws`].
+    pub fn draws_offset(&self, layer: RenderLayer) -> u64 {
+        debug_assert!(layer != RenderLayer::Translucent);
+        layer.index() as u64 * self.slot_capacity as u64 * Self::DRAW_SIZE
+    }
+
+    /// Slots the slot and draw buffers have room for.
+    pub fn slot_capacity(&self) -> u32 {
+        self.slot_capacity
+    }
+
+    /// One past the highest slot in use. Slots below it may be unused.
+    pub fn slot_count(&self) -> u32 {
+        self.slot_count
+    }
+
+    /// Chunks with a mesh in the buffers.
+    pub fn chunk_count(&self) -> usize {
+        self.slot_count as usize - self.free_slots.len()
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Bytes of vertex buffer allocated, used or not.
+    pub fn capacity_in_bytes(&self) -> u64 {
+        self.quads.capacity() as u64 * Self::QUAD_SIZE
+    }
+
+    /// Bytes of vertex buffer holding quads.
+    pub fn used_bytes(&self) -> u64 {
+        (self.quads.capacity() - self.quads.free_size()) as u64 * Self::QUAD_SIZE
+    }
+
+    /// Bytes of GPU memory taken by the vertex, slot and draw buffers.
+    pub fn allocated_bytes(&self) -> u64 {
+        self.vertices.size() + self.slots.size() + self.draws.size()
+    }
+
+    /// Copy a mesh into the buffers. Returns `None` for empty meshes, and
+    /// when the vertex buffer can't grow any further.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        coord: IVec3,
+        mesh: &ChunkMesh,
+    ) -> Option<ChunkAllocation> {
+        let quad_count = mesh.quad_count();
+        if quad_count == 0 {
+            return None;
+        }
+
+        let quads = match self.quads.allocate(quad_count) {
+            Some(quads) => quads,
+            None => {
+                self.grow_vertices(device, queue, quad_count)?;
+                self.quads.allocate(quad_count)?
+            }
+        };
+        queue.write_buffer(
+            &self.vertices,
+            quads.start as u64 * Self::QUAD_SIZE,
+            bytemuck::cast_slice(&mesh.vertices),
+        );
+
+        let slot = self.allocate_slot(device, queue);
+        let data = ChunkSlot {
+            origin: chunk_origin(coord).to_array(),
+            first_quad: quads.start,
+            opaque_quads: mesh.layer_quads[RenderLayer::Opaque.index()],
+            cutout_quads: mesh.layer_quads[RenderLayer::Cutout.index()],
+            translucent_quads: mesh.layer_quads[RenderLayer::Translucent.index()],
+            ..Default::default()
+        };
+        queue.write_buffer(&self.slots, slot as u64 * Self::SLOT_SIZE, bytemuck::bytes_of(&data));
+
+        Some(ChunkAllocation {
+            slot,
+            quads,
+            layer_quads: mesh.layer_quads,
+        })
+    }
+
+    /// Overwrite the vertices of some quads of an allocation, for example
+    /// after sorting them.
+    pub fn write_quads(&self, queue: &wgpu::Queue, first_quad: u32, vertices: &[ChunkVertex]) {
+        queue.write_buffer(
+            &self.vertices,
+            first_quad as u64 * Self::QUAD_SIZE,
+            bytemuck::cast_slice(vertices),
+        );
+    }
+
+    pub fn free(&mut self, queue: &wgpu::Queue, allocation: &ChunkAllocation) {
+        self.quads.free(allocation.quads.clone());
+
+        // An empty slot makes the culling pass write an empty draw
+        queue.write_buffer(
+            &self.slots,
+            allocation.slot as u64 * Self::SLOT_SIZE,
+            bytemuck::bytes_of(&ChunkSlot::default()),
+        );
+        self.free_slots.insert(allocation.slot);
+
+        while self.slot_count > 0 && self.free_slots.remove(&(self.slot_count - 1)) {
+            self.slot_count -= 1;
+        }
+    }
+
+    fn allocate_slot(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> u32 {
+        if let Some(slot) = self.free_slots.pop_first() {
+            return slot;
+        }
+
+        if self.slot_count == self.slot_capacity {
+            let capacity = self.slot_capacity * 2;
+            let (slots, draws) = Self::create_slot_buffers(device, capacity);
+            Self::copy_buffer(device, queue, &self.slots, &slots);
+
+            self.group = Self::create_bind_group(device, &self.layout, &slots);
+            self.slots = slots;
+            self.draws = draws;
+            self.slot_capacity = capacity;
+            self.generation += 1;
+        }
+
+        self.slot_count += 1;
+        self.slot_count - 1
+    }
+
+    fn grow_vertices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        needed: u32,
+    ) -> Option<()> {
+        let max_quads = (device.limits().max_buffer_size / Self::QUAD_SIZE).min(u32::MAX as u64) as u32;
+        let wanted = (self.quads.capacity() + needed).next_power_of_two();
+        let capacity = wanted.min(max_quads);
+        if capacity <= self.quads.capacity() {
+            return None;
+        }
+
+        let vertices = Self::create_vertex_buffer(device, capacity);
+        Self::copy_buffer(device, queue, &self.vertices, &vertices);
+        self.vertices = vertices;
+        self.quads.grow(capacity);
+        Some(())
+    }
+
+    /// Copy all of `from` to the start of `to`, ahead of any later writes.
+    fn copy_buffer(device: &wgpu::Device, queue: &wgpu::Queue, from: &wgpu::Buffer, to: &wgpu::Buffer) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("chunk buffer grow encoder"),
+        });
+        encoder.copy_buffer_to_buffer(from, 0, to, 0, from.size());
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_first_fit() {
+        let mut allocator = RangeAllocator::new(100);
+        assert_eq!(allocator.allocate(10), Some(0..10));
+        assert_eq!(allocator.allocate(20), Some(10..30));
+        assert_eq!(allocator.allocate(70), Some(30..100));
+        assert_eq!(allocator.allocate(1), None);
+        assert_eq!(allocator.free_size(), 0);
+    }
+
+    #[test]
+    fn freed_ranges_are_merged_and_reused() {
+        let mut allocator = RangeAllocator::new(100);
+        let a = allocator.allocate(10).unwrap();
+        let b = allocator.allocate(10).unwrap();
+        let c = allocator.allocate(10).unwrap();
+        let _d = allocator.allocate(70).unwrap();
+
+        allocator.free(a);
+        allocator.free(c);
+        // Two separate holes of 10
+        assert_eq!(allocator.allocate(15), None);
+
+        allocator.free(b);
+        assert_eq!(allocator.free_size(), 30);
+        assert_eq!(allocator.allocate(30), Some(0..30));
+    }
+
+    #[test]
+    fn growing_extends_the_last_free_range() {
+        let mut allocator = RangeAllocator::new(10);
+        allocator.allocate(4).unwrap();
+        allocator.grow(20);
+        assert_eq!(allocator.allocate(16), Some(4..20));
+
+        let mut full = RangeAllocator::new(0);
+        full.grow(8);
+        assert_eq!(full.allocate(8), Some(0..8));
+    }
+
+    #[test]
+    fn slot_layout_matches_the_shader() {
+        // vec3<i32> then four u32, rounded up to the 16 byte alignment
+        assert_eq!(size_of::<ChunkSlot>(), 32);
+        assert_eq!(ChunkBuffers::DRAW_SIZE, 20);
+    }
+}
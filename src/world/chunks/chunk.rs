@@ -1,109 +1,253 @@
-use glam::{Vec3, Mat4};
+use glam::{IVec3, UVec3, Vec3};
 
 use wgpu::util::DeviceExt;
 
+use std::sync::Arc;
+
+use super::blocks::{BlockId, ChunkBlocks};
+use super::buffers::{ChunkAllocation, ChunkBuffers};
+use super::coords::{chunk_origin, CHUNK_SIZE};
+use super::light::{ChunkLight, LightLevel};
+use super::mesher::{self, ChunkMesh, Face};
+use crate::world::registry::RenderLayer;
+
 pub struct NoData;
 pub struct GPUData {
-    v_buffer: wgpu::Buffer,
-    mm_buffer: wgpu::Buffer,
-
-    bind_group: wgpu::BindGroup,
+    /// `None` for chunks with an empty mesh.
+    allocation: Option<ChunkAllocation>,
+    /// Copy of the translucent quads, re-sorted as the camera moves.
+    translucent: Vec<ChunkVertex>,
 }
 
 pub struct WorldChunk<D> {
-    vertices: Vec<ChunkVertex>,
-    position: Vec3,
+    coord: IVec3,
+    blocks: Arc<ChunkBlocks>,
+    light: Arc<ChunkLight>,
+    /// Whether the blocks changed since they were generated or loaded, and
+    /// have to be saved.
+    dirty: bool,
     gpu_data: D,
 }
 
 impl WorldChunk<NoData> {
-    pub fn generate(position: Vec3) -> Self {
-        let vertices = vec![
-            ChunkVertex {
-                position: Vec3::new(-0.5, -0.5, 0.0),
-                color: Vec3::new(1.0, 0.0, 0.0),
-            },
-            ChunkVertex {
-                position: Vec3::new(0.5, -0.5, 0.0),
-                color: Vec3::new(0.0, 1.0, 0.0),
-            },
-            ChunkVertex {
-                position: Vec3::new(0.0, 0.5, 0.0),
-                color: Vec3::new(0.0, 0.0, 1.0),
-            },
-        ];
+    pub fn new(coord: IVec3, blocks: ChunkBlocks, light: ChunkLight) -> Self {
         Self {
-            vertices,
-            position,
+            coord,
+            blocks: Arc::new(blocks),
+            light: Arc::new(light),
+            dirty: false,
             gpu_data: NoData,
         }
     }
-    
+
+    /// Copy the mesh into the shared chunk buffers.
     pub fn upload_to_gpu(
         self,
         gfx: &crate::GfxContext,
-        chunk_bind_group_layout: &wgpu::BindGroupLayout,
+        buffers: &mut ChunkBuffers,
+        mesh: &ChunkMesh,
     ) -> WorldChunk<GPUData> {
-        let v_buffer = gfx.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&self.vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let mm_buffer = gfx.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(self.model_matrix().as_ref()),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        let allocation = buffers.upload(&gfx.device, &gfx.queue, self.coord, mesh);
+        if allocation.is_none() && !mesh.is_empty() {
+            tracing::warn!("Out of chunk vertex memory, chunk {} is not drawn", self.coord);
+        }
 
-        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("chunk bind group"),
-            layout: chunk_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: mm_buffer.as_entire_binding(),
-            }],
-        });
+        let translucent = match allocation {
+            Some(_) => mesh.layer_vertices(RenderLayer::Translucent).to_vec(),
+            None => Vec::new(),
+        };
 
         WorldChunk {
-            vertices: self.vertices,
-            position: self.position,
+            coord: self.coord,
+            blocks: self.blocks,
+            light: self.light,
+            dirty: self.dirty,
             gpu_data: GPUData {
-                v_buffer,
-                mm_buffer,
-
-                bind_group,
+                allocation,
+                translucent,
             },
         }
     }
 }
 
 impl WorldChunk<GPUData> {
-    pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
-        render_pass.set_bind_group(1, &self.gpu_data.bind_group, &[]);
+    /// Free the chunk's space in the chunk buffers, keeping its blocks.
+    pub fn unload(self, gfx: &crate::GfxContext, buffers: &mut ChunkBuffers) -> WorldChunk<NoData> {
+        if let Some(allocation) = &self.gpu_data.allocation {
+            buffers.free(&gfx.queue, allocation);
+        }
+        self.forget_gpu_data()
+    }
+
+    /// Drop the chunk's GPU data without freeing it, for when the chunk
+    /// buffers are gone already.
+    pub fn forget_gpu_data(self) -> WorldChunk<NoData> {
+        WorldChunk {
+            coord: self.coord,
+            blocks: self.blocks,
+            light: self.light,
+            dirty: self.dirty,
+            gpu_data: NoData,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gpu_data.allocation.is_none()
+    }
 
-        render_pass.set_vertex_buffer(0, self.gpu_data.v_buffer.slice(..));
-        render_pass.draw(0..self.vertices.len() as u32, 0..1);
+    pub fn vertex_count(&self) -> u32 {
+        self.gpu_data
+            .allocation
+            .as_ref()
+            .map_or(0, |allocation| allocation.quad_count() * 4)
+    }
+
+    pub fn vertex_buffer_size(&self) -> u64 {
+        self.vertex_count() as u64 * size_of::<ChunkVertex>() as u64
+    }
+
+    pub fn has_translucent(&self) -> bool {
+        !self.gpu_data.translucent.is_empty()
+    }
+
+    /// Sort the translucent quads back to front as seen from `eye`, in world
+    /// space, and upload them again.
+    pub fn sort_translucent(&mut self, queue: &wgpu::Queue, buffers: &ChunkBuffers, eye: Vec3) {
+        let Some(allocation) = &self.gpu_data.allocation else {
+            return;
+        };
+        if self.gpu_data.translucent.is_empty() {
+            return;
+        }
+
+        let local_eye = eye - chunk_origin(self.coord).as_vec3();
+        mesher::sort_back_to_front(&mut self.gpu_data.translucent, local_eye);
+        let first_quad =
+            allocation.quads.start + allocation.layer_range(RenderLayer::Translucent).start;
+        buffers.write_quads(queue, first_quad, &self.gpu_data.translucent);
+    }
+
+    /// Draw one layer of the chunk on its own. Expects the chunk buffers and
+    /// the shared [`QuadIndexBuffer`] to be bound already.
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass, layer: RenderLayer) {
+        let Some(allocation) = &self.gpu_data.allocation else {
+            return;
+        };
+
+        let quads = allocation.layer_range(layer);
+        if quads.is_empty() {
+            return;
+        }
+        render_pass.draw_indexed(
+            quads.start * 6..quads.end * 6,
+            allocation.base_vertex(),
+            allocation.slot..allocation.slot + 1,
+        );
     }
 }
 
 impl<D> WorldChunk<D> {
-    fn model_matrix(&self) -> Mat4 {
-        Mat4::from_translation(self.position)
+    pub fn coord(&self) -> IVec3 {
+        self.coord
+    }
+
+    pub fn blocks(&self) -> &ChunkBlocks {
+        &self.blocks
+    }
+
+    /// Shared handle to the blocks, for meshing on another thread.
+    pub fn shared_blocks(&self) -> Arc<ChunkBlocks> {
+        self.blocks.clone()
+    }
+
+    pub fn get_block(&self, local: UVec3) -> BlockId {
+        self.blocks.get(local)
+    }
+
+    pub fn set_block(&mut self, local: UVec3, block: BlockId) {
+        Arc::make_mut(&mut self.blocks).set(local, block);
+        self.dirty = true;
+    }
+
+    pub fn light(&self) -> &ChunkLight {
+        &self.light
+    }
+
+    /// Shared handle to the light, for meshing on another thread.
+    pub fn shared_light(&self) -> Arc<ChunkLight> {
+        self.light.clone()
+    }
+
+    /// Light doesn't need saving, so this leaves the chunk clean.
+    pub fn set_light(&mut self, local: UVec3, level: LightLevel) {
+        Arc::make_mut(&mut self.light).set(local, level);
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+    }
+
+    /// World space bounds of the chunk, as minimum and maximum corners.
+    pub fn aabb(&self) -> (Vec3, Vec3) {
+        let min = chunk_origin(self.coord).as_vec3();
+        (min, min + Vec3::splat(CHUNK_SIZE as f32))
     }
 }
 
+/// A chunk vertex packed into two words:
+///
+/// - `data[0]`: x, y, z in chunk (6 bits each), face normal index (3 bits),
+///   ambient occlusion (2 bits)
+/// - `data[1]`: texture layer (16 bits), light (8 bits)
+///
+/// `chunk.wgsl` unpacks the same layout.
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ChunkVertex {
-    position: Vec3,
-    color: Vec3,
+    data: [u32; 2],
 }
 
 impl ChunkVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
-        0 => Float32x3,
-        1 => Float32x3,
+    pub fn new(position: UVec3, face: Face, ao: u32, layer: u32, light: u32) -> Self {
+        debug_assert!(position.max_element() <= CHUNK_SIZE as u32);
+        debug_assert!(ao < 4 && layer <= 0xffff && light <= 0xff);
+
+        let position = position.x | position.y << 6 | position.z << 12;
+        Self {
+            data: [
+                position | (face.index() as u32) << 18 | ao << 21,
+                layer | light << 16,
+            ],
+        }
+    }
+
+    pub fn position(&self) -> UVec3 {
+        let p = self.data[0];
+        UVec3::new(p & 0x3f, (p >> 6) & 0x3f, (p >> 12) & 0x3f)
+    }
+
+    pub fn face(&self) -> Face {
+        Face::ALL[((self.data[0] >> 18) & 0x7) as usize]
+    }
+
+    pub fn ao(&self) -> u32 {
+        (self.data[0] >> 21) & 0x3
+    }
+
+    pub fn layer(&self) -> u32 {
+        self.data[1] & 0xffff
+    }
+
+    pub fn light(&self) -> u32 {
+        (self.data[1] >> 16) & 0xff
+    }
+
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![
+        0 => Uint32x2,
     ];
 
     pub fn layout() -> wgpu::VertexBufferLayout<'static> {
@@ -114,3 +258,84 @@ impl ChunkVertex {
         }
     }
 }
+
+/// Index buffer shared by every chunk. Chunk meshes are lists of quads with
+/// four vertices each, so the indices are the same for all of them; the
+/// buffer only has to be as long as the largest mesh.
+pub struct QuadIndexBuffer {
+    buffer: wgpu::Buffer,
+    quad_capacity: u32,
+}
+
+impl QuadIndexBuffer {
+    pub const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+
+    const INITIAL_QUADS: u32 = 1 << 14;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self::with_capacity(device, Self::INITIAL_QUADS)
+    }
+
+    fn with_capacity(device: &wgpu::Device, quad_capacity: u32) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("chunk quad index buffer"),
+            contents: bytemuck::cast_slice(&Self::indices(quad_capacity)),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            buffer,
+            quad_capacity,
+        }
+    }
+
+    pub fn indices(quads: u32) -> Vec<u32> {
+        (0..quads)
+            .flat_map(|q| [0, 1, 2, 0, 2, 3].map(|i| q * 4 + i))
+            .collect()
+    }
+
+    /// Grow the buffer so it can index a mesh of `quads` quads.
+    pub fn reserve(&mut self, device: &wgpu::Device, quads: u32) {
+        if quads > self.quad_capacity {
+            *self = Self::with_capacity(device, quads.next_power_of_two());
+        }
+    }
+
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_packing_round_trip() {
+        for face in Face::ALL {
+            let v = ChunkVertex::new(UVec3::new(32, 0, 17), face, 3, 0xffff, 0xa5);
+            assert_eq!(v.position(), UVec3::new(32, 0, 17));
+            assert_eq!(v.face(), face);
+            assert_eq!(v.ao(), 3);
+            assert_eq!(v.layer(), 0xffff);
+            assert_eq!(v.light(), 0xa5);
+        }
+    }
+
+    #[test]
+    fn packed_quads_use_at_least_4x_less_memory() {
+        // Previously: six unindexed vertices of two Float32x3 per face.
+        let old_face_bytes = 6 * 2 * size_of::<[f32; 3]>();
+        let new_face_bytes = 4 * size_of::<ChunkVertex>();
+        assert!(new_face_bytes * 4 <= old_face_bytes);
+    }
+
+    #[test]
+    fn quad_indices() {
+        assert_eq!(
+            QuadIndexBuffer::indices(2),
+            vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]
+        );
+    }
+}
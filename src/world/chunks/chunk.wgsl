@@ -1,33 +1,131 @@
 
-
 struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
+    @location(0) data: vec2<u32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) @interpolate(flat) layer: u32,
+    @location(2) shade: f32,
 }
 
 @group(0) @binding(0) var<uniform> view_matrix: mat4x4<f32>;
 @group(0) @binding(1) var<uniform> projection_matrix: mat4x4<f32>;
 
-@group(1) @binding(0) var<uniform> model_matrix: mat4x4<f32>;
+// Must match `ChunkSlot` in buffers.rs
+struct ChunkSlot {
+    origin: vec3<i32>,
+    first_quad: u32,
+    opaque_quads: u32,
+    cutout_quads: u32,
+    translucent_quads: u32,
+}
+
+// Indexed by instance index, chunks are drawn with their slot as instance
+@group(1) @binding(0) var<storage, read> chunk_slots: array<ChunkSlot>;
+
+@group(2) @binding(0) var block_textures: texture_2d_array<f32>;
+@group(2) @binding(1) var block_sampler: sampler;
+
+// Must match the layout of `ChunkVertex` in chunk.rs
+struct Vertex {
+    position: vec3<f32>,
+    face: u32,
+    ao: u32,
+    layer: u32,
+    light: u32,
+}
+
+fn unpack_vertex(data: vec2<u32>) -> Vertex {
+    var v: Vertex;
+    v.position = vec3<f32>(
+        f32(data.x & 0x3fu),
+        f32((data.x >> 6u) & 0x3fu),
+        f32((data.x >> 12u) & 0x3fu),
+    );
+    v.face = (data.x >> 18u) & 0x7u;
+    v.ao = (data.x >> 21u) & 0x3u;
+    v.layer = data.y & 0xffffu;
+    v.light = (data.y >> 16u) & 0xffu;
+    return v;
+}
+
+// Indexed by `Face`: +X, -X, +Y, -Y, +Z, -Z
+fn face_shade(face: u32) -> f32 {
+    var shades = array<f32, 6>(0.8, 0.8, 1.0, 0.5, 0.65, 0.65);
+    return shades[face];
+}
+
+// Indexed by ambient occlusion, 0 for the darkest corners to 3 for corners
+// nothing is next to
+fn ao_brightness(ao: u32) -> f32 {
+    var brightness = array<f32, 4>(0.45, 0.65, 0.82, 1.0);
+    return brightness[ao];
+}
+
+// Light levels are 0 to 15, sunlight in the high four bits and block light in
+// the low four. Every level is 80% as bright as the one above it.
+fn light_brightness(light: u32) -> f32 {
+    let sun = f32(light >> 4u);
+    let block = f32(light & 0xfu);
+    return pow(0.8, 15.0 - max(sun, block));
+}
+
+// Texture coordinates from the position on the face. They are not wrapped to
+// 0..1, so greedy quads repeat the texture once per block. Side faces keep
+// the texture upright and unmirrored when seen from outside.
+fn face_uv(position: vec3<f32>, face: u32) -> vec2<f32> {
+    switch face {
+        case 0u: { return vec2<f32>(position.z, -position.y); }
+        case 1u: { return vec2<f32>(-position.z, -position.y); }
+        case 4u: { return vec2<f32>(-position.x, -position.y); }
+        case 5u: { return vec2<f32>(position.x, -position.y); }
+        default: { return position.xz; }
+    }
+}
 
 @vertex
 fn vs_main(
     model: VertexInput,
+    @builtin(instance_index) slot: u32,
 ) -> VertexOutput {
+    let v = unpack_vertex(model.data);
+    let world_position = vec3<f32>(chunk_slots[slot].origin) + v.position;
+
     var out: VertexOutput;
-    out.clip_position = projection_matrix * view_matrix * model_matrix * vec4<f32>(model.position, 1.0);
-    out.color = model.color;
+    out.clip_position = projection_matrix * view_matrix * vec4<f32>(world_position, 1.0);
+    out.uv = face_uv(v.position, v.face);
+    out.layer = v.layer;
+    out.shade = face_shade(v.face) * light_brightness(v.light) * ao_brightness(v.ao);
     return out;
 }
 
 @fragment
-fn fs_main(
+fn fs_opaque(
+    input: VertexOutput,
+) -> @location(0) vec4<f32> {
+    let color = textureSample(block_textures, block_sampler, input.uv, input.layer);
+    return vec4<f32>(color.rgb * input.shade, 1.0);
+}
+
+// Alpha tested, for blocks like leaves with fully transparent holes
+@fragment
+fn fs_cutout(
+    input: VertexOutput,
+) -> @location(0) vec4<f32> {
+    let color = textureSample(block_textures, block_sampler, input.uv, input.layer);
+    if color.a < 0.5 {
+        discard;
+    }
+    return vec4<f32>(color.rgb * input.shade, 1.0);
+}
+
+// Alpha blended, drawn back to front after everything else
+@fragment
+fn fs_translucent(
     input: VertexOutput,
 ) -> @location(0) vec4<f32> {
-    return vec4<f32>(input.color, 1.0);
+    let color = textureSample(block_textures, block_sampler, input.uv, input.layer);
+    return vec4<f32>(color.rgb * input.shade, color.a);
 }
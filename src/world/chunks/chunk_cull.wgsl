@@ -0,0 +1,71 @@
+// Must match `ChunkSlot` in buffers.rs
+struct ChunkSlot {
+    origin: vec3<i32>,
+    first_quad: u32,
+    opaque_quads: u32,
+    cutout_quads: u32,
+    translucent_quads: u32,
+}
+
+// Same layout as `wgpu::util::DrawIndexedIndirectArgs`
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// Must match `CullParams` in culling.rs
+struct CullParams {
+    // Left, right, bottom, top, near, far; normals facing inwards
+    planes: array<vec4<f32>, 6>,
+    slot_count: u32,
+    // Offset of the cutout draws in `draws`
+    slot_capacity: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: CullParams;
+@group(0) @binding(1) var<storage, read> slots: array<ChunkSlot>;
+@group(0) @binding(2) var<storage, read_write> draws: array<DrawIndexedIndirect>;
+@group(0) @binding(3) var<storage, read_write> drawn: atomic<u32>;
+
+const CHUNK_SIZE: f32 = 32.0;
+
+// Same test as `Frustum::intersects_aabb`
+fn intersects_frustum(lo: vec3<f32>, hi: vec3<f32>) -> bool {
+    for (var i = 0u; i < 6u; i++) {
+        let plane = params.planes[i];
+        let corner = select(lo, hi, plane.xyz >= vec3<f32>(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.slot_count {
+        return;
+    }
+
+    let slot = slots[i];
+    let lo = vec3<f32>(slot.origin);
+
+    // Unused and culled slots get draws with no instances. Translucent quads
+    // are drawn from the CPU, but still count towards the drawn chunks.
+    var opaque = DrawIndexedIndirect(0u, 0u, 0u, 0, i);
+    var cutout = DrawIndexedIndirect(0u, 0u, 0u, 0, i);
+    let quads = slot.opaque_quads + slot.cutout_quads + slot.translucent_quads;
+    if quads > 0u && intersects_frustum(lo, lo + vec3<f32>(CHUNK_SIZE)) {
+        let base_vertex = i32(slot.first_quad * 4u);
+        opaque = DrawIndexedIndirect(slot.opaque_quads * 6u, 1u, 0u, base_vertex, i);
+        let first_cutout = slot.opaque_quads * 6u;
+        cutout = DrawIndexedIndirect(slot.cutout_quads * 6u, 1u, first_cutout, base_vertex, i);
+        atomicAdd(&drawn, 1u);
+    }
+    draws[i] = opaque;
+    draws[params.slot_capacity + i] = cutout;
+}
@@ -0,0 +1,103 @@
+use glam::{IVec3, UVec3};
+
+/// Side length of a chunk, in blocks.
+pub const CHUNK_SIZE: usize = 32;
+pub const CHUNK_SIZE_I32: i32 = CHUNK_SIZE as i32;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// Chunk coordinate containing the given world block coordinate.
+pub fn world_to_chunk(world: IVec3) -> IVec3 {
+    world.div_euclid(IVec3::splat(CHUNK_SIZE_I32))
+}
+
+/// Local coordinate of a world block coordinate inside its chunk.
+pub fn world_to_local(world: IVec3) -> UVec3 {
+    world.rem_euclid(IVec3::splat(CHUNK_SIZE_I32)).as_uvec3()
+}
+
+/// World block coordinate of the chunk's (0, 0, 0) block.
+pub fn chunk_origin(chunk: IVec3) -> IVec3 {
+    chunk * CHUNK_SIZE_I32
+}
+
+pub fn local_to_world(chunk: IVec3, local: UVec3) -> IVec3 {
+    chunk_origin(chunk) + local.as_ivec3()
+}
+
+/// Index into a chunk's block array. X varies fastest, then Z, then Y.
+pub fn local_index(local: UVec3) -> usize {
+    debug_assert!(is_local(local.as_ivec3()), "local coordinate out of range: {local}");
+    local.x as usize + (local.z as usize + local.y as usize * CHUNK_SIZE) * CHUNK_SIZE
+}
+
+pub fn index_to_local(index: usize) -> UVec3 {
+    debug_assert!(index < CHUNK_VOLUME);
+    UVec3::new(
+        (index % CHUNK_SIZE) as u32,
+        (index / (CHUNK_SIZE * CHUNK_SIZE)) as u32,
+        ((index / CHUNK_SIZE) % CHUNK_SIZE) as u32,
+    )
+}
+
+/// Whether a coordinate relative to a chunk's origin lies inside that chunk.
+pub fn is_local(pos: IVec3) -> bool {
+    pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(CHUNK_SIZE_I32)).all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_to_chunk_rounds_towards_negative_infinity() {
+        assert_eq!(world_to_chunk(IVec3::new(0, 0, 0)), IVec3::ZERO);
+        assert_eq!(world_to_chunk(IVec3::new(31, 31, 31)), IVec3::ZERO);
+        assert_eq!(world_to_chunk(IVec3::new(32, -1, -32)), IVec3::new(1, -1, -1));
+        assert_eq!(world_to_chunk(IVec3::new(-33, 64, 0)), IVec3::new(-2, 2, 0));
+    }
+
+    #[test]
+    fn world_to_local_wraps_negative_coordinates() {
+        assert_eq!(world_to_local(IVec3::new(5, 40, 0)), UVec3::new(5, 8, 0));
+        assert_eq!(world_to_local(IVec3::new(-1, -32, -33)), UVec3::new(31, 0, 31));
+    }
+
+    #[test]
+    fn world_local_round_trip() {
+        for world in [
+            IVec3::new(0, 0, 0),
+            IVec3::new(-1, 17, 95),
+            IVec3::new(-64, -65, 1000),
+            IVec3::new(31, 32, -31),
+        ] {
+            let chunk = world_to_chunk(world);
+            let local = world_to_local(world);
+            assert_eq!(local_to_world(chunk, local), world);
+        }
+    }
+
+    #[test]
+    fn local_index_is_a_bijection() {
+        let mut seen = vec![false; CHUNK_VOLUME];
+        for y in 0..CHUNK_SIZE as u32 {
+            for z in 0..CHUNK_SIZE as u32 {
+                for x in 0..CHUNK_SIZE as u32 {
+                    let local = UVec3::new(x, y, z);
+                    let index = local_index(local);
+                    assert!(!seen[index]);
+                    seen[index] = true;
+                    assert_eq!(index_to_local(index), local);
+                }
+            }
+        }
+        assert!(seen.iter().all(|s| *s));
+    }
+
+    #[test]
+    fn is_local_bounds() {
+        assert!(is_local(IVec3::ZERO));
+        assert!(is_local(IVec3::splat(31)));
+        assert!(!is_local(IVec3::new(-1, 0, 0)));
+        assert!(!is_local(IVec3::new(0, 32, 0)));
+    }
+}
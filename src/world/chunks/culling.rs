@@ -0,0 +1,254 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use super::buffers::ChunkBuffers;
+use crate::render::frustum::Frustum;
+
+/// Must match `CullParams` in chunk_cull.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    planes: [[f32; 4]; 6],
+    slot_count: u32,
+    slot_capacity: u32,
+    _padding: [u32; 2],
+}
+
+impl CullParams {
+    fn new(frustum: &Frustum, slot_count: u32, slot_capacity: u32) -> Self {
+        Self {
+            planes: frustum
+                .planes
+                .map(|plane| plane.normal.extend(plane.distance).to_array()),
+            slot_count,
+            slot_capacity,
+            ..Default::default()
+        }
+    }
+}
+
+// States of the drawn counter readback
+const IDLE: u8 = 0;
+const COPIED: u8 = 1;
+const MAPPING: u8 = 2;
+const MAPPED: u8 = 3;
+
+/// Compute pass testing every chunk slot against the view frustum and
+/// writing indirect draws of its opaque and cutout quads into
+/// [`ChunkBuffers::draws`].
+///
+/// The pass also counts the chunks it lets through. The count is read back
+/// asynchronously, so it lags the frame being drawn by a frame or two.
+pub struct GpuCulling {
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    /// Bind group and the [`ChunkBuffers::generation`] it was made for.
+    group: Option<(wgpu::BindGroup, u32)>,
+
+    params: wgpu::Buffer,
+    slot_count: u32,
+
+    drawn: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    readback_state: Arc<AtomicU8>,
+    last_drawn: u32,
+}
+
+impl GpuCulling {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    /// Features needed to draw chunks from the culling pass output.
+    pub const FEATURES: wgpu::Features =
+        wgpu::Features::MULTI_DRAW_INDIRECT.union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
+    pub fn is_supported(device: &wgpu::Device) -> bool {
+        device.features().contains(Self::FEATURES)
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("./chunk_cull.wgsl"));
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("chunk culling bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("chunk culling pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("chunk culling pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cull"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk culling params"),
+            size: size_of::<CullParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let drawn = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk culling drawn counter"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk culling drawn readback"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            layout,
+            group: None,
+
+            params,
+            slot_count: 0,
+
+            drawn,
+            readback,
+            readback_state: Arc::new(AtomicU8::new(IDLE)),
+            last_drawn: 0,
+        }
+    }
+
+    /// Chunks drawn in a recent frame.
+    pub fn drawn(&self) -> u32 {
+        self.last_drawn
+    }
+
+    /// Upload the frustum, and pick up the drawn count if it's been read back.
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, buffers: &ChunkBuffers, frustum: &Frustum) {
+        self.slot_count = buffers.slot_count();
+        queue.write_buffer(
+            &self.params,
+            0,
+            bytemuck::bytes_of(&CullParams::new(frustum, self.slot_count, buffers.slot_capacity())),
+        );
+
+        if !matches!(&self.group, Some((_, generation)) if *generation == buffers.generation()) {
+            let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("chunk culling bind group"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.params.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: buffers.slots().as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffers.draws().as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.drawn.as_entire_binding(),
+                    },
+                ],
+            });
+            self.group = Some((group, buffers.generation()));
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        if self.readback_state.load(Ordering::Acquire) == MAPPED {
+            let data = self.readback.slice(..).get_mapped_range();
+            self.last_drawn = *bytemuck::from_bytes::<u32>(&data);
+            drop(data);
+            self.readback.unmap();
+            self.readback_state.store(IDLE, Ordering::Release);
+        }
+    }
+
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some((group, _)) = &self.group else {
+            return;
+        };
+
+        encoder.clear_buffer(&self.drawn, 0, None);
+
+        if self.slot_count > 0 {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("chunk culling pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, group, &[]);
+            pass.dispatch_workgroups(self.slot_count.div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+        }
+
+        // Only one readback in flight at a time
+        if self.readback_state.load(Ordering::Acquire) == IDLE {
+            encoder.copy_buffer_to_buffer(&self.drawn, 0, &self.readback, 0, self.drawn.size());
+            self.readback_state.store(COPIED, Ordering::Release);
+        }
+    }
+
+    /// Start mapping the drawn count copied by [`Self::encode`]. Must be
+    /// called after the encoder was submitted.
+    pub fn submitted(&self) {
+        if self.readback_state.load(Ordering::Acquire) != COPIED {
+            return;
+        }
+        self.readback_state.store(MAPPING, Ordering::Release);
+
+        let state = self.readback_state.clone();
+        self.readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                state.store(if result.is_ok() { MAPPED } else { IDLE }, Ordering::Release);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_layout_matches_the_shader() {
+        // array<vec4<f32>, 6> then two u32, rounded up to 16 bytes
+        assert_eq!(size_of::<CullParams>(), 112);
+    }
+}
@@ -0,0 +1,606 @@
+use glam::{IVec3, UVec3};
+
+use std::collections::VecDeque;
+
+use super::blocks::{BlockId, ChunkBlocks};
+use super::coords::{index_to_local, is_local, local_index, CHUNK_SIZE, CHUNK_SIZE_I32, CHUNK_VOLUME};
+use super::mesher::Face;
+use crate::world::registry::BlockRegistry;
+
+/// Brightest light level, of the sky and of the brightest lamps.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Sunlight and block light of a single block, four bits each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LightLevel(u8);
+
+impl LightLevel {
+    /// Open sky, with no lamps around.
+    pub const SKY: LightLevel = LightLevel(MAX_LIGHT << 4);
+
+    pub fn new(sun: u8, block: u8) -> Self {
+        debug_assert!(sun <= MAX_LIGHT && block <= MAX_LIGHT);
+        Self(sun << 4 | block)
+    }
+
+    pub fn sun(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    pub fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sun => self.sun(),
+            LightChannel::Block => self.block(),
+        }
+    }
+
+    pub fn with(self, channel: LightChannel, level: u8) -> Self {
+        match channel {
+            LightChannel::Sun => Self::new(level, self.block()),
+            LightChannel::Block => Self::new(self.sun(), level),
+        }
+    }
+
+    /// Both levels packed as `sun << 4 | block`, like in chunk vertices.
+    pub fn packed(self) -> u8 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightChannel {
+    /// Light from the sky. Travels straight down without getting dimmer.
+    Sun,
+    /// Light from blocks that glow.
+    Block,
+}
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 2] = [LightChannel::Sun, LightChannel::Block];
+}
+
+/// Light level of every block in a chunk, laid out like [`ChunkBlocks`].
+#[derive(Clone)]
+pub struct ChunkLight {
+    levels: Box<[LightLevel]>,
+}
+
+impl Default for ChunkLight {
+    fn default() -> Self {
+        Self {
+            levels: vec![LightLevel::default(); CHUNK_VOLUME].into_boxed_slice(),
+        }
+    }
+}
+
+impl ChunkLight {
+    pub fn get(&self, local: UVec3) -> LightLevel {
+        self.levels[local_index(local)]
+    }
+
+    pub fn set(&mut self, local: UVec3, level: LightLevel) {
+        self.levels[local_index(local)] = level;
+    }
+
+    /// Light a chunk on its own, as if it was open to the sky above and
+    /// surrounded by darkness otherwise. Light from the neighbours is added
+    /// by [`add_chunk`] once the chunk is part of the world.
+    pub fn compute(blocks: &ChunkBlocks, registry: &BlockRegistry) -> Self {
+        let mut world = LoneChunk {
+            blocks,
+            light: ChunkLight::default(),
+        };
+        let mut sun = LightUpdate::new(LightChannel::Sun);
+        let mut block = LightUpdate::new(LightChannel::Block);
+
+        for index in 0..CHUNK_VOLUME {
+            let local = index_to_local(index);
+            let pos = local.as_ivec3();
+            let id = blocks.get(local);
+
+            if local.y == CHUNK_SIZE as u32 - 1 && !registry.is_opaque(id) {
+                sun.set_source(&mut world, pos, MAX_LIGHT);
+            }
+            let emission = registry.get(id).light_emission;
+            if emission > 0 {
+                block.set_source(&mut world, pos, emission);
+            }
+        }
+
+        sun.run(&mut world, registry);
+        block.run(&mut world, registry);
+        world.light
+    }
+}
+
+/// Blocks and light of the loaded part of the world, in world coordinates.
+pub trait LightStorage {
+    /// `None` outside of loaded chunks.
+    fn block(&self, pos: IVec3) -> Option<BlockId>;
+    /// `None` outside of loaded chunks.
+    fn light(&self, pos: IVec3) -> Option<LightLevel>;
+    /// Only called for positions in loaded chunks.
+    fn set_light(&mut self, pos: IVec3, level: LightLevel);
+}
+
+/// A single chunk at the origin with nothing around it.
+struct LoneChunk<'a> {
+    blocks: &'a ChunkBlocks,
+    light: ChunkLight,
+}
+
+impl LightStorage for LoneChunk<'_> {
+    fn block(&self, pos: IVec3) -> Option<BlockId> {
+        is_local(pos).then(|| self.blocks.get(pos.as_uvec3()))
+    }
+
+    fn light(&self, pos: IVec3) -> Option<LightLevel> {
+        is_local(pos).then(|| self.light.get(pos.as_uvec3()))
+    }
+
+    fn set_light(&mut self, pos: IVec3, level: LightLevel) {
+        self.light.set(pos.as_uvec3(), level);
+    }
+}
+
+/// Flood fill of one light channel. Light being removed is cleared first,
+/// then light is spread again from everything bordering the cleared area,
+/// and from new sources.
+struct LightUpdate {
+    channel: LightChannel,
+    /// Blocks to spread light from.
+    spread: VecDeque<IVec3>,
+    /// Blocks that were cleared, with the level they had.
+    removed: VecDeque<(IVec3, u8)>,
+}
+
+impl LightUpdate {
+    fn new(channel: LightChannel) -> Self {
+        Self {
+            channel,
+            spread: VecDeque::new(),
+            removed: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, world: &impl LightStorage, pos: IVec3) -> Option<u8> {
+        world.light(pos).map(|level| level.get(self.channel))
+    }
+
+    fn set(&self, world: &mut impl LightStorage, pos: IVec3, level: u8) {
+        if let Some(current) = world.light(pos) {
+            world.set_light(pos, current.with(self.channel, level));
+        }
+    }
+
+    /// Light `pos` with at least `level` and spread it from there.
+    fn set_source(&mut self, world: &mut impl LightStorage, pos: IVec3, level: u8) {
+        if self.get(world, pos).is_some_and(|current| current < level) {
+            self.set(world, pos, level);
+            self.spread.push_back(pos);
+        }
+    }
+
+    /// Spread the light `pos` has again, for example because a neighbour
+    /// became transparent.
+    fn respread(&mut self, pos: IVec3) {
+        self.spread.push_back(pos);
+    }
+
+    /// Clear the light at `pos` and everything lit through it.
+    fn remove(&mut self, world: &mut impl LightStorage, pos: IVec3) {
+        if let Some(level) = self.get(world, pos).filter(|level| *level > 0) {
+            self.set(world, pos, 0);
+            self.removed.push_back((pos, level));
+        }
+    }
+
+    /// Light `level` passing from a block to its neighbour on `face`.
+    fn spread_level(&self, level: u8, face: Face) -> u8 {
+        if self.channel == LightChannel::Sun && face == Face::NegY && level == MAX_LIGHT {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+
+    fn run(&mut self, world: &mut impl LightStorage, registry: &BlockRegistry) {
+        while let Some((pos, level)) = self.removed.pop_front() {
+            for face in Face::ALL {
+                let neighbour = pos + face.normal();
+                let Some(neighbour_level) = self.get(world, neighbour) else {
+                    continue;
+                };
+                if neighbour_level == 0 {
+                    continue;
+                }
+
+                if neighbour_level <= self.spread_level(level, face) {
+                    // Lit through `pos`
+                    self.set(world, neighbour, 0);
+                    self.removed.push_back((neighbour, neighbour_level));
+                    self.relight_source(world, registry, neighbour);
+                } else {
+                    // Lit some other way, so it can fill the gap again
+                    self.spread.push_back(neighbour);
+                }
+            }
+        }
+
+        while let Some(pos) = self.spread.pop_front() {
+            let Some(level) = self.get(world, pos) else {
+                continue;
+            };
+            for face in Face::ALL {
+                let neighbour = pos + face.normal();
+                let transparent = world.block(neighbour).is_some_and(|id| !registry.is_opaque(id));
+                let spread = self.spread_level(level, face);
+                if transparent && self.get(world, neighbour).is_some_and(|current| current < spread) {
+                    self.set(world, neighbour, spread);
+                    self.spread.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Glowing blocks keep their own light when light around them goes out.
+    fn relight_source(&mut self, world: &mut impl LightStorage, registry: &BlockRegistry, pos: IVec3) {
+        if self.channel != LightChannel::Block {
+            return;
+        }
+        let emission = world.block(pos).map_or(0, |id| registry.get(id).light_emission);
+        if emission > 0 {
+            self.set(world, pos, emission);
+            self.spread.push_back(pos);
+        }
+    }
+}
+
+/// Update the light around `pos` after its block changed. The block must
+/// already be changed in `world`.
+pub fn block_changed(world: &mut impl LightStorage, registry: &BlockRegistry, pos: IVec3) {
+    let Some(id) = world.block(pos) else {
+        return;
+    };
+
+    for channel in LightChannel::ALL {
+        let mut update = LightUpdate::new(channel);
+        update.remove(world, pos);
+        update.run(world, registry);
+
+        match channel {
+            LightChannel::Sun if !registry.is_opaque(id) => {
+                // Unloaded chunks above are assumed to be open sky
+                if world.light(pos + IVec3::Y).is_none() {
+                    update.set_source(world, pos, MAX_LIGHT);
+                }
+                for face in Face::ALL {
+                    update.respread(pos + face.normal());
+                }
+            }
+            LightChannel::Block => {
+                update.set_source(world, pos, registry.get(id).light_emission);
+                if !registry.is_opaque(id) {
+                    for face in Face::ALL {
+                        update.respread(pos + face.normal());
+                    }
+                }
+            }
+            LightChannel::Sun => (),
+        }
+        update.run(world, registry);
+    }
+}
+
+/// Connect the light of a chunk that was just added to the world with its
+/// neighbours'. The chunk's own light must come from [`ChunkLight::compute`].
+pub fn add_chunk(world: &mut impl LightStorage, registry: &BlockRegistry, coord: IVec3) {
+    let origin = coord * CHUNK_SIZE_I32;
+    let last = CHUNK_SIZE_I32 - 1;
+    let sun_at = |world: &mut _, pos| LightStorage::light(world, pos).map(LightLevel::sun);
+
+    // The chunk was lit as if open to the sky, and the chunk below as if
+    // this one was. Columns where that isn't true any more go dark.
+    let mut sun = LightUpdate::new(LightChannel::Sun);
+    let mut lost_sky = vec![];
+    for z in 0..CHUNK_SIZE_I32 {
+        for x in 0..CHUNK_SIZE_I32 {
+            for y in [last, -1] {
+                let pos = origin + IVec3::new(x, y, z);
+                let sky = sun_at(world, pos) == Some(MAX_LIGHT);
+                let above = sun_at(world, pos + IVec3::Y);
+                if sky && above.is_some_and(|above| above != MAX_LIGHT) {
+                    lost_sky.push(pos);
+                }
+            }
+        }
+    }
+    for pos in lost_sky {
+        sun.remove(world, pos);
+    }
+    sun.run(world, registry);
+
+    // Light crossing the borders, both ways
+    let mut block = LightUpdate::new(LightChannel::Block);
+    for face in Face::ALL {
+        let axis = face.axis();
+        let (u_axis, v_axis) = face.tangent_axes();
+        for v in 0..CHUNK_SIZE_I32 {
+            for u in 0..CHUNK_SIZE_I32 {
+                let mut inside = IVec3::ZERO;
+                inside[axis] = if face.is_positive() { last } else { 0 };
+                inside[u_axis] = u;
+                inside[v_axis] = v;
+                let inside = origin + inside;
+                let outside = inside + face.normal();
+                if world.light(outside).is_none() {
+                    continue;
+                }
+
+                for pos in [inside, outside] {
+                    sun.respread(pos);
+                    block.respread(pos);
+                }
+            }
+        }
+    }
+    sun.run(world, registry);
+    block.run(world, registry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::world::chunks::blocks::AIR;
+    use crate::world::chunks::coords::{world_to_chunk, world_to_local};
+
+    /// Loaded chunks, each with its own light.
+    #[derive(Default)]
+    struct TestWorld {
+        chunks: HashMap<IVec3, (ChunkBlocks, ChunkLight)>,
+    }
+
+    impl TestWorld {
+        fn add_chunk(&mut self, coord: IVec3, blocks: ChunkBlocks, registry: &BlockRegistry) {
+            let light = ChunkLight::compute(&blocks, registry);
+            self.chunks.insert(coord, (blocks, light));
+            add_chunk(self, registry, coord);
+        }
+
+        fn set_block(&mut self, pos: IVec3, id: BlockId, registry: &BlockRegistry) {
+            let (blocks, _) = self.chunks.get_mut(&world_to_chunk(pos)).unwrap();
+            blocks.set(world_to_local(pos), id);
+            block_changed(self, registry, pos);
+        }
+
+        fn at(&self, pos: IVec3) -> LightLevel {
+            self.light(pos).unwrap()
+        }
+
+        /// Light of every loaded block, to compare worlds built differently.
+        fn snapshot(&self) -> HashMap<IVec3, Vec<LightLevel>> {
+            self.chunks
+                .iter()
+                .map(|(coord, (_, light))| (*coord, light.levels.to_vec()))
+                .collect()
+        }
+    }
+
+    impl LightStorage for TestWorld {
+        fn block(&self, pos: IVec3) -> Option<BlockId> {
+            let (blocks, _) = self.chunks.get(&world_to_chunk(pos))?;
+            Some(blocks.get(world_to_local(pos)))
+        }
+
+        fn light(&self, pos: IVec3) -> Option<LightLevel> {
+            let (_, light) = self.chunks.get(&world_to_chunk(pos))?;
+            Some(light.get(world_to_local(pos)))
+        }
+
+        fn set_light(&mut self, pos: IVec3, level: LightLevel) {
+            let (_, light) = self.chunks.get_mut(&world_to_chunk(pos)).unwrap();
+            light.set(world_to_local(pos), level);
+        }
+    }
+
+    fn stone(registry: &BlockRegistry) -> BlockId {
+        registry.id("stone").unwrap()
+    }
+
+    /// An emissive block from the built-in registry.
+    fn lamp(registry: &BlockRegistry) -> (BlockId, u8) {
+        registry
+            .iter()
+            .find(|block| block.light_emission > 0)
+            .map(|block| (block.id, block.light_emission))
+            .expect("no glowing block in the built-in registry")
+    }
+
+    /// A chunk with a stone roof on its top layer.
+    fn roofed(registry: &BlockRegistry) -> ChunkBlocks {
+        let mut blocks = ChunkBlocks::default();
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                blocks.set(UVec3::new(x, 31, z), stone(registry));
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn packs_both_channels() {
+        let level = LightLevel::new(12, 5);
+        assert_eq!((level.sun(), level.block()), (12, 5));
+        assert_eq!(level.with(LightChannel::Block, 9), LightLevel::new(12, 9));
+        assert_eq!(level.packed(), 12 << 4 | 5);
+        assert_eq!(LightLevel::SKY.sun(), MAX_LIGHT);
+    }
+
+    #[test]
+    fn sunlight_falls_straight_down_and_spreads_under_roofs() {
+        let registry = BlockRegistry::builtin();
+        let mut blocks = ChunkBlocks::default();
+        // A 5x5 roof at y = 20
+        for z in 10..15 {
+            for x in 10..15 {
+                blocks.set(UVec3::new(x, 20, z), stone(&registry));
+            }
+        }
+        let light = ChunkLight::compute(&blocks, &registry);
+
+        assert_eq!(light.get(UVec3::new(0, 0, 0)).sun(), MAX_LIGHT);
+        assert_eq!(light.get(UVec3::new(12, 21, 12)).sun(), MAX_LIGHT);
+        assert_eq!(light.get(UVec3::new(12, 20, 12)).sun(), 0);
+        // Three steps in from the edge of the roof
+        assert_eq!(light.get(UVec3::new(12, 19, 12)).sun(), MAX_LIGHT - 3);
+        assert_eq!(light.get(UVec3::new(10, 5, 12)).sun(), MAX_LIGHT - 1);
+    }
+
+    #[test]
+    fn block_light_fades_with_distance() {
+        let registry = BlockRegistry::builtin();
+        let (lamp, emission) = lamp(&registry);
+        let mut blocks = roofed(&registry);
+        blocks.set(UVec3::new(16, 10, 16), lamp);
+        // A wall the light has to go around
+        for y in 0..31 {
+            for z in 0..CHUNK_SIZE as u32 {
+                blocks.set(UVec3::new(18, y, z), stone(&registry));
+            }
+        }
+        let light = ChunkLight::compute(&blocks, &registry);
+
+        assert_eq!(light.get(UVec3::new(16, 10, 16)).block(), emission);
+        assert_eq!(light.get(UVec3::new(13, 10, 16)).block(), emission - 3);
+        assert_eq!(light.get(UVec3::new(16, 12, 15)).block(), emission - 3);
+        assert_eq!(light.get(UVec3::new(19, 10, 16)).block(), 0);
+        assert_eq!(light.get(UVec3::new(16, 10, 16)).sun(), 0);
+    }
+
+    #[test]
+    fn removing_a_lamp_clears_its_light() {
+        let registry = BlockRegistry::builtin();
+        let (lamp, emission) = lamp(&registry);
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, roofed(&registry), &registry);
+
+        let pos = IVec3::new(5, 5, 5);
+        world.set_block(pos, lamp, &registry);
+        assert_eq!(world.at(pos).block(), emission);
+        assert_eq!(world.at(pos + IVec3::new(2, 1, 0)).block(), emission - 3);
+
+        world.set_block(pos, AIR, &registry);
+        for x in 0..12 {
+            assert_eq!(world.at(IVec3::new(x, 5, 5)).block(), 0);
+        }
+    }
+
+    #[test]
+    fn removing_one_of_two_lamps_keeps_the_other() {
+        let registry = BlockRegistry::builtin();
+        let (lamp, emission) = lamp(&registry);
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, roofed(&registry), &registry);
+
+        let (a, b) = (IVec3::new(4, 5, 5), IVec3::new(10, 5, 5));
+        world.set_block(a, lamp, &registry);
+        world.set_block(b, lamp, &registry);
+        world.set_block(a, AIR, &registry);
+
+        // Only `b`'s light remains, even where `a`'s was brighter
+        assert_eq!(world.at(a).block(), emission - 6);
+        assert_eq!(world.at(a - IVec3::X).block(), emission - 7);
+        assert_eq!(world.at(b).block(), emission);
+    }
+
+    #[test]
+    fn placing_and_breaking_a_roof_block() {
+        let registry = BlockRegistry::builtin();
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, ChunkBlocks::default(), &registry);
+        let before = world.snapshot();
+
+        let roof = IVec3::new(8, 20, 8);
+        world.set_block(roof, stone(&registry), &registry);
+        assert_eq!(world.at(roof).sun(), 0);
+        assert_eq!(world.at(roof + IVec3::Y).sun(), MAX_LIGHT);
+        // Lit from the sides only below it
+        assert_eq!(world.at(IVec3::new(8, 0, 8)).sun(), MAX_LIGHT - 1);
+
+        world.set_block(roof, AIR, &registry);
+        assert!(world.snapshot() == before);
+    }
+
+    #[test]
+    fn light_crosses_chunk_borders() {
+        let registry = BlockRegistry::builtin();
+        let (lamp, emission) = lamp(&registry);
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, roofed(&registry), &registry);
+        world.add_chunk(IVec3::X, roofed(&registry), &registry);
+
+        let pos = IVec3::new(30, 5, 5);
+        world.set_block(pos, lamp, &registry);
+        assert_eq!(world.at(IVec3::new(33, 5, 5)).block(), emission - 3);
+
+        world.set_block(pos, AIR, &registry);
+        assert_eq!(world.at(IVec3::new(33, 5, 5)).block(), 0);
+    }
+
+    #[test]
+    fn chunks_loaded_later_join_their_neighbours_light() {
+        let registry = BlockRegistry::builtin();
+        let (lamp, emission) = lamp(&registry);
+        let mut lamp_chunk = roofed(&registry);
+        lamp_chunk.set(UVec3::new(30, 5, 5), lamp);
+
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, lamp_chunk, &registry);
+        world.add_chunk(IVec3::X, roofed(&registry), &registry);
+        assert_eq!(world.at(IVec3::new(33, 5, 5)).block(), emission - 3);
+
+        // Loading in the other order gives the same light
+        let mut reversed = TestWorld::default();
+        reversed.add_chunk(IVec3::X, roofed(&registry), &registry);
+        reversed.add_chunk(IVec3::ZERO, world.chunks[&IVec3::ZERO].0.clone(), &registry);
+        assert!(reversed.snapshot() == world.snapshot());
+    }
+
+    #[test]
+    fn chunks_below_solid_ground_lose_the_sky() {
+        let registry = BlockRegistry::builtin();
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, ChunkBlocks::default(), &registry);
+        assert_eq!(world.at(IVec3::new(3, 0, 3)).sun(), MAX_LIGHT);
+
+        // Solid ground loads above, the open chunk below is a cave now
+        world.add_chunk(IVec3::Y, ChunkBlocks::filled(stone(&registry)), &registry);
+        assert_eq!(world.at(IVec3::new(3, 31, 3)).sun(), 0);
+        assert_eq!(world.at(IVec3::new(3, 0, 3)).sun(), 0);
+
+        // And an open chunk loading under solid ground is dark from the start
+        world.add_chunk(IVec3::NEG_Y, ChunkBlocks::default(), &registry);
+        assert_eq!(world.at(IVec3::new(3, -1, 3)).sun(), 0);
+    }
+
+    #[test]
+    fn digging_a_shaft_lets_the_sun_in() {
+        let registry = BlockRegistry::builtin();
+        let mut world = TestWorld::default();
+        world.add_chunk(IVec3::ZERO, ChunkBlocks::default(), &registry);
+        world.add_chunk(IVec3::Y, roofed(&registry), &registry);
+        assert_eq!(world.at(IVec3::new(3, 10, 3)).sun(), 0);
+
+        world.set_block(IVec3::new(3, 63, 3), AIR, &registry);
+        assert_eq!(world.at(IVec3::new(3, 63, 3)).sun(), MAX_LIGHT);
+        assert_eq!(world.at(IVec3::new(3, 0, 3)).sun(), MAX_LIGHT);
+        assert_eq!(world.at(IVec3::new(5, 0, 3)).sun(), MAX_LIGHT - 2);
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use crate::render::texture::Texture;
+use crate::world::registry::BlockRegistry;
+
+/// Block textures the chunk shader samples, one array layer per texture name
+/// in the registry.
+pub struct BlockMaterials {
+    pub layout: wgpu::BindGroupLayout,
+    pub group: wgpu::BindGroup,
+
+    textures: Texture,
+}
+
+impl BlockMaterials {
+    pub const TEXTURE_DIR: &'static str = "assets/textures";
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, registry: &BlockRegistry) -> Self {
+        let textures = Texture::load_block_texture_array(
+            device,
+            queue,
+            Path::new(Self::TEXTURE_DIR),
+            registry.texture_names(),
+        );
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("block materials bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("block materials bind group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&textures.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&textures.sampler),
+                },
+            ],
+        });
+
+        Self {
+            layout,
+            group,
+            textures,
+        }
+    }
+}
@@ -0,0 +1,881 @@
+use glam::{IVec3, UVec3, Vec3};
+
+use std::ops::Range;
+
+use super::blocks::{BlockId, ChunkBlocks, AIR};
+use super::chunk::ChunkVertex;
+use super::coords::{CHUNK_SIZE, CHUNK_SIZE_I32};
+use super::light::{ChunkLight, LightLevel};
+use crate::world::registry::{BlockRegistry, RenderLayer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Face {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::PosX,
+        Face::NegX,
+        Face::PosY,
+        Face::NegY,
+        Face::PosZ,
+        Face::NegZ,
+    ];
+
+    /// Index of the axis the face is perpendicular to (0 = X, 1 = Y, 2 = Z).
+    pub fn axis(self) -> usize {
+        match self {
+            Face::PosX | Face::NegX => 0,
+            Face::PosY | Face::NegY => 1,
+            Face::PosZ | Face::NegZ => 2,
+        }
+    }
+
+    pub fn is_positive(self) -> bool {
+        matches!(self, Face::PosX | Face::PosY | Face::PosZ)
+    }
+
+    pub fn normal(self) -> IVec3 {
+        let mut normal = IVec3::ZERO;
+        normal[self.axis()] = if self.is_positive() { 1 } else { -1 };
+        normal
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The face perpendicular to `axis` (0 = X, 1 = Y, 2 = Z) on its positive
+    /// or negative side.
+    pub fn from_axis(axis: usize, positive: bool) -> Self {
+        Face::ALL[axis * 2 + !positive as usize]
+    }
+
+    /// The two in-plane axes of the face. `u × v` always points along the
+    /// positive face axis.
+    pub fn tangent_axes(self) -> (usize, usize) {
+        let axis = self.axis();
+        ((axis + 1) % 3, (axis + 2) % 3)
+    }
+}
+
+/// A chunk's blocks together with whichever of its 26 neighbours are loaded,
+/// so faces on the chunk border can be culled against the adjacent chunk.
+/// Missing neighbours are treated as air, and missing light as open sky.
+pub struct ChunkNeighbourhood<'a> {
+    chunks: [Option<&'a ChunkBlocks>; 27],
+    lights: [Option<&'a ChunkLight>; 27],
+}
+
+impl<'a> ChunkNeighbourhood<'a> {
+    pub fn new(center: &'a ChunkBlocks) -> Self {
+        let mut chunks = [None; 27];
+        chunks[Self::slot(IVec3::ZERO)] = Some(center);
+        Self {
+            chunks,
+            lights: [None; 27],
+        }
+    }
+
+    /// Set the neighbour at `offset`, where each component is in `-1..=1`.
+    pub fn with_neighbour(mut self, offset: IVec3, blocks: &'a ChunkBlocks) -> Self {
+        self.chunks[Self::slot(offset)] = Some(blocks);
+        self
+    }
+
+    /// Set the light of the chunk at `offset`, the center included.
+    pub fn with_light(mut self, offset: IVec3, light: &'a ChunkLight) -> Self {
+        self.lights[Self::slot(offset)] = Some(light);
+        self
+    }
+
+    pub fn center(&self) -> &'a ChunkBlocks {
+        self.chunks[Self::slot(IVec3::ZERO)].expect("neighbourhood without a center chunk")
+    }
+
+    /// Block at a position relative to the center chunk's origin. Positions
+    /// may reach one chunk out in every direction.
+    pub fn get(&self, pos: IVec3) -> BlockId {
+        let offset = pos.div_euclid(IVec3::splat(CHUNK_SIZE_I32));
+        let local = pos.rem_euclid(IVec3::splat(CHUNK_SIZE_I32)).as_uvec3();
+        match self.chunks[Self::slot(offset)] {
+            Some(blocks) => blocks.get(local),
+            None => AIR,
+        }
+    }
+
+    /// Light at a position relative to the center chunk's origin, reaching
+    /// one chunk out like [`Self::get`].
+    pub fn light(&self, pos: IVec3) -> LightLevel {
+        let offset = pos.div_euclid(IVec3::splat(CHUNK_SIZE_I32));
+        let local = pos.rem_euclid(IVec3::splat(CHUNK_SIZE_I32)).as_uvec3();
+        match self.lights[Self::slot(offset)] {
+            Some(light) => light.get(local),
+            None => LightLevel::SKY,
+        }
+    }
+
+    fn slot(offset: IVec3) -> usize {
+        debug_assert!(offset.abs().max_element() <= 1, "neighbour offset out of range: {offset}");
+        let o = offset + IVec3::ONE;
+        (o.x + o.y * 3 + o.z * 9) as usize
+    }
+}
+
+/// A rectangular block face of `w` × `h` blocks, spanning the face's tangent
+/// axes starting at the block `pos`. Lit by the light in front of the face.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quad {
+    pub face: Face,
+    pub pos: UVec3,
+    pub w: u32,
+    pub h: u32,
+    pub block: BlockId,
+    pub light: LightLevel,
+    /// Ambient occlusion of the corners, see [`face_ao`].
+    pub ao: [u8; 4],
+}
+
+/// No ambient occlusion on any corner.
+pub const NO_AO: [u8; 4] = [3; 4];
+
+/// Ambient occlusion of a face vertex from the three blocks touching it in
+/// front of the face, from 0 (darkest) to 3 (not occluded). Two sides hide
+/// the corner block, so they are as dark as it gets.
+pub fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        0
+    } else {
+        3 - (side1 as u8 + side2 as u8 + corner as u8)
+    }
+}
+
+/// Ambient occlusion of the four corners of the `face` of the block at
+/// `pos`, indexed by `u + 2 * v` with `u` and `v` the corner's offset, 0 or
+/// 1, along the face's tangent axes.
+pub fn face_ao(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    pos: IVec3,
+    face: Face,
+) -> [u8; 4] {
+    let (u_axis, v_axis) = face.tangent_axes();
+    let front = pos + face.normal();
+    let occludes = |pos: IVec3| registry.is_opaque(neighbourhood.get(pos));
+
+    std::array::from_fn(|corner| {
+        let mut du = IVec3::ZERO;
+        du[u_axis] = if corner & 1 == 0 { -1 } else { 1 };
+        let mut dv = IVec3::ZERO;
+        dv[v_axis] = if corner & 2 == 0 { -1 } else { 1 };
+        vertex_ao(occludes(front + du), occludes(front + dv), occludes(front + du + dv))
+    })
+}
+
+/// Four vertices per quad, to be drawn with the shared quad index buffer.
+/// Quads are grouped by [`RenderLayer`], in layer order.
+#[derive(Default)]
+pub struct ChunkMesh {
+    pub vertices: Vec<ChunkVertex>,
+    /// Quads in each layer, indexed by [`RenderLayer::index`].
+    pub layer_quads: [u32; 3],
+}
+
+impl ChunkMesh {
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn size_in_bytes(&self) -> u64 {
+        (self.vertices.len() * size_of::<ChunkVertex>()) as u64
+    }
+
+    pub fn quad_count(&self) -> u32 {
+        (self.vertices.len() / 4) as u32
+    }
+
+    /// Range of quads in `layer`.
+    pub fn layer_range(&self, layer: RenderLayer) -> Range<u32> {
+        layer_range(self.layer_quads, layer)
+    }
+
+    pub fn layer_vertices(&self, layer: RenderLayer) -> &[ChunkVertex] {
+        let quads = self.layer_range(layer);
+        &self.vertices[quads.start as usize * 4..quads.end as usize * 4]
+    }
+
+    pub fn layer_vertices_mut(&mut self, layer: RenderLayer) -> &mut [ChunkVertex] {
+        let quads = self.layer_range(layer);
+        &mut self.vertices[quads.start as usize * 4..quads.end as usize * 4]
+    }
+
+    pub fn from_quads(quads: &[Quad], registry: &BlockRegistry) -> Self {
+        let mut mesh = ChunkMesh {
+            vertices: Vec::with_capacity(quads.len() * 4),
+            layer_quads: [0; 3],
+        };
+
+        let mut quads = quads.to_vec();
+        quads.sort_by_key(|quad| registry.render_layer(quad.block).index());
+        for quad in &quads {
+            mesh.push_quad(quad, registry);
+            mesh.layer_quads[registry.render_layer(quad.block).index()] += 1;
+        }
+
+        mesh
+    }
+
+    fn push_quad(&mut self, quad: &Quad, registry: &BlockRegistry) {
+        let (u_axis, v_axis) = quad.face.tangent_axes();
+
+        let mut base = quad.pos;
+        if quad.face.is_positive() {
+            base[quad.face.axis()] += 1;
+        }
+
+        let mut u = UVec3::ZERO;
+        u[u_axis] = quad.w;
+        let mut v = UVec3::ZERO;
+        v[v_axis] = quad.h;
+
+        // Counter-clockwise when seen from outside the block. With a
+        // left-handed basis that means `u × v` has to point into the block.
+        // Corners are given as their index into `Quad::ao`.
+        let mut corners = if quad.face.is_positive() {
+            [(base, 0), (base + v, 2), (base + u + v, 3), (base + u, 1)]
+        } else {
+            [(base, 0), (base + u, 1), (base + u + v, 3), (base + v, 2)]
+        };
+
+        // Quads are split into triangles along the first and third vertex.
+        // Splitting along the other diagonal when it is darker keeps the
+        // occlusion gradient the same whichever way the quad is turned.
+        let ao = quad.ao;
+        if ao[0] + ao[3] > ao[1] + ao[2] {
+            corners.rotate_left(1);
+        }
+
+        let layer = registry.texture_layer(quad.block, quad.face);
+        let light = quad.light.packed() as u32;
+        self.vertices.extend(corners.iter().map(|(position, corner)| {
+            ChunkVertex::new(*position, quad.face, ao[*corner] as u32, layer, light)
+        }));
+    }
+}
+
+/// How chunks are meshed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshOptions {
+    pub mode: MeshingMode,
+    /// Darken face corners next to other blocks.
+    pub ambient_occlusion: bool,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            mode: MeshingMode::default(),
+            ambient_occlusion: true,
+        }
+    }
+}
+
+/// Range of quads in `layer`, for a mesh with `layer_quads` quads in each.
+pub fn layer_range(layer_quads: [u32; 3], layer: RenderLayer) -> Range<u32> {
+    let start: u32 = layer_quads[..layer.index()].iter().sum();
+    start..start + layer_quads[layer.index()]
+}
+
+/// Reorder quads so the ones furthest from `eye` come first, for blending
+/// translucent faces correctly. `eye` is relative to the chunk origin.
+pub fn sort_back_to_front(vertices: &mut [ChunkVertex], eye: Vec3) {
+    let distance = |quad: &[ChunkVertex]| {
+        let sum: UVec3 = quad.iter().map(|v| v.position()).sum();
+        (sum.as_vec3() / 4.0 - eye).length_squared()
+    };
+
+    let quads: &mut [[ChunkVertex; 4]] = bytemuck::cast_slice_mut(vertices);
+    quads.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshingMode {
+    /// One quad per visible block face.
+    Naive,
+    /// Coplanar faces of the same block type merged into larger quads.
+    #[default]
+    Greedy,
+}
+
+impl MeshingMode {
+    pub fn next(self) -> Self {
+        match self {
+            MeshingMode::Naive => MeshingMode::Greedy,
+            MeshingMode::Greedy => MeshingMode::Naive,
+        }
+    }
+}
+
+/// Whether the face of `block` that touches `neighbour` can be seen. Faces
+/// between two blocks of the same transparent type, like water against
+/// water, are hidden too.
+pub fn is_face_visible(registry: &BlockRegistry, block: BlockId, neighbour: BlockId) -> bool {
+    block != AIR && !registry.is_opaque(neighbour) && (neighbour != block || registry.is_opaque(block))
+}
+
+/// Emit one quad per visible block face.
+pub fn cull_faces(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: bool,
+) -> Vec<Quad> {
+    let blocks = neighbourhood.center();
+    let mut quads = vec![];
+
+    if blocks.is_empty() {
+        return quads;
+    }
+
+    for y in 0..CHUNK_SIZE as u32 {
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                let pos = UVec3::new(x, y, z);
+                let block = blocks.get(pos);
+                if block == AIR {
+                    continue;
+                }
+
+                for face in Face::ALL {
+                    let front = pos.as_ivec3() + face.normal();
+                    let neighbour = neighbourhood.get(front);
+                    if is_face_visible(registry, block, neighbour) {
+                        quads.push(Quad {
+                            face,
+                            pos,
+                            w: 1,
+                            h: 1,
+                            block,
+                            light: neighbourhood.light(front),
+                            ao: if ambient_occlusion {
+                                face_ao(neighbourhood, registry, pos.as_ivec3(), face)
+                            } else {
+                                NO_AO
+                            },
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    quads
+}
+
+/// Merge visible faces that share a plane, a block type, a light level and
+/// ambient occlusion into as few rectangles as possible, sweeping each 32×32
+/// slice of the chunk.
+pub fn greedy_faces(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    ambient_occlusion: bool,
+) -> Vec<Quad> {
+    let blocks = neighbourhood.center();
+    let mut quads = vec![];
+
+    if blocks.is_empty() {
+        return quads;
+    }
+
+    const N: usize = CHUNK_SIZE;
+    let mut mask: [Option<(BlockId, LightLevel, [u8; 4])>; N * N] = [None; N * N];
+
+    for face in Face::ALL {
+        let axis = face.axis();
+        let (u_axis, v_axis) = face.tangent_axes();
+
+        for slice in 0..N as u32 {
+            for v in 0..N as u32 {
+                for u in 0..N as u32 {
+                    let mut pos = UVec3::ZERO;
+                    pos[axis] = slice;
+                    pos[u_axis] = u;
+                    pos[v_axis] = v;
+
+                    let block = blocks.get(pos);
+                    let front = pos.as_ivec3() + face.normal();
+                    let neighbour = neighbourhood.get(front);
+                    let visible = is_face_visible(registry, block, neighbour);
+
+                    mask[u as usize + v as usize * N] = visible.then(|| {
+                        let ao = if ambient_occlusion {
+                            face_ao(neighbourhood, registry, pos.as_ivec3(), face)
+                        } else {
+                            NO_AO
+                        };
+                        (block, neighbourhood.light(front), ao)
+                    });
+                }
+            }
+
+            for v in 0..N {
+                let mut u = 0;
+                while u < N {
+                    let Some(key @ (block, light, ao)) = mask[u + v * N] else {
+                        u += 1;
+                        continue;
+                    };
+
+                    let mut w = 1;
+                    while u + w < N && mask[u + w + v * N] == Some(key) {
+                        w += 1;
+                    }
+
+                    let mut h = 1;
+                    'grow: while v + h < N {
+                        for du in 0..w {
+                            if mask[u + du + (v + h) * N] != Some(key) {
+                                break 'grow;
+                            }
+                        }
+                        h += 1;
+                    }
+
+                    for dv in 0..h {
+                        mask[u + (v + dv) * N..u + w + (v + dv) * N].fill(None);
+                    }
+
+                    let mut pos = UVec3::ZERO;
+                    pos[axis] = slice;
+                    pos[u_axis] = u as u32;
+                    pos[v_axis] = v as u32;
+
+                    quads.push(Quad {
+                        face,
+                        pos,
+                        w: w as u32,
+                        h: h as u32,
+                        block,
+                        light,
+                        ao,
+                    });
+
+                    u += w;
+                }
+            }
+        }
+    }
+
+    quads
+}
+
+pub fn mesh_quads(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    options: MeshOptions,
+) -> Vec<Quad> {
+    match options.mode {
+        MeshingMode::Naive => cull_faces(neighbourhood, registry, options.ambient_occlusion),
+        MeshingMode::Greedy => greedy_faces(neighbourhood, registry, options.ambient_occlusion),
+    }
+}
+
+pub fn mesh_chunk(
+    neighbourhood: &ChunkNeighbourhood,
+    registry: &BlockRegistry,
+    options: MeshOptions,
+) -> ChunkMesh {
+    ChunkMesh::from_quads(&mesh_quads(neighbourhood, registry, options), registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::Vec3;
+
+    use std::collections::HashMap;
+
+    fn naive() -> MeshOptions {
+        MeshOptions {
+            mode: MeshingMode::Naive,
+            ..Default::default()
+        }
+    }
+
+    fn face_count(neighbourhood: &ChunkNeighbourhood) -> usize {
+        cull_faces(neighbourhood, &BlockRegistry::builtin(), true).len()
+    }
+
+    /// Every unit block face covered by a set of quads, with its block type.
+    fn covered_faces(quads: &[Quad]) -> HashMap<(Face, UVec3), BlockId> {
+        let mut covered = HashMap::new();
+        for quad in quads {
+            let (u_axis, v_axis) = quad.face.tangent_axes();
+            for v in 0..quad.h {
+                for u in 0..quad.w {
+                    let mut pos = quad.pos;
+                    pos[u_axis] += u;
+                    pos[v_axis] += v;
+                    let previous = covered.insert((quad.face, pos), quad.block);
+                    assert!(previous.is_none(), "overlapping quads at {pos} {:?}", quad.face);
+                }
+            }
+        }
+        covered
+    }
+
+    fn assert_same_surface(neighbourhood: &ChunkNeighbourhood) {
+        let naive = cull_faces(neighbourhood, &BlockRegistry::builtin(), true);
+        let greedy = greedy_faces(neighbourhood, &BlockRegistry::builtin(), true);
+        assert!(greedy.len() <= naive.len());
+        assert_eq!(covered_faces(&naive), covered_faces(&greedy));
+    }
+
+    fn terrain() -> ChunkBlocks {
+        let mut blocks = ChunkBlocks::default();
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                let height = 4 + (x * 7 + z * 13) % 9 + (x / 8) * 2;
+                for y in 0..height {
+                    let block = if y + 1 == height { 3 } else if y + 4 > height { 2 } else { 1 };
+                    blocks.set(UVec3::new(x, y, z), block);
+                }
+            }
+        }
+        blocks.set(UVec3::new(3, 1, 3), AIR);
+        blocks.set(UVec3::new(10, 2, 20), AIR);
+        blocks
+    }
+
+    #[test]
+    fn empty_chunk_has_no_faces() {
+        let blocks = ChunkBlocks::default();
+        let mesh = mesh_chunk(&ChunkNeighbourhood::new(&blocks), &BlockRegistry::builtin(), MeshOptions::default());
+        assert!(mesh.is_empty());
+        assert!(mesh.vertices.is_empty());
+    }
+
+    #[test]
+    fn single_block_has_six_faces() {
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(4, 4, 4), 1);
+
+        let mesh = mesh_chunk(&ChunkNeighbourhood::new(&blocks), &BlockRegistry::builtin(), naive());
+        assert_eq!(mesh.vertices.len(), 6 * 4);
+        assert_eq!(mesh.quad_count(), 6);
+    }
+
+    #[test]
+    fn adjacent_blocks_hide_shared_faces() {
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(4, 4, 4), 1);
+        blocks.set(UVec3::new(5, 4, 4), 2);
+        blocks.set(UVec3::new(5, 5, 4), 1);
+
+        assert_eq!(face_count(&ChunkNeighbourhood::new(&blocks)), 3 * 6 - 2 * 2);
+    }
+
+    #[test]
+    fn full_chunk_without_neighbours_only_has_its_shell() {
+        let blocks = ChunkBlocks::filled(1);
+        assert_eq!(
+            face_count(&ChunkNeighbourhood::new(&blocks)),
+            6 * CHUNK_SIZE * CHUNK_SIZE
+        );
+    }
+
+    #[test]
+    fn full_chunk_surrounded_by_full_chunks_has_no_faces() {
+        let blocks = ChunkBlocks::filled(1);
+        let mut neighbourhood = ChunkNeighbourhood::new(&blocks);
+        for face in Face::ALL {
+            neighbourhood = neighbourhood.with_neighbour(face.normal(), &blocks);
+        }
+        assert_eq!(face_count(&neighbourhood), 0);
+    }
+
+    #[test]
+    fn border_faces_are_culled_against_neighbours() {
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(31, 0, 0), 1);
+
+        let mut east = ChunkBlocks::default();
+        east.set(UVec3::new(0, 0, 0), 1);
+
+        assert_eq!(face_count(&ChunkNeighbourhood::new(&blocks)), 6);
+
+        let neighbourhood = ChunkNeighbourhood::new(&blocks).with_neighbour(IVec3::X, &east);
+        let quads = cull_faces(&neighbourhood, &BlockRegistry::builtin(), true);
+        assert_eq!(quads.len(), 5);
+        assert!(quads.iter().all(|q| q.face != Face::PosX));
+    }
+
+    #[test]
+    fn neighbourhood_lookup_crosses_chunk_borders() {
+        let center = ChunkBlocks::default();
+        let mut below = ChunkBlocks::default();
+        below.set(UVec3::new(0, 31, 5), 3);
+
+        let neighbourhood = ChunkNeighbourhood::new(&center).with_neighbour(IVec3::NEG_Y, &below);
+        assert_eq!(neighbourhood.get(IVec3::new(0, -1, 5)), 3);
+        assert_eq!(neighbourhood.get(IVec3::new(0, 32, 5)), AIR);
+    }
+
+    #[test]
+    fn greedy_merges_flat_slab_into_six_quads() {
+        let mut blocks = ChunkBlocks::default();
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                blocks.set(UVec3::new(x, 0, z), 1);
+            }
+        }
+
+        let neighbourhood = ChunkNeighbourhood::new(&blocks);
+        assert_eq!(greedy_faces(&neighbourhood, &BlockRegistry::builtin(), true).len(), 6);
+        assert_same_surface(&neighbourhood);
+    }
+
+    #[test]
+    fn greedy_does_not_merge_different_blocks() {
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(0, 0, 0), 1);
+        blocks.set(UVec3::new(1, 0, 0), 2);
+
+        let neighbourhood = ChunkNeighbourhood::new(&blocks);
+        let top: Vec<_> = greedy_faces(&neighbourhood, &BlockRegistry::builtin(), true)
+            .into_iter()
+            .filter(|q| q.face == Face::PosY)
+            .collect();
+        assert_eq!(top.len(), 2);
+        assert_same_surface(&neighbourhood);
+    }
+
+    #[test]
+    fn greedy_and_naive_cover_the_same_surface() {
+        let blocks = terrain();
+        assert_same_surface(&ChunkNeighbourhood::new(&blocks));
+
+        let full = ChunkBlocks::filled(1);
+        let neighbourhood = ChunkNeighbourhood::new(&blocks)
+            .with_neighbour(IVec3::NEG_X, &full)
+            .with_neighbour(IVec3::Z, &full);
+        assert_same_surface(&neighbourhood);
+    }
+
+    #[test]
+    fn transparent_blocks_only_hide_faces_of_their_own_type() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.id("stone").unwrap();
+        let water = registry.id("water").unwrap();
+        let glass = registry.id("glass").unwrap();
+
+        assert!(is_face_visible(&registry, stone, water));
+        assert!(is_face_visible(&registry, water, AIR));
+        assert!(is_face_visible(&registry, water, glass));
+        assert!(!is_face_visible(&registry, water, water));
+        assert!(!is_face_visible(&registry, water, stone));
+        assert!(!is_face_visible(&registry, AIR, AIR));
+
+        // Two water blocks side by side render as one 2x1x1 volume
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(4, 4, 4), water);
+        blocks.set(UVec3::new(5, 4, 4), water);
+        assert_eq!(face_count(&ChunkNeighbourhood::new(&blocks)), 10);
+    }
+
+    #[test]
+    fn faces_take_the_light_in_front_of_them() {
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(4, 0, 4), 1);
+        blocks.set(UVec3::new(5, 0, 4), 1);
+
+        let mut light = ChunkLight::default();
+        light.set(UVec3::new(4, 1, 4), LightLevel::new(15, 0));
+        light.set(UVec3::new(5, 1, 4), LightLevel::new(9, 3));
+
+        let neighbourhood = ChunkNeighbourhood::new(&blocks).with_light(IVec3::ZERO, &light);
+        let registry = BlockRegistry::builtin();
+
+        // Differently lit faces don't merge
+        let top: Vec<_> = greedy_faces(&neighbourhood, &registry, true)
+            .into_iter()
+            .filter(|q| q.face == Face::PosY)
+            .collect();
+        assert_eq!(top.len(), 2);
+
+        let mesh = ChunkMesh::from_quads(&top, &registry);
+        let mut lights: Vec<u32> = mesh.vertices.iter().map(|v| v.light()).collect();
+        lights.dedup();
+        assert_eq!(lights, vec![15 << 4, 9 << 4 | 3]);
+
+        // Faces of the bottom layer see the missing chunk below as sky
+        let bottom = cull_faces(&neighbourhood, &registry, true)
+            .into_iter()
+            .find(|q| q.face == Face::NegY)
+            .unwrap();
+        assert_eq!(bottom.light, LightLevel::SKY);
+    }
+
+    #[test]
+    fn vertex_ao_counts_occluders() {
+        assert_eq!(vertex_ao(false, false, false), 3);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, true), 1);
+        assert_eq!(vertex_ao(true, true, false), 0);
+    }
+
+    #[test]
+    fn blocks_next_to_a_face_darken_its_corners() {
+        let registry = BlockRegistry::builtin();
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(4, 4, 4), 1);
+        // Above and to the +X side of the top face
+        blocks.set(UVec3::new(5, 5, 4), 1);
+
+        let neighbourhood = ChunkNeighbourhood::new(&blocks);
+        // The top face's tangent axes are Z then X, so corners with x = 1
+        // are 2 and 3
+        let ao = face_ao(&neighbourhood, &registry, IVec3::new(4, 4, 4), Face::PosY);
+        assert_eq!(ao, [3, 3, 2, 2]);
+
+        let ao = face_ao(&neighbourhood, &registry, IVec3::new(4, 4, 4), Face::NegY);
+        assert_eq!(ao, NO_AO);
+    }
+
+    #[test]
+    fn greedy_only_merges_faces_with_the_same_ao() {
+        let registry = BlockRegistry::builtin();
+        let mut blocks = ChunkBlocks::default();
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                blocks.set(UVec3::new(x, 0, z), 1);
+            }
+        }
+        // A single block on the floor darkens the floor around it
+        blocks.set(UVec3::new(10, 1, 10), 1);
+        let neighbourhood = ChunkNeighbourhood::new(&blocks);
+
+        let floor = |ambient_occlusion| {
+            greedy_faces(&neighbourhood, &registry, ambient_occlusion)
+                .into_iter()
+                .filter(|q| q.face == Face::PosY && q.pos.y == 0)
+                .collect::<Vec<_>>()
+        };
+        let with_ao = floor(true);
+        let without_ao = floor(false);
+        assert!(with_ao.len() > without_ao.len());
+        assert!(without_ao.iter().all(|q| q.ao == NO_AO));
+
+        // Every merged face has the AO of each block face it covers
+        for quad in &with_ao {
+            let (u_axis, v_axis) = quad.face.tangent_axes();
+            for v in 0..quad.h {
+                for u in 0..quad.w {
+                    let mut pos = quad.pos;
+                    pos[u_axis] += u;
+                    pos[v_axis] += v;
+                    assert_eq!(face_ao(&neighbourhood, &registry, pos.as_ivec3(), quad.face), quad.ao);
+                }
+            }
+        }
+        assert_same_surface(&neighbourhood);
+    }
+
+    #[test]
+    fn quads_split_along_the_darker_diagonal() {
+        let registry = BlockRegistry::builtin();
+        for ao in [[0, 3, 3, 3], [3, 0, 3, 3], [3, 3, 0, 3], [3, 3, 3, 0], [3, 1, 3, 2]] {
+            for face in Face::ALL {
+                let quad = Quad {
+                    face,
+                    pos: UVec3::ZERO,
+                    w: 1,
+                    h: 1,
+                    block: 1,
+                    light: LightLevel::SKY,
+                    ao,
+                };
+                let mesh = ChunkMesh::from_quads(&[quad], &registry);
+                let v: Vec<u32> = mesh.vertices.iter().map(|v| v.ao()).collect();
+                // Triangles share the first and third vertex
+                assert!(v[0] + v[2] <= v[1] + v[3], "{face:?} {ao:?}: {v:?}");
+                let mut sorted = v.clone();
+                sorted.sort();
+                let mut expected: Vec<u32> = ao.iter().map(|a| *a as u32).collect();
+                expected.sort();
+                assert_eq!(sorted, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn quads_wind_counter_clockwise_from_outside() {
+        for face in Face::ALL {
+            let quad = Quad {
+                face,
+                pos: UVec3::ZERO,
+                w: 1,
+                h: 1,
+                block: 1,
+                light: LightLevel::SKY,
+                ao: NO_AO,
+            };
+            let mesh = ChunkMesh::from_quads(&[quad], &BlockRegistry::builtin());
+            let p: Vec<Vec3> = mesh.vertices.iter().map(|v| v.position().as_vec3()).collect();
+            // In a left-handed system a triangle faces the viewer when its
+            // (right-handed) cross product points away from them.
+            let n = (p[1] - p[0]).cross(p[2] - p[0]).normalize();
+            assert_eq!(n, -face.normal().as_vec3(), "{face:?}");
+        }
+    }
+
+    #[test]
+    fn meshes_group_quads_by_render_layer() {
+        let registry = BlockRegistry::builtin();
+        let glass = registry.id("glass").unwrap();
+        let leaves = registry.id("leaves").unwrap();
+        let mut blocks = ChunkBlocks::default();
+        blocks.set(UVec3::new(2, 2, 2), glass);
+        blocks.set(UVec3::new(6, 2, 2), 1);
+        blocks.set(UVec3::new(10, 2, 2), leaves);
+
+        let mesh = mesh_chunk(&ChunkNeighbourhood::new(&blocks), &registry, naive());
+        assert_eq!(mesh.layer_quads, [6, 6, 6]);
+        assert_eq!(mesh.layer_range(RenderLayer::Cutout), 6..12);
+        for layer in RenderLayer::ALL {
+            assert!(mesh.layer_vertices(layer).iter().all(|v| {
+                let block = [1, leaves, glass][layer.index()];
+                v.layer() == registry.texture_layer(block, v.face())
+            }));
+        }
+    }
+
+    #[test]
+    fn back_to_front_sorts_furthest_quads_first() {
+        let quad = |x| Quad {
+            face: Face::PosY,
+            pos: UVec3::new(x, 0, 0),
+            w: 1,
+            h: 1,
+            block: 1,
+            light: LightLevel::SKY,
+            ao: NO_AO,
+        };
+        let quads = [quad(3), quad(0), quad(9), quad(5)];
+        let mut mesh = ChunkMesh::from_quads(&quads, &BlockRegistry::builtin());
+
+        sort_back_to_front(&mut mesh.vertices, Vec3::new(4.0, 2.0, 0.0));
+        let order: Vec<u32> = mesh
+            .vertices
+            .chunks(4)
+            .map(|quad| quad.iter().map(|v| v.position().x).min().unwrap())
+            .collect();
+        assert_eq!(order, vec![9, 0, 5, 3]);
+    }
+}
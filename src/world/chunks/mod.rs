@@ -1,37 +1,266 @@
-use chunk::ChunkVertex;
+use chunk::{ChunkVertex, QuadIndexBuffer};
 
+pub mod blocks;
+pub mod buffers;
 pub mod chunk;
+pub mod coords;
+pub mod culling;
+pub mod light;
+pub mod materials;
+pub mod mesher;
+pub mod streaming;
+pub mod workers;
 
+use buffers::ChunkBuffers;
 use chunk::{WorldChunk, NoData, GPUData};
+use culling::GpuCulling;
+use light::{ChunkLight, LightLevel, LightStorage};
+use materials::BlockMaterials;
+use mesher::{ChunkMesh, MeshOptions, MeshingMode};
+use streaming::RenderDistance;
+use workers::{ChunkWorkers, JobHandle, JobKind, JobResult, MeshInput};
+
+use crate::render::frustum::Frustum;
+use crate::world::gen::TerrainGenerator;
+use crate::world::registry::{BlockRegistry, RenderLayer};
+use crate::world::save::WorldSave;
+
+use coords::CHUNK_SIZE;
+
+use glam::{IVec3, Vec3};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+enum ChunkEntry {
+    Generated(WorldChunk<NoData>),
+    Loaded(Box<WorldChunk<GPUData>>),
+}
+
+impl ChunkEntry {
+    fn blocks(&self) -> &blocks::ChunkBlocks {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.blocks(),
+            ChunkEntry::Loaded(chunk) => chunk.blocks(),
+        }
+    }
+
+    fn shared_blocks(&self) -> Arc<blocks::ChunkBlocks> {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.shared_blocks(),
+            ChunkEntry::Loaded(chunk) => chunk.shared_blocks(),
+        }
+    }
+
+    fn light(&self) -> &ChunkLight {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.light(),
+            ChunkEntry::Loaded(chunk) => chunk.light(),
+        }
+    }
+
+    fn shared_light(&self) -> Arc<ChunkLight> {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.shared_light(),
+            ChunkEntry::Loaded(chunk) => chunk.shared_light(),
+        }
+    }
+
+    fn set_light(&mut self, local: glam::UVec3, level: LightLevel) {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.set_light(local, level),
+            ChunkEntry::Loaded(chunk) => chunk.set_light(local, level),
+        }
+    }
+
+    fn is_dirty(&self) -> bool {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.is_dirty(),
+            ChunkEntry::Loaded(chunk) => chunk.is_dirty(),
+        }
+    }
+
+    fn set_block(&mut self, local: glam::UVec3, block: blocks::BlockId) {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.set_block(local, block),
+            ChunkEntry::Loaded(chunk) => chunk.set_block(local, block),
+        }
+    }
+
+    fn mark_saved(&mut self) {
+        match self {
+            ChunkEntry::Generated(chunk) => chunk.mark_saved(),
+            ChunkEntry::Loaded(chunk) => chunk.mark_saved(),
+        }
+    }
+}
+
+/// The loaded chunks as seen by the light flood fill, remembering which
+/// chunks' meshes the new light shows up in.
+struct LitChunks<'a> {
+    chunks: &'a mut HashMap<IVec3, ChunkEntry>,
+    changed: HashSet<IVec3>,
+}
+
+impl<'a> LitChunks<'a> {
+    fn new(chunks: &'a mut HashMap<IVec3, ChunkEntry>) -> Self {
+        Self {
+            chunks,
+            changed: HashSet::new(),
+        }
+    }
+}
+
+impl LightStorage for LitChunks<'_> {
+    fn block(&self, pos: IVec3) -> Option<blocks::BlockId> {
+        block_at(self.chunks, pos)
+    }
+
+    fn light(&self, pos: IVec3) -> Option<LightLevel> {
+        self.chunks
+            .get(&coords::world_to_chunk(pos))
+            .map(|entry| entry.light().get(coords::world_to_local(pos)))
+    }
+
+    fn set_light(&mut self, pos: IVec3, level: LightLevel) {
+        let coord = coords::world_to_chunk(pos);
+        let local = coords::world_to_local(pos);
+        if let Some(entry) = self.chunks.get_mut(&coord) {
+            entry.set_light(local, level);
+            self.changed.extend(edit_offsets(local).map(|offset| coord + offset));
+        }
+    }
+}
+
+/// Block at a world coordinate, or `None` if its chunk isn't in `chunks`.
+fn block_at(chunks: &HashMap<IVec3, ChunkEntry>, world: IVec3) -> Option<blocks::BlockId> {
+    chunks
+        .get(&coords::world_to_chunk(world))
+        .map(|entry| entry.blocks().get(coords::world_to_local(world)))
+}
+
+/// Chunks to mesh again after a block edit.
+#[derive(Debug, PartialEq, Eq)]
+struct EditedChunks {
+    /// The block's chunk and any neighbour sharing its border.
+    edited: HashSet<IVec3>,
+    /// Other loaded chunks the light changed in.
+    relit: HashSet<IVec3>,
+}
+
+/// Change the block at a world coordinate and the light around it.
+/// Returns `None`, changing nothing, if its chunk isn't in `chunks`.
+fn edit_block(
+    chunks: &mut HashMap<IVec3, ChunkEntry>,
+    registry: &BlockRegistry,
+    world: IVec3,
+    block: blocks::BlockId,
+) -> Option<EditedChunks> {
+    let coord = coords::world_to_chunk(world);
+    let local = coords::world_to_local(world);
+    chunks.get_mut(&coord)?.set_block(local, block);
+
+    let mut lit = LitChunks::new(chunks);
+    light::block_changed(&mut lit, registry, world);
+    let mut relit = lit.changed;
+
+    let edited: HashSet<_> = edit_offsets(local).map(|offset| coord + offset).collect();
+    relit.retain(|coord| !edited.contains(coord) && chunks.contains_key(coord));
+    Some(EditedChunks { edited, relit })
+}
+
+/// How many loaded chunks with a mesh were drawn and how many were outside
+/// the view frustum in the last frame. With GPU culling the numbers are a
+/// frame or two old.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+/// Chunk counts and the GPU memory of the chunk buffers, for the debug
+/// overlay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkStats {
+    pub loaded: usize,
+    pub pending: usize,
+    pub culling: CullingStats,
+    /// Bytes of the chunk buffers holding meshes.
+    pub gpu_used: u64,
+    /// Bytes of the chunk buffers, used or not.
+    pub gpu_allocated: u64,
+}
 
 pub struct Chunks {
-    chunks: Vec<WorldChunk<GPUData>>,
-    pending_chunks: Vec<WorldChunk<NoData>>,
+    chunks: HashMap<IVec3, ChunkEntry>,
+    /// Loaded chunks inside the view frustum, drawn by [`Chunks::render`]
+    /// when culling on the CPU.
+    visible: Vec<IVec3>,
+    /// Loaded chunks with translucent quads inside the view frustum,
+    /// furthest first. Always culled on the CPU.
+    translucent: Vec<IVec3>,
+    /// Camera position of the frame being prepared.
+    eye: Vec3,
+    /// Block the camera was in when translucent quads were last sorted.
+    sorted_from: Option<IVec3>,
+    culling_stats: CullingStats,
+
+    /// Chunks in range that still have to be generated, nearest first.
+    generate_queue: VecDeque<IVec3>,
+    /// Generated chunks whose mesh is missing or out of date.
+    mesh_queue: HashSet<IVec3>,
+    /// Chunks in `mesh_queue` to mesh before the rest, because an edit
+    /// changed their light.
+    mesh_first: HashSet<IVec3>,
+    /// Meshes back from the workers, waiting to be sent to the GPU.
+    upload_queue: HashMap<IVec3, ChunkMesh>,
+    /// Loaded chunks with edited blocks, meshed on the render thread before
+    /// the next frame is drawn.
+    edited: HashSet<IVec3>,
 
-    pipeline: wgpu::RenderPipeline,
+    generating: HashMap<IVec3, JobHandle>,
+    meshing: HashMap<IVec3, JobHandle>,
+    workers: ChunkWorkers,
 
-    bind_group_layout: wgpu::BindGroupLayout,
+    center: Option<IVec3>,
+    render_distance: RenderDistance,
+    /// Bytes of vertex data uploaded per frame, at most. At least one chunk
+    /// is uploaded every frame regardless.
+    upload_budget: u64,
+
+    generator: TerrainGenerator,
+    registry: Arc<BlockRegistry>,
+    save: Arc<WorldSave>,
+    mesh_options: MeshOptions,
+
+    renderer: ChunkRenderer,
 }
 
-impl Chunks {
-    pub fn init(gfx: &crate::GfxContext) -> Self {
+/// The GPU side of [`Chunks`]: chunk meshes, textures and pipelines.
+struct ChunkRenderer {
+    quad_indices: QuadIndexBuffer,
+    buffers: ChunkBuffers,
+    /// Culls and draws every chunk with one compute dispatch and one
+    /// multi-draw. `None` when the adapter can't, in which case chunks are
+    /// culled on the CPU and drawn one by one.
+    gpu_culling: Option<GpuCulling>,
+    materials: BlockMaterials,
+    /// Indexed by [`RenderLayer::index`].
+    pipelines: [wgpu::RenderPipeline; 3],
+}
+
+impl ChunkRenderer {
+    fn new(gfx: &crate::GfxContext, registry: &BlockRegistry) -> Self {
+        let materials = BlockMaterials::new(&gfx.device, &gfx.queue, registry);
+
         let shader = gfx.device.create_shader_module(wgpu::include_wgsl!("./chunk.wgsl"));
 
-        let bind_group_layout = gfx.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("chunk bind group layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-            ],
-        });
+        let buffers = ChunkBuffers::new(&gfx.device);
+
+        // Set SHALLOW_STONE_DIRECT_DRAW to force the fallback path
+        let gpu_culling = (GpuCulling::is_supported(&gfx.device)
+            && std::env::var_os("SHALLOW_STONE_DIRECT_DRAW").is_none())
+        .then(|| GpuCulling::new(&gfx.device));
 
         let pipeline_layout =
             gfx.device
@@ -39,78 +268,799 @@ impl Chunks {
                     label: Some("world pipeline layout"),
                     bind_group_layouts: &[
                         &gfx.global_shader_bindings.layout,
-                        &bind_group_layout,
+                        &buffers.layout,
+                        &materials.layout,
                     ],
                     push_constant_ranges: &[],
                 });
 
-        let pipeline = gfx.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("world pipeline"),
-            layout: Some(&pipeline_layout),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None, //Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                compilation_options: Default::default(),
-                buffers: &[ChunkVertex::layout()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                compilation_options: Default::default(),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: gfx.config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::all(),
-                })],
-            }),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: crate::render::texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: Default::default(),
-            multiview: None,
-            cache: None,
-        });
+        let pipelines = RenderLayer::ALL
+            .map(|layer| create_layer_pipeline(gfx, &pipeline_layout, &shader, layer));
+
+        Self {
+            quad_indices: QuadIndexBuffer::new(&gfx.device),
+            buffers,
+            gpu_culling,
+            materials,
+            pipelines,
+        }
+    }
+}
 
+impl Chunks {
+    pub fn init(
+        gfx: &crate::GfxContext,
+        generator: TerrainGenerator,
+        registry: Arc<BlockRegistry>,
+        save: Arc<WorldSave>,
+    ) -> Self {
         Self {
-            chunks: vec![],
-            pending_chunks: vec![
-                WorldChunk::generate(glam::Vec3::new(0.0, 0.0, 2.0)),
-            ],
+            chunks: HashMap::new(),
+            visible: vec![],
+            translucent: vec![],
+            eye: Vec3::ZERO,
+            sorted_from: None,
+            culling_stats: CullingStats::default(),
+
+            generate_queue: VecDeque::new(),
+            mesh_queue: HashSet::new(),
+            mesh_first: HashSet::new(),
+            upload_queue: HashMap::new(),
+            edited: HashSet::new(),
+
+            generating: HashMap::new(),
+            meshing: HashMap::new(),
+            workers: ChunkWorkers::new(generator.clone(), registry.clone(), Some(save.clone())),
+
+            center: None,
+            render_distance: RenderDistance::default(),
+            upload_budget: Self::DEFAULT_UPLOAD_BUDGET,
+
+            renderer: ChunkRenderer::new(gfx, &registry),
+            generator,
+            registry,
+            save,
+            mesh_options: MeshOptions::default(),
+        }
+    }
+
+    /// Create every GPU resource again, after the device was lost. The old
+    /// meshes went with it, so every chunk is meshed again from its blocks.
+    pub fn recreate_gpu_resources(&mut self, gfx: &crate::GfxContext) {
+        self.renderer = ChunkRenderer::new(gfx, &self.registry);
+
+        for (coord, entry) in std::mem::take(&mut self.chunks) {
+            let chunk = match entry {
+                ChunkEntry::Loaded(chunk) => {
+                    self.mesh_queue.insert(coord);
+                    chunk.forget_gpu_data()
+                }
+                ChunkEntry::Generated(chunk) => chunk,
+            };
+            self.chunks.insert(coord, ChunkEntry::Generated(chunk));
+        }
+
+        self.visible.clear();
+        self.translucent.clear();
+        self.sorted_from = None;
+        self.culling_stats = CullingStats::default();
+    }
+
+    /// Chunks this close to the camera have their translucent quads sorted
+    /// again whenever the camera enters another block.
+    const TRANSLUCENT_SORT_RADIUS: i32 = 2;
+
+    pub const DEFAULT_UPLOAD_BUDGET: u64 = 2 * 1024 * 1024;
+
+    pub fn prepare_render(
+        &mut self,
+        gfx: &crate::GfxContext,
+        camera_position: Vec3,
+        frustum: &Frustum,
+    ) {
+        self.eye = camera_position;
+        self.update_center(gfx, streaming::camera_chunk(camera_position));
+        self.remesh_edited(gfx);
+        self.collect_finished_jobs();
+        self.dispatch_generation();
+        self.dispatch_meshing();
+        self.upload_meshes(gfx);
+        self.sort_translucent(gfx);
+        self.cull(gfx, frustum);
+    }
+
+    /// Record the GPU culling pass, if chunks are culled on the GPU. Must be
+    /// submitted before the render pass drawing the chunks.
+    pub fn encode_culling(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(culling) = &self.renderer.gpu_culling {
+            culling.encode(encoder);
+        }
+    }
+
+    /// Called after the frame's commands were submitted.
+    pub fn frame_submitted(&self) {
+        if let Some(culling) = &self.renderer.gpu_culling {
+            culling.submitted();
+        }
+    }
+
+    pub fn uses_gpu_culling(&self) -> bool {
+        self.renderer.gpu_culling.is_some()
+    }
+
+    pub fn generator(&self) -> &TerrainGenerator {
+        &self.generator
+    }
+
+    pub fn registry(&self) -> &Arc<BlockRegistry> {
+        &self.registry
+    }
+
+    pub fn save(&self) -> &WorldSave {
+        &self.save
+    }
+
+    /// Write every modified chunk to the save.
+    pub fn save_all(&mut self) -> anyhow::Result<()> {
+        for (coord, entry) in &mut self.chunks {
+            if entry.is_dirty() {
+                self.save.save_chunk(*coord, entry.blocks())?;
+                entry.mark_saved();
+            }
+        }
+        self.save.flush()
+    }
+
+    /// Block at a world coordinate, or `None` if its chunk isn't loaded.
+    pub fn get_block(&self, world: IVec3) -> Option<blocks::BlockId> {
+        block_at(&self.chunks, world)
+    }
+
+    /// Change the block at a world coordinate. Returns false, changing
+    /// nothing, if its chunk isn't loaded.
+    ///
+    /// The chunk and any neighbour sharing the block's border are meshed
+    /// again before the next frame. Other chunks the light changed in are
+    /// meshed on the workers, ahead of chunks streaming in.
+    pub fn set_block(&mut self, world: IVec3, block: blocks::BlockId) -> bool {
+        let Some(changed) = edit_block(&mut self.chunks, &self.registry, world, block) else {
+            return false;
+        };
+        for coord in changed.edited {
+            self.remesh(coord, true);
+        }
+        for coord in changed.relit {
+            self.remesh(coord, false);
+        }
+        true
+    }
+
+    /// Mesh a chunk again because its blocks, its neighbours' or its light
+    /// changed. A loaded chunk is meshed before the next frame if `now`,
+    /// otherwise on the workers before any chunk that isn't.
+    fn remesh(&mut self, coord: IVec3, now: bool) {
+        // A mesh being built from the old blocks is out of date
+        if let Some(job) = self.meshing.remove(&coord) {
+            job.cancel();
+        }
+        self.upload_queue.remove(&coord);
+
+        match self.chunks.get(&coord) {
+            Some(ChunkEntry::Loaded(_)) if now || self.edited.contains(&coord) => {
+                self.mesh_queue.remove(&coord);
+                self.mesh_first.remove(&coord);
+                self.edited.insert(coord);
+            }
+            Some(ChunkEntry::Loaded(_)) => {
+                self.mesh_queue.insert(coord);
+                self.mesh_first.insert(coord);
+            }
+            Some(ChunkEntry::Generated(_)) => {
+                self.mesh_queue.insert(coord);
+            }
+            None => (),
+        }
+    }
+
+    /// Mesh and upload edited chunks right away, so edits show up in the
+    /// frame they were made in.
+    fn remesh_edited(&mut self, gfx: &crate::GfxContext) {
+        for coord in std::mem::take(&mut self.edited) {
+            let Some(entry) = self.chunks.get(&coord) else {
+                continue;
+            };
+
+            let mut neighbourhood = mesher::ChunkNeighbourhood::new(entry.blocks())
+                .with_light(IVec3::ZERO, entry.light());
+            for offset in neighbour_offsets() {
+                if let Some(neighbour) = self.chunks.get(&(coord + offset)) {
+                    neighbourhood = neighbourhood
+                        .with_neighbour(offset, neighbour.blocks())
+                        .with_light(offset, neighbour.light());
+                }
+            }
+
+            let mesh = mesher::mesh_chunk(&neighbourhood, &self.registry, self.mesh_options);
+            self.upload_mesh(gfx, coord, &mesh);
+        }
+    }
 
-            pipeline,
+    pub fn render_distance(&self) -> RenderDistance {
+        self.render_distance
+    }
 
-            bind_group_layout,
+    pub fn set_render_distance(&mut self, render_distance: RenderDistance) {
+        if render_distance != self.render_distance {
+            self.render_distance = render_distance;
+            // Force the loaded area to be recomputed on the next frame
+            self.center = None;
         }
     }
 
-    pub fn prepare_render(&mut self, gfx: &crate::GfxContext) {
-        self.load_pending_chunks(gfx);
+    pub fn meshing_mode(&self) -> MeshingMode {
+        self.mesh_options.mode
+    }
+
+    /// Switch mesher and queue every loaded chunk to be meshed again.
+    pub fn set_meshing_mode(&mut self, mode: MeshingMode) {
+        self.set_mesh_options(MeshOptions {
+            mode,
+            ..self.mesh_options
+        });
+    }
+
+    pub fn ambient_occlusion(&self) -> bool {
+        self.mesh_options.ambient_occlusion
+    }
+
+    pub fn set_ambient_occlusion(&mut self, enabled: bool) {
+        self.set_mesh_options(MeshOptions {
+            ambient_occlusion: enabled,
+            ..self.mesh_options
+        });
+    }
+
+    fn set_mesh_options(&mut self, options: MeshOptions) {
+        self.mesh_options = options;
+        self.mesh_queue.extend(
+            self.chunks
+                .iter()
+                .filter(|(_, entry)| matches!(entry, ChunkEntry::Loaded(_)))
+                .map(|(coord, _)| *coord),
+        );
+    }
+
+    fn loaded(&self) -> impl Iterator<Item = &WorldChunk<GPUData>> {
+        self.chunks.values().filter_map(|entry| match entry {
+            ChunkEntry::Loaded(chunk) => Some(chunk.as_ref()),
+            ChunkEntry::Generated(_) => None,
+        })
+    }
+
+    pub fn loaded_count(&self) -> usize {
+        self.loaded().count()
     }
 
+    pub fn pending_count(&self) -> usize {
+        self.generate_queue.len()
+            + self.generating.len()
+            + self.mesh_queue.len()
+            + self.meshing.len()
+            + self.upload_queue.len()
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.culling_stats
+    }
+
+    pub fn stats(&self) -> ChunkStats {
+        ChunkStats {
+            loaded: self.loaded_count(),
+            pending: self.pending_count(),
+            culling: self.culling_stats,
+            gpu_used: self.renderer.buffers.used_bytes(),
+            gpu_allocated: self.renderer.buffers.allocated_bytes(),
+        }
+    }
+
+    pub fn set_upload_budget(&mut self, bytes: u64) {
+        self.upload_budget = bytes;
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.loaded().map(|c| c.vertex_count()).sum()
+    }
+
+    pub fn vertex_buffer_size(&self) -> u64 {
+        self.loaded().map(|c| c.vertex_buffer_size()).sum()
+    }
+
+    /// Draw the opaque and cutout quads of every visible chunk, then the
+    /// translucent ones back to front without writing depth.
     pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
-        render_pass.set_pipeline(&self.pipeline);
-        for chunk in &self.chunks {
-            chunk.render(render_pass);
+        let renderer = &self.renderer;
+        render_pass.set_bind_group(1, &renderer.buffers.group, &[]);
+        render_pass.set_bind_group(2, &renderer.materials.group, &[]);
+        render_pass.set_vertex_buffer(0, renderer.buffers.vertices().slice(..));
+        render_pass.set_index_buffer(renderer.quad_indices.slice(), QuadIndexBuffer::FORMAT);
+
+        for layer in ChunkBuffers::DRAWN_LAYERS {
+            render_pass.set_pipeline(&renderer.pipelines[layer.index()]);
+
+            if renderer.gpu_culling.is_some() {
+                let count = renderer.buffers.slot_count();
+                if count > 0 {
+                    let offset = renderer.buffers.draws_offset(layer);
+                    render_pass.multi_draw_indexed_indirect(renderer.buffers.draws(), offset, count);
+                }
+                continue;
+            }
+
+            for coord in &self.visible {
+                if let Some(ChunkEntry::Loaded(chunk)) = self.chunks.get(coord) {
+                    chunk.render(render_pass, layer);
+                }
+            }
+        }
+
+        render_pass.set_pipeline(&renderer.pipelines[RenderLayer::Translucent.index()]);
+        for coord in &self.translucent {
+            if let Some(ChunkEntry::Loaded(chunk)) = self.chunks.get(coord) {
+                chunk.render(render_pass, RenderLayer::Translucent);
+            }
+        }
+    }
+
+    /// Pick the loaded chunks to draw this frame. Chunks with an empty mesh
+    /// are neither drawn nor counted as culled.
+    fn cull(&mut self, gfx: &crate::GfxContext, frustum: &Frustum) {
+        self.cull_translucent(frustum);
+
+        if let Some(culling) = &mut self.renderer.gpu_culling {
+            culling.prepare(&gfx.device, &gfx.queue, &self.renderer.buffers, frustum);
+            let drawn = culling.drawn() as usize;
+            self.culling_stats = CullingStats {
+                drawn,
+                culled: self.renderer.buffers.chunk_count().saturating_sub(drawn),
+            };
+            return;
+        }
+
+        self.visible.clear();
+        let mut stats = CullingStats::default();
+
+        for chunk in self.chunks.values().filter_map(|entry| match entry {
+            ChunkEntry::Loaded(chunk) if !chunk.is_empty() => Some(chunk),
+            _ => None,
+        }) {
+            let (min, max) = chunk.aabb();
+            if frustum.intersects_aabb(min, max) {
+                self.visible.push(chunk.coord());
+                stats.drawn += 1;
+            } else {
+                stats.culled += 1;
+            }
+        }
+
+        self.culling_stats = stats;
+    }
+
+    /// Collect the visible chunks with translucent quads, sorted so the
+    /// furthest is drawn first.
+    fn cull_translucent(&mut self, frustum: &Frustum) {
+        self.translucent = self
+            .loaded()
+            .filter(|chunk| chunk.has_translucent())
+            .filter(|chunk| {
+                let (min, max) = chunk.aabb();
+                frustum.intersects_aabb(min, max)
+            })
+            .map(|chunk| chunk.coord())
+            .collect();
+
+        let half = Vec3::splat(CHUNK_SIZE as f32 / 2.0);
+        let eye = self.eye;
+        let distance =
+            |coord: &IVec3| (coords::chunk_origin(*coord).as_vec3() + half).distance_squared(eye);
+        self.translucent.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+    }
+
+    /// Sort the translucent quads of the chunks near the camera again once it
+    /// moved to another block. Chunks further away keep the order they were
+    /// uploaded with, which rarely changes as the camera moves.
+    fn sort_translucent(&mut self, gfx: &crate::GfxContext) {
+        let block = self.eye.floor().as_ivec3();
+        if self.sorted_from == Some(block) {
+            return;
+        }
+        self.sorted_from = Some(block);
+
+        let center = coords::world_to_chunk(block);
+        let radius = Self::TRANSLUCENT_SORT_RADIUS;
+        for entry in self.chunks.values_mut() {
+            if let ChunkEntry::Loaded(chunk) = entry {
+                if (chunk.coord() - center).abs().max_element() <= radius {
+                    chunk.sort_translucent(&gfx.queue, &self.renderer.buffers, self.eye);
+                }
+            }
+        }
+    }
+
+    /// Unload chunks that left the render distance, cancel their jobs and
+    /// queue the ones that entered it.
+    fn update_center(&mut self, gfx: &crate::GfxContext, center: IVec3) {
+        if self.center == Some(center) {
+            return;
+        }
+        self.center = Some(center);
+
+        let distance = self.render_distance;
+        let in_range = |coord: &IVec3| distance.contains(center, *coord);
+
+        let out_of_range: Vec<IVec3> = self.chunks.keys().copied().filter(|c| !in_range(c)).collect();
+        for coord in out_of_range {
+            let entry = self.chunks.remove(&coord).unwrap();
+            if entry.is_dirty() {
+                if let Err(err) = self.save.save_chunk(coord, entry.blocks()) {
+                    tracing::error!("{err:#}");
+                }
+            }
+            if let ChunkEntry::Loaded(chunk) = entry {
+                chunk.unload(gfx, &mut self.renderer.buffers);
+            }
+        }
+        self.mesh_queue.retain(in_range);
+        self.mesh_first.retain(in_range);
+        self.upload_queue.retain(|coord, _| in_range(coord));
+        for jobs in [&mut self.generating, &mut self.meshing] {
+            jobs.retain(|coord, job| {
+                let keep = in_range(coord);
+                if !keep {
+                    job.cancel();
+                }
+                keep
+            });
         }
+
+        self.generate_queue = distance
+            .coords_around(center)
+            .into_iter()
+            .filter(|coord| !self.chunks.contains_key(coord) && !self.generating.contains_key(coord))
+            .collect();
+    }
+
+    fn collect_finished_jobs(&mut self) {
+        for finished in self.workers.finished() {
+            let coord = finished.coord;
+            match finished.result {
+                JobResult::Generated(blocks, light) => {
+                    if !self.generating.get(&coord).is_some_and(|job| job.is(&finished.token)) {
+                        continue;
+                    }
+                    self.generating.remove(&coord);
+
+                    let chunk = WorldChunk::new(coord, blocks, light);
+                    self.chunks.insert(coord, ChunkEntry::Generated(chunk));
+                    self.mesh_queue.insert(coord);
+
+                    let mut lit = LitChunks::new(&mut self.chunks);
+                    light::add_chunk(&mut lit, &self.registry, coord);
+                    let relit = lit.changed;
+
+                    // Loaded neighbours were meshed as if this chunk were air,
+                    // and others may be lit differently now
+                    for neighbour in neighbour_offsets().map(|offset| coord + offset) {
+                        if let Some(ChunkEntry::Loaded(_)) = self.chunks.get(&neighbour) {
+                            self.mesh_queue.insert(neighbour);
+                        }
+                    }
+                    self.mesh_queue
+                        .extend(relit.into_iter().filter(|coord| self.chunks.contains_key(coord)));
+                }
+                JobResult::Meshed(mesh) => {
+                    if !self.meshing.get(&coord).is_some_and(|job| job.is(&finished.token)) {
+                        continue;
+                    }
+                    self.meshing.remove(&coord);
+                    self.upload_queue.insert(coord, mesh);
+                }
+            }
+        }
+    }
+
+    /// Jobs of each kind allowed in flight. Keeping the worker queues short
+    /// means jobs are still dispatched nearest-first after the camera moves.
+    fn max_jobs_in_flight(&self) -> usize {
+        self.workers.thread_count() * 2
+    }
+
+    fn dispatch_generation(&mut self) {
+        while self.generating.len() < self.max_jobs_in_flight() {
+            let Some(coord) = self.generate_queue.pop_front() else {
+                break;
+            };
+            let job = self.workers.submit(coord, JobKind::Generate);
+            self.generating.insert(coord, job);
+        }
+    }
+
+    /// A chunk is ready to mesh once every neighbour that is in range has
+    /// been generated.
+    fn is_ready_to_mesh(&self, center: IVec3, coord: IVec3) -> bool {
+        neighbour_offsets().all(|offset| {
+            let neighbour = coord + offset;
+            !self.render_distance.contains(center, neighbour) || self.chunks.contains_key(&neighbour)
+        })
     }
 
-    fn load_pending_chunks(&mut self, gfx: &crate::GfxContext) {
-        if let Some(unloaded) = self.pending_chunks.pop() {
-            let loaded = unloaded.upload_to_gpu(gfx, &self.bind_group_layout);
-            self.chunks.push(loaded);
+    fn dispatch_meshing(&mut self) {
+        let Some(center) = self.center else {
+            return;
+        };
+
+        let free = self.max_jobs_in_flight().saturating_sub(self.meshing.len());
+        if free == 0 || self.mesh_queue.is_empty() {
+            return;
+        }
+
+        // A chunk already being meshed stays queued until that job is back
+        let mut ready: Vec<IVec3> = self
+            .mesh_queue
+            .iter()
+            .copied()
+            .filter(|coord| !self.meshing.contains_key(coord))
+            .filter(|coord| self.is_ready_to_mesh(center, *coord))
+            .collect();
+        ready.sort_by_key(|coord| {
+            (!self.mesh_first.contains(coord), (*coord - center).length_squared())
+        });
+
+        for coord in ready.into_iter().take(free) {
+            let Some(entry) = self.chunks.get(&coord) else {
+                continue;
+            };
+            self.mesh_queue.remove(&coord);
+            self.mesh_first.remove(&coord);
+
+            let mut input = MeshInput::new(entry.shared_blocks(), entry.shared_light());
+            for offset in neighbour_offsets() {
+                if let Some(neighbour) = self.chunks.get(&(coord + offset)) {
+                    input.set_neighbour(offset, neighbour.shared_blocks(), neighbour.shared_light());
+                }
+            }
+
+            let job = self
+                .workers
+                .submit(coord, JobKind::Mesh(Box::new(input), self.mesh_options));
+            self.meshing.insert(coord, job);
+        }
+    }
+
+    fn upload_meshes(&mut self, gfx: &crate::GfxContext) {
+        let Some(center) = self.center else {
+            return;
+        };
+        if self.upload_queue.is_empty() {
+            return;
+        }
+        let queued = self.upload_queue.len();
+        let _span = tracing::info_span!("upload_meshes", queued).entered();
+
+        let mut order: Vec<IVec3> = self.upload_queue.keys().copied().collect();
+        order.sort_by_key(|coord| (*coord - center).length_squared());
+
+        let mut uploaded = 0;
+        for coord in order {
+            if uploaded >= self.upload_budget {
+                break;
+            }
+
+            let mesh = self.upload_queue.remove(&coord).unwrap();
+            self.upload_mesh(gfx, coord, &mesh);
+            uploaded += mesh.size_in_bytes();
         }
+
+        if self.pending_count() == 0 {
+            tracing::debug!(
+                chunks = self.loaded_count(),
+                mode = ?self.mesh_options.mode,
+                ambient_occlusion = self.mesh_options.ambient_occlusion,
+                vertices = self.vertex_count(),
+                kib = self.vertex_buffer_size() / 1024,
+                "Meshed every chunk in range",
+            );
+        }
+    }
+
+    /// Replace the mesh of a chunk on the GPU, if the chunk still exists.
+    fn upload_mesh(&mut self, gfx: &crate::GfxContext, coord: IVec3, mesh: &ChunkMesh) {
+        let chunk = match self.chunks.remove(&coord) {
+            Some(ChunkEntry::Generated(chunk)) => chunk,
+            Some(ChunkEntry::Loaded(chunk)) => chunk.unload(gfx, &mut self.renderer.buffers),
+            None => return,
+        };
+
+        self.renderer.quad_indices.reserve(&gfx.device, mesh.quad_count());
+        let mut loaded = chunk.upload_to_gpu(gfx, &mut self.renderer.buffers, mesh);
+        loaded.sort_translucent(&gfx.queue, &self.renderer.buffers, self.eye);
+        self.chunks.insert(coord, ChunkEntry::Loaded(Box::new(loaded)));
+    }
+}
+
+/// The pipeline drawing one layer of the chunk meshes. Opaque quads are
+/// back-face culled. Cutout and translucent blocks like leaves and glass can
+/// be seen through, so their back faces are drawn too. Translucent quads are
+/// blended and don't write depth, so the ones behind them still show.
+fn create_layer_pipeline(
+    gfx: &crate::GfxContext,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    layer: RenderLayer,
+) -> wgpu::RenderPipeline {
+    let (label, entry_point, cull_mode, blend, depth_write_enabled) = match layer {
+        RenderLayer::Opaque => (
+            "world opaque pipeline",
+            "fs_opaque",
+            Some(wgpu::Face::Back),
+            wgpu::BlendState::REPLACE,
+            true,
+        ),
+        RenderLayer::Cutout => (
+            "world cutout pipeline",
+            "fs_cutout",
+            None,
+            wgpu::BlendState::REPLACE,
+            true,
+        ),
+        RenderLayer::Translucent => (
+            "world translucent pipeline",
+            "fs_translucent",
+            None,
+            wgpu::BlendState::ALPHA_BLENDING,
+            false,
+        ),
+    };
+
+    gfx.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[ChunkVertex::layout()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: gfx.config.format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::all(),
+            })],
+        }),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: crate::render::texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare: crate::render::texture::Texture::DEPTH_COMPARE,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: Default::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Offsets of the chunks whose meshes can change when the block at `local`
+/// changes: its own chunk, and the neighbours it borders.
+fn edit_offsets(local: glam::UVec3) -> impl Iterator<Item = IVec3> {
+    let last = CHUNK_SIZE as u32 - 1;
+    let range = |l: u32| match l {
+        0 => -1..=0,
+        l if l == last => 0..=1,
+        _ => 0..=0,
+    };
+    let (xs, ys, zs) = (range(local.x), range(local.y), range(local.z));
+    xs.flat_map(move |x| {
+        let zs = zs.clone();
+        ys.clone()
+            .flat_map(move |y| zs.clone().map(move |z| IVec3::new(x, y, z)))
+    })
+}
+
+/// Offsets of the 26 chunks surrounding a chunk.
+fn neighbour_offsets() -> impl Iterator<Item = IVec3> {
+    (-1..=1)
+        .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+        .filter(|offset| *offset != IVec3::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blocks::{ChunkBlocks, AIR};
+    use glam::UVec3;
+
+    fn air_chunks(coords: &[IVec3]) -> HashMap<IVec3, ChunkEntry> {
+        coords
+            .iter()
+            .map(|&coord| {
+                let chunk = WorldChunk::new(coord, ChunkBlocks::default(), ChunkLight::default());
+                (coord, ChunkEntry::Generated(chunk))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn border_edits_change_the_block_and_remesh_the_neighbour() {
+        let registry = BlockRegistry::builtin();
+        let stone = registry.id("stone").unwrap();
+        let (chunk, neighbour) = (IVec3::ZERO, IVec3::X);
+        let mut chunks = air_chunks(&[chunk, neighbour]);
+
+        let pos = IVec3::new(31, 5, 9);
+        let changed = edit_block(&mut chunks, &registry, pos, stone).unwrap();
+        assert_eq!(changed.edited, HashSet::from([chunk, neighbour]));
+        assert_eq!(block_at(&chunks, pos), Some(stone));
+        assert_eq!(block_at(&chunks, pos + IVec3::X), Some(AIR));
+        assert!(chunks[&chunk].is_dirty() && !chunks[&neighbour].is_dirty());
+
+        // Breaking it again
+        let changed = edit_block(&mut chunks, &registry, pos, AIR).unwrap();
+        assert!(changed.edited.contains(&neighbour));
+        assert_eq!(block_at(&chunks, pos), Some(AIR));
+
+        // Away from the border only the chunk itself is meshed again
+        let changed = edit_block(&mut chunks, &registry, IVec3::new(40, 5, 9), stone).unwrap();
+        assert_eq!(changed.edited, HashSet::from([neighbour]));
+
+        let unloaded = IVec3::new(-1, 5, 9);
+        assert_eq!(edit_block(&mut chunks, &registry, unloaded, stone), None);
+        assert_eq!(block_at(&chunks, unloaded), None);
+    }
+
+    #[test]
+    fn light_only_changes_are_kept_apart_from_edits() {
+        let registry = BlockRegistry::builtin();
+        let lamp = registry.id("lamp").unwrap();
+        let (chunk, neighbour) = (IVec3::ZERO, IVec3::X);
+        let mut chunks = air_chunks(&[chunk, neighbour]);
+
+        // The lamp's light reaches 14 blocks, across the border into `chunk`
+        let pos = IVec3::new(40, 5, 9);
+        let changed = edit_block(&mut chunks, &registry, pos, lamp).unwrap();
+        assert_eq!(changed.edited, HashSet::from([neighbour]));
+        assert_eq!(changed.relit, HashSet::from([chunk]));
+        assert!(!chunks[&chunk].is_dirty());
+
+        let changed = edit_block(&mut chunks, &registry, pos, AIR).unwrap();
+        assert_eq!(changed.relit, HashSet::from([chunk]));
+    }
+
+    #[test]
+    fn edits_remesh_bordering_chunks() {
+        let inner: Vec<_> = edit_offsets(UVec3::new(5, 17, 30)).collect();
+        assert_eq!(inner, vec![IVec3::ZERO]);
+
+        let face: Vec<_> = edit_offsets(UVec3::new(0, 4, 9)).collect();
+        assert_eq!(face, vec![IVec3::NEG_X, IVec3::ZERO]);
+
+        // A corner block touches seven other chunks, diagonals included
+        let corner: Vec<_> = edit_offsets(UVec3::new(31, 0, 31)).collect();
+        assert_eq!(corner.len(), 8);
+        assert!(corner.contains(&IVec3::new(1, -1, 1)));
+        assert!(corner.iter().all(|o| o.x >= 0 && o.y <= 0 && o.z >= 0));
     }
 }
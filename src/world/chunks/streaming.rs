@@ -0,0 +1,117 @@
+use glam::{IVec3, Vec3};
+
+use super::coords::{world_to_chunk, CHUNK_SIZE};
+
+/// How far around the camera chunks are kept loaded, in chunks. The loaded
+/// area is a cylinder: a disc of `horizontal` chunks radius, `vertical`
+/// chunks above and below the camera's chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderDistance {
+    pub horizontal: u32,
+    pub vertical: u32,
+}
+
+impl Default for RenderDistance {
+    fn default() -> Self {
+        Self {
+            horizontal: 8,
+            vertical: 3,
+        }
+    }
+}
+
+impl RenderDistance {
+    pub const MIN: u32 = 2;
+    pub const MAX: u32 = 32;
+
+    pub fn with_horizontal(self, horizontal: u32) -> Self {
+        Self {
+            horizontal: horizontal.clamp(Self::MIN, Self::MAX),
+            ..self
+        }
+    }
+
+    pub fn contains(&self, center: IVec3, coord: IVec3) -> bool {
+        let d = coord - center;
+        let r = self.horizontal as i32;
+        d.x * d.x + d.z * d.z <= r * r && d.y.unsigned_abs() <= self.vertical
+    }
+
+    /// Every chunk coordinate in range of `center`, nearest first.
+    pub fn coords_around(&self, center: IVec3) -> Vec<IVec3> {
+        let r = self.horizontal as i32;
+        let v = self.vertical as i32;
+
+        let mut coords: Vec<IVec3> = (-r..=r)
+            .flat_map(|x| (-v..=v).flat_map(move |y| (-r..=r).map(move |z| IVec3::new(x, y, z))))
+            .map(|offset| center + offset)
+            .filter(|coord| self.contains(center, *coord))
+            .collect();
+
+        coords.sort_by_key(|coord| (*coord - center).length_squared());
+        coords
+    }
+
+    /// Farthest distance, in blocks, at which a loaded chunk can be seen.
+    pub fn far_distance(&self) -> f32 {
+        let r = (self.horizontal + 1) as f32;
+        let v = (self.vertical + 1) as f32;
+        (r * r + v * v).sqrt() * CHUNK_SIZE as f32
+    }
+}
+
+pub fn camera_chunk(position: Vec3) -> IVec3 {
+    world_to_chunk(position.floor().as_ivec3())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_is_a_cylinder() {
+        let distance = RenderDistance {
+            horizontal: 4,
+            vertical: 1,
+        };
+        let center = IVec3::new(10, -3, 7);
+
+        assert!(distance.contains(center, center));
+        assert!(distance.contains(center, center + IVec3::new(4, 1, 0)));
+        assert!(distance.contains(center, center + IVec3::new(0, -1, -4)));
+        assert!(!distance.contains(center, center + IVec3::new(0, 2, 0)));
+        assert!(!distance.contains(center, center + IVec3::new(4, 0, 4)));
+        assert!(!distance.contains(center, center + IVec3::new(5, 0, 0)));
+    }
+
+    #[test]
+    fn coords_around_are_in_range_and_nearest_first() {
+        let distance = RenderDistance {
+            horizontal: 3,
+            vertical: 2,
+        };
+        let center = IVec3::new(-2, 0, 5);
+        let coords = distance.coords_around(center);
+
+        assert_eq!(coords[0], center);
+        assert!(coords.iter().all(|c| distance.contains(center, *c)));
+
+        let distances: Vec<i32> = coords.iter().map(|c| (*c - center).length_squared()).collect();
+        assert!(distances.windows(2).all(|w| w[0] <= w[1]));
+
+        // 29 columns in a radius 3 disc, 5 chunks high
+        assert_eq!(coords.len(), 29 * 5);
+    }
+
+    #[test]
+    fn camera_chunk_of_negative_positions() {
+        assert_eq!(camera_chunk(Vec3::new(-0.5, 31.9, 32.0)), IVec3::new(-1, 0, 1));
+    }
+
+    #[test]
+    fn horizontal_distance_is_clamped() {
+        let distance = RenderDistance::default();
+        assert_eq!(distance.with_horizontal(0).horizontal, RenderDistance::MIN);
+        assert_eq!(distance.with_horizontal(100).horizontal, RenderDistance::MAX);
+    }
+}
@@ -0,0 +1,353 @@
+use glam::IVec3;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use super::blocks::ChunkBlocks;
+use super::light::ChunkLight;
+use super::mesher::{self, ChunkMesh, ChunkNeighbourhood, MeshOptions};
+use crate::world::gen::TerrainGenerator;
+use crate::world::registry::BlockRegistry;
+use crate::world::save::WorldSave;
+
+/// Snapshot of the blocks and light of a chunk and its 26 neighbours, indexed
+/// like [`ChunkNeighbourhood`], to be meshed off the render thread.
+pub struct MeshInput {
+    chunks: [Option<(Arc<ChunkBlocks>, Arc<ChunkLight>)>; 27],
+}
+
+impl MeshInput {
+    pub fn new(center: Arc<ChunkBlocks>, light: Arc<ChunkLight>) -> Self {
+        let mut chunks = std::array::from_fn(|_| None);
+        chunks[Self::slot(IVec3::ZERO)] = Some((center, light));
+        Self { chunks }
+    }
+
+    pub fn set_neighbour(&mut self, offset: IVec3, blocks: Arc<ChunkBlocks>, light: Arc<ChunkLight>) {
+        self.chunks[Self::slot(offset)] = Some((blocks, light));
+    }
+
+    fn slot(offset: IVec3) -> usize {
+        let o = offset + IVec3::ONE;
+        (o.x + o.y * 3 + o.z * 9) as usize
+    }
+
+    fn neighbourhood(&self) -> ChunkNeighbourhood<'_> {
+        let (center, _) = self.chunks[Self::slot(IVec3::ZERO)]
+            .as_ref()
+            .expect("mesh input without a center chunk");
+
+        let mut neighbourhood = ChunkNeighbourhood::new(center);
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if let Some((blocks, light)) = chunk {
+                let i = i as i32;
+                let offset = IVec3::new(i % 3, (i / 3) % 3, i / 9) - IVec3::ONE;
+                neighbourhood = neighbourhood
+                    .with_neighbour(offset, blocks)
+                    .with_light(offset, light);
+            }
+        }
+        neighbourhood
+    }
+}
+
+pub enum JobKind {
+    Generate,
+    Mesh(Box<MeshInput>, MeshOptions),
+}
+
+pub enum JobResult {
+    /// Blocks with the light they get on their own, see
+    /// [`ChunkLight::compute`].
+    Generated(ChunkBlocks, ChunkLight),
+    Meshed(ChunkMesh),
+}
+
+struct Job {
+    coord: IVec3,
+    kind: JobKind,
+    cancelled: Arc<AtomicBool>,
+}
+
+pub struct Finished {
+    pub coord: IVec3,
+    pub result: JobResult,
+    /// Same token as the [`JobHandle`] returned when the job was queued.
+    pub token: Arc<AtomicBool>,
+}
+
+/// Handle to a queued job. Cancelling it makes workers skip the job if it
+/// hasn't started yet and drop its result otherwise.
+pub struct JobHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl JobHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is(&self, token: &Arc<AtomicBool>) -> bool {
+        Arc::ptr_eq(&self.cancelled, token)
+    }
+}
+
+/// Pool of threads generating and meshing chunks. Finished jobs are sent back
+/// over a channel and collected with [`ChunkWorkers::finished`].
+///
+/// Generate jobs load the chunk from the save first, if there is one, and
+/// only run the generator for chunks that were never saved.
+pub struct ChunkWorkers {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Finished>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ChunkWorkers {
+    pub fn new(
+        generator: TerrainGenerator,
+        registry: Arc<BlockRegistry>,
+        save: Option<Arc<WorldSave>>,
+    ) -> Self {
+        let count = std::thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(1))
+            .unwrap_or(1)
+            .max(1);
+
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let (result_tx, result_rx) = mpsc::channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let threads = (0..count)
+            .map(|i| {
+                let jobs = job_rx.clone();
+                let results = result_tx.clone();
+                let generator = generator.clone();
+                let registry = registry.clone();
+                let save = save.clone();
+                std::thread::Builder::new()
+                    .name(format!("chunk worker {i}"))
+                    .spawn(move || Self::run(jobs, results, generator, registry, save))
+                    .expect("Could not spawn chunk worker thread")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_tx),
+            results: result_rx,
+            threads,
+        }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn submit(&self, coord: IVec3, kind: JobKind) -> JobHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let job = Job {
+            coord,
+            kind,
+            cancelled: cancelled.clone(),
+        };
+
+        self.jobs
+            .as_ref()
+            .expect("chunk workers shut down")
+            .send(job)
+            .expect("All chunk workers exited");
+
+        JobHandle { cancelled }
+    }
+
+    /// Jobs finished since the last call, excluding cancelled ones.
+    pub fn finished(&self) -> impl Iterator<Item = Finished> + '_ {
+        self.results
+            .try_iter()
+            .filter(|finished| !finished.token.load(Ordering::Relaxed))
+    }
+
+    fn run(
+        jobs: Arc<Mutex<Receiver<Job>>>,
+        results: Sender<Finished>,
+        generator: TerrainGenerator,
+        registry: Arc<BlockRegistry>,
+        save: Option<Arc<WorldSave>>,
+    ) {
+        loop {
+            let job = {
+                let Ok(jobs) = jobs.lock() else {
+                    return;
+                };
+                match jobs.recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                }
+            };
+
+            if job.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let result = match job.kind {
+                JobKind::Generate => {
+                    let _span =
+                        tracing::debug_span!("generate_chunk", coord = %job.coord).entered();
+                    let blocks = Self::load_or_generate(job.coord, &generator, save.as_deref());
+                    let light = ChunkLight::compute(&blocks, &registry);
+                    JobResult::Generated(blocks, light)
+                }
+                JobKind::Mesh(input, options) => {
+                    let _span = tracing::debug_span!("mesh_chunk", coord = %job.coord).entered();
+                    JobResult::Meshed(mesher::mesh_chunk(&input.neighbourhood(), &registry, options))
+                }
+            };
+
+            let finished = Finished {
+                coord: job.coord,
+                result,
+                token: job.cancelled,
+            };
+
+            if results.send(finished).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn load_or_generate(
+        coord: IVec3,
+        generator: &TerrainGenerator,
+        save: Option<&WorldSave>,
+    ) -> ChunkBlocks {
+        match save.map(|save| save.load_chunk(coord)) {
+            Some(Ok(Some(blocks))) => blocks,
+            Some(Err(err)) => {
+                tracing::warn!("{err:#}, generating it again");
+                generator.generate_chunk(coord)
+            }
+            Some(Ok(None)) | None => generator.generate_chunk(coord),
+        }
+    }
+}
+
+impl Drop for ChunkWorkers {
+    fn drop(&mut self) {
+        // Closing the job channel makes every worker return
+        self.jobs.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    use crate::world::save::tests::TempDir;
+
+    fn workers() -> ChunkWorkers {
+        let registry = Arc::new(BlockRegistry::builtin());
+        ChunkWorkers::new(TerrainGenerator::new(0, &registry), registry, None)
+    }
+
+    fn wait_for(workers: &ChunkWorkers, count: usize) -> Vec<Finished> {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut finished = vec![];
+        while finished.len() < count && Instant::now() < deadline {
+            finished.extend(workers.finished());
+            std::thread::yield_now();
+        }
+        finished
+    }
+
+    #[test]
+    fn generates_the_same_blocks_as_the_generator() {
+        let registry = Arc::new(BlockRegistry::builtin());
+        let generator = TerrainGenerator::new(11, &registry);
+        let workers = ChunkWorkers::new(generator.clone(), registry, None);
+
+        let coord = IVec3::new(3, -1, -2);
+        workers.submit(coord, JobKind::Generate);
+
+        let finished = wait_for(&workers, 1);
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].coord, coord);
+        match &finished[0].result {
+            JobResult::Generated(blocks, _) => {
+                assert_eq!(blocks.as_slice(), generator.generate_chunk(coord).as_slice())
+            }
+            JobResult::Meshed(_) => panic!("expected generated blocks"),
+        }
+    }
+
+    #[test]
+    fn loads_saved_chunks_instead_of_generating() {
+        let dir = TempDir::new("workers_load");
+        let save = Arc::new(WorldSave::open(dir.path()).unwrap());
+        let saved = ChunkBlocks::filled(9);
+        save.save_chunk(IVec3::new(0, 4, 0), &saved).unwrap();
+
+        let registry = Arc::new(BlockRegistry::builtin());
+        let generator = TerrainGenerator::new(5, &registry);
+        let workers = ChunkWorkers::new(generator.clone(), registry, Some(save));
+
+        workers.submit(IVec3::new(0, 4, 0), JobKind::Generate);
+        workers.submit(IVec3::new(0, -1, 0), JobKind::Generate);
+
+        for finished in wait_for(&workers, 2) {
+            let JobResult::Generated(blocks, _) = finished.result else {
+                panic!("expected generated blocks");
+            };
+            let expected = match finished.coord.y {
+                4 => saved.as_slice().to_vec(),
+                _ => generator.generate_chunk(finished.coord).as_slice().to_vec(),
+            };
+            assert_eq!(blocks.as_slice(), expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn meshes_with_neighbours() {
+        let workers = workers();
+
+        let full = Arc::new(ChunkBlocks::filled(1));
+        let dark = Arc::new(ChunkLight::default());
+        let mut input = MeshInput::new(full.clone(), dark.clone());
+        for offset in [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z] {
+            input.set_neighbour(offset, full.clone(), dark.clone());
+        }
+        workers.submit(IVec3::ZERO, JobKind::Mesh(Box::new(input), MeshOptions::default()));
+
+        let finished = wait_for(&workers, 1);
+        match &finished[0].result {
+            JobResult::Meshed(mesh) => assert!(mesh.is_empty()),
+            JobResult::Generated(..) => panic!("expected a mesh"),
+        }
+    }
+
+    #[test]
+    fn cancelled_jobs_produce_no_results() {
+        let workers = workers();
+
+        let handles: Vec<_> = (0..16)
+            .map(|x| workers.submit(IVec3::new(x, 0, 0), JobKind::Generate))
+            .collect();
+        for handle in handles.iter().step_by(2) {
+            handle.cancel();
+        }
+
+        let finished = wait_for(&workers, 8);
+        std::thread::sleep(Duration::from_millis(50));
+        let late: Vec<_> = workers.finished().collect();
+
+        assert!(late.is_empty());
+        assert_eq!(finished.len(), 8);
+        assert!(finished.iter().all(|f| f.coord.x % 2 == 1));
+    }
+}
@@ -0,0 +1,274 @@
+pub mod noise;
+
+use glam::{IVec3, UVec3};
+
+use crate::world::chunks::{
+    blocks::{BlockId, ChunkBlocks, AIR},
+    coords::{chunk_origin, CHUNK_SIZE},
+};
+
+use crate::world::registry::BlockRegistry;
+
+use noise::{split_mix64, Fbm};
+
+/// Ids of the blocks the generator places.
+#[derive(Clone, Copy)]
+struct TerrainBlocks {
+    stone: BlockId,
+    dirt: BlockId,
+    grass: BlockId,
+    water: BlockId,
+    sand: BlockId,
+}
+
+impl TerrainBlocks {
+    fn resolve(registry: &BlockRegistry) -> Self {
+        let id = |name| {
+            registry
+                .id(name)
+                .unwrap_or_else(|| panic!("Terrain generation needs a {name:?} block"))
+        };
+        Self {
+            stone: id("stone"),
+            dirt: id("dirt"),
+            grass: id("grass"),
+            water: id("water"),
+            sand: id("sand"),
+        }
+    }
+}
+
+/// Deterministic terrain: a 2D heightmap roughened by 3D noise, covered with
+/// grass over a few layers of dirt, and flooded with water up to sea level.
+/// The same seed always generates the same blocks for a chunk coordinate.
+#[derive(Clone)]
+pub struct TerrainGenerator {
+    seed: u64,
+    blocks: TerrainBlocks,
+    continents: Fbm,
+    hills: Fbm,
+    detail: Fbm,
+}
+
+impl TerrainGenerator {
+    pub const SEA_LEVEL: i32 = 0;
+
+    const DIRT_DEPTH: u32 = 3;
+    const DETAIL_AMPLITUDE: f64 = 8.0;
+
+    pub fn new(seed: u64, registry: &BlockRegistry) -> Self {
+        let mut state = seed;
+        Self {
+            seed,
+            blocks: TerrainBlocks::resolve(registry),
+            continents: Fbm::new(split_mix64(&mut state), 3, 1.0 / 512.0),
+            hills: Fbm::new(split_mix64(&mut state), 4, 1.0 / 96.0),
+            detail: Fbm::new(split_mix64(&mut state), 3, 1.0 / 32.0),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Height of the 2D heightmap at a world column, before 3D detail.
+    pub fn surface_height(&self, x: i32, z: i32) -> f64 {
+        let (x, z) = (x as f64, z as f64);
+        let continent = self.continents.get2(x, z);
+        let hills = self.hills.get2(x, z).abs();
+        Self::SEA_LEVEL as f64 + 4.0 + continent * 40.0 + hills * (10.0 + continent.max(0.0) * 40.0)
+    }
+
+    fn is_solid(&self, world: IVec3, surface: f64) -> bool {
+        let density = surface - world.y as f64;
+        if density > Self::DETAIL_AMPLITUDE {
+            return true;
+        }
+        if density < -Self::DETAIL_AMPLITUDE {
+            return false;
+        }
+
+        let detail = self.detail.get3(world.x as f64, world.y as f64, world.z as f64);
+        density + detail * Self::DETAIL_AMPLITUDE > 0.0
+    }
+
+    pub fn generate_chunk(&self, coord: IVec3) -> ChunkBlocks {
+        let mut blocks = ChunkBlocks::default();
+        let origin = chunk_origin(coord);
+        let TerrainBlocks {
+            stone,
+            dirt,
+            grass,
+            water,
+            sand,
+        } = self.blocks;
+
+        // Bail out early for chunks far above or below any surface
+        let lowest = origin.y as f64 - Self::DETAIL_AMPLITUDE;
+        let highest = (origin.y + CHUNK_SIZE as i32) as f64 + Self::DETAIL_AMPLITUDE;
+
+        for z in 0..CHUNK_SIZE as u32 {
+            for x in 0..CHUNK_SIZE as u32 {
+                let world_x = origin.x + x as i32;
+                let world_z = origin.z + z as i32;
+                let surface = self.surface_height(world_x, world_z);
+
+                if surface >= highest + Self::DIRT_DEPTH as f64 {
+                    for y in 0..CHUNK_SIZE as u32 {
+                        blocks.set(UVec3::new(x, y, z), stone);
+                    }
+                    continue;
+                }
+                if surface < lowest && origin.y > Self::SEA_LEVEL {
+                    continue;
+                }
+
+                // Walk down the column, starting far enough above the chunk
+                // that the grass and dirt layers continue across chunk borders.
+                let mut depth = 0;
+                let top = CHUNK_SIZE as i32 + Self::DIRT_DEPTH as i32 + 1;
+                for y in (0..top).rev() {
+                    let world = IVec3::new(world_x, origin.y + y, world_z);
+
+                    let block = if self.is_solid(world, surface) {
+                        depth += 1;
+                        match depth {
+                            1 if world.y >= Self::SEA_LEVEL => grass,
+                            1 if world.y >= Self::SEA_LEVEL - 3 => sand,
+                            d if d <= Self::DIRT_DEPTH + 1 => dirt,
+                            _ => stone,
+                        }
+                    } else {
+                        depth = 0;
+                        if world.y <= Self::SEA_LEVEL {
+                            water
+                        } else {
+                            AIR
+                        }
+                    };
+
+                    if y < CHUNK_SIZE as i32 && block != AIR {
+                        blocks.set(UVec3::new(x, y as u32, z), block);
+                    }
+                }
+            }
+        }
+
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::world::chunks::coords::{index_to_local, local_to_world};
+
+    const STONE: BlockId = 1;
+    const DIRT: BlockId = 2;
+    const GRASS: BlockId = 3;
+    const WATER: BlockId = 4;
+    const SAND: BlockId = 5;
+
+    fn generator(seed: u64) -> TerrainGenerator {
+        TerrainGenerator::new(seed, &BlockRegistry::builtin())
+    }
+
+    fn column(generator: &TerrainGenerator, x: i32, z: i32) -> Vec<(i32, BlockId)> {
+        (-3..=3)
+            .flat_map(|cy| {
+                let blocks = generator.generate_chunk(IVec3::new(x.div_euclid(32), cy, z.div_euclid(32)));
+                let (lx, lz) = (x.rem_euclid(32) as u32, z.rem_euclid(32) as u32);
+                (0..CHUNK_SIZE as u32).map(move |y| {
+                    let local = UVec3::new(lx, y, lz);
+                    (local_to_world(IVec3::new(0, cy, 0), local).y, blocks.get(local))
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_generates_identical_chunks() {
+        let a = generator(1234);
+        let b = generator(1234);
+
+        for coord in [IVec3::new(0, 0, 0), IVec3::new(-3, -1, 7), IVec3::new(12, 1, -40)] {
+            assert_eq!(
+                a.generate_chunk(coord).as_slice(),
+                b.generate_chunk(coord).as_slice(),
+                "{coord}"
+            );
+        }
+    }
+
+    #[test]
+    fn different_seeds_generate_different_terrain() {
+        let a = generator(1);
+        let b = generator(2);
+
+        let differs = (-2..=2).any(|x| {
+            (-1..=0).any(|y| {
+                let coord = IVec3::new(x, y, 0);
+                a.generate_chunk(coord).as_slice() != b.generate_chunk(coord).as_slice()
+            })
+        });
+        assert!(differs);
+    }
+
+    #[test]
+    fn generation_does_not_depend_on_order() {
+        let generator = generator(99);
+        let first = generator.generate_chunk(IVec3::new(5, 0, 5));
+        generator.generate_chunk(IVec3::new(-8, -2, 3));
+        assert_eq!(first.as_slice(), generator.generate_chunk(IVec3::new(5, 0, 5)).as_slice());
+    }
+
+    #[test]
+    fn columns_are_layered() {
+        let generator = generator(5);
+
+        for (x, z) in [(0, 0), (17, -40), (-100, 33), (250, 250)] {
+            let column = column(&generator, x, z);
+
+            // Below the deepest solid block there is nothing but stone
+            assert_eq!(column.first().unwrap().1, STONE, "({x}, {z})");
+
+            for pair in column.windows(2) {
+                let ((_, below), (y, above)) = (pair[0], pair[1]);
+                match above {
+                    AIR => assert!(
+                        matches!(below, AIR | GRASS | WATER),
+                        "uncovered {below} at ({x}, {}, {z})",
+                        y - 1
+                    ),
+                    WATER => assert!(
+                        matches!(below, WATER | SAND | DIRT),
+                        "{below} under water at ({x}, {}, {z})",
+                        y - 1
+                    ),
+                    _ => (),
+                }
+                if below == GRASS {
+                    assert!(above == AIR, "buried grass at ({x}, {y}, {z})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sea_is_filled_with_water_only_up_to_sea_level() {
+        let generator = generator(3);
+        for cy in -2..=2 {
+            for cx in -4..=4 {
+                let coord = IVec3::new(cx, cy, 0);
+                let blocks = generator.generate_chunk(coord);
+                for (i, block) in blocks.as_slice().iter().enumerate() {
+                    if *block == WATER {
+                        let world = local_to_world(coord, index_to_local(i));
+                        assert!(world.y <= TerrainGenerator::SEA_LEVEL);
+                    }
+                }
+            }
+        }
+    }
+}
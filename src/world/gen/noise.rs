@@ -0,0 +1,220 @@
+/// Seeded Perlin gradient noise. Output is roughly in `-1.0..=1.0`.
+#[derive(Clone)]
+pub struct Perlin {
+    perm: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table: [u8; 256] = std::array::from_fn(|i| i as u8);
+
+        // Fisher-Yates shuffle driven by SplitMix64
+        let mut state = seed;
+        for i in (1..table.len()).rev() {
+            let j = (split_mix64(&mut state) % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i & 255];
+        }
+
+        Self { perm }
+    }
+
+    pub fn get2(&self, x: f64, y: f64) -> f64 {
+        let (xi, xf) = split(x);
+        let (yi, yf) = split(y);
+
+        let u = fade(xf);
+        let v = fade(yf);
+
+        let p = &self.perm;
+        let a = p[xi] as usize + yi;
+        let b = p[xi + 1] as usize + yi;
+
+        let x1 = lerp(u, grad2(p[a], xf, yf), grad2(p[b], xf - 1.0, yf));
+        let x2 = lerp(
+            u,
+            grad2(p[a + 1], xf, yf - 1.0),
+            grad2(p[b + 1], xf - 1.0, yf - 1.0),
+        );
+
+        lerp(v, x1, x2)
+    }
+
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (xi, xf) = split(x);
+        let (yi, yf) = split(y);
+        let (zi, zf) = split(z);
+
+        let u = fade(xf);
+        let v = fade(yf);
+        let w = fade(zf);
+
+        let p = &self.perm;
+        let a = p[xi] as usize + yi;
+        let aa = p[a] as usize + zi;
+        let ab = p[a + 1] as usize + zi;
+        let b = p[xi + 1] as usize + yi;
+        let ba = p[b] as usize + zi;
+        let bb = p[b + 1] as usize + zi;
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad3(p[aa], xf, yf, zf), grad3(p[ba], xf - 1.0, yf, zf)),
+                lerp(
+                    u,
+                    grad3(p[ab], xf, yf - 1.0, zf),
+                    grad3(p[bb], xf - 1.0, yf - 1.0, zf),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    grad3(p[aa + 1], xf, yf, zf - 1.0),
+                    grad3(p[ba + 1], xf - 1.0, yf, zf - 1.0),
+                ),
+                lerp(
+                    u,
+                    grad3(p[ab + 1], xf, yf - 1.0, zf - 1.0),
+                    grad3(p[bb + 1], xf - 1.0, yf - 1.0, zf - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+/// Several octaves of [`Perlin`] noise summed together, each at double the
+/// frequency and half the amplitude of the previous one.
+#[derive(Clone)]
+pub struct Fbm {
+    octaves: Vec<Perlin>,
+    frequency: f64,
+}
+
+impl Fbm {
+    pub fn new(seed: u64, octaves: usize, frequency: f64) -> Self {
+        let mut state = seed;
+        Self {
+            octaves: (0..octaves)
+                .map(|_| Perlin::new(split_mix64(&mut state)))
+                .collect(),
+            frequency,
+        }
+    }
+
+    pub fn get2(&self, x: f64, y: f64) -> f64 {
+        self.sum(|perlin, f| perlin.get2(x * f, y * f))
+    }
+
+    pub fn get3(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.sum(|perlin, f| perlin.get3(x * f, y * f, z * f))
+    }
+
+    fn sum(&self, sample: impl Fn(&Perlin, f64) -> f64) -> f64 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut max = 0.0;
+        let mut frequency = self.frequency;
+
+        for perlin in &self.octaves {
+            total += sample(perlin, frequency) * amplitude;
+            max += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        total / max
+    }
+}
+
+pub fn split_mix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Lattice cell (wrapped to the permutation table) and offset inside it.
+fn split(x: f64) -> (usize, f64) {
+    let floor = x.floor();
+    ((floor as i64 & 255) as usize, x - floor)
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad2(hash: u8, x: f64, y: f64) -> f64 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+fn grad3(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    match hash & 15 {
+        0 | 12 => x + y,
+        1 | 14 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 | 13 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_deterministic_per_seed() {
+        let a = Perlin::new(42);
+        let b = Perlin::new(42);
+        let c = Perlin::new(43);
+
+        let samples = [(0.5, 1.25, -3.75), (10.1, -7.3, 2.2), (-100.9, 0.01, 55.5)];
+        for (x, y, z) in samples {
+            assert_eq!(a.get2(x, y), b.get2(x, y));
+            assert_eq!(a.get3(x, y, z), b.get3(x, y, z));
+        }
+        assert!(samples.iter().any(|&(x, y, z)| a.get3(x, y, z) != c.get3(x, y, z)));
+    }
+
+    #[test]
+    fn noise_is_zero_on_lattice_points_and_bounded() {
+        let perlin = Perlin::new(7);
+        assert_eq!(perlin.get2(3.0, -4.0), 0.0);
+        assert_eq!(perlin.get3(1.0, 2.0, -8.0), 0.0);
+
+        let fbm = Fbm::new(7, 4, 0.013);
+        for i in 0..2000 {
+            let x = i as f64 * 0.731;
+            let z = i as f64 * -1.379;
+            assert!(fbm.get2(x, z).abs() <= 1.0);
+            assert!(fbm.get3(x, z, x * 0.5).abs() <= 1.0);
+        }
+    }
+}
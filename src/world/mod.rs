@@ -1,35 +1,327 @@
 pub mod camera;
 pub mod chunks;
+pub mod gen;
+pub mod outline;
+pub mod player;
+pub mod raycast;
+pub mod registry;
+pub mod save;
 
 use crate::{
     input::InputState,
-    world::{camera::Camera, chunks::Chunks},
+    world::chunks::blocks::{BlockId, AIR},
+    world::{
+        camera::Camera,
+        chunks::{ChunkStats, Chunks, CullingStats},
+        gen::TerrainGenerator,
+        outline::BlockOutline,
+        player::{Aabb, MovementMode, Player, PlayerControls},
+        raycast::{raycast, RayHit},
+        registry::BlockRegistry,
+        save::{CameraState, WorldHeader, WorldSave},
+    },
 };
 
-use glam::{Quat, Vec3};
+use anyhow::Result;
+
+use std::sync::Arc;
+
+use glam::{IVec3, Quat, Vec3};
+use winit::event::MouseButton;
 
 pub struct World {
     chunks: Chunks,
     pub camera: Camera,
+
+    /// Block the camera is looking at, updated every frame.
+    target: Option<RayHit>,
+    /// Block placed with the right mouse button.
+    selected_block: BlockId,
+    movement_mode: MovementMode,
+    player: Player,
+    outline: BlockOutline,
 }
 
 impl World {
-    pub fn new(gfx: &crate::GfxContext) -> Self {
-        Self {
-            chunks: Chunks::init(gfx),
-            camera: Camera::default(),
+    pub const DEFAULT_SEED: u64 = 0x05ba_1105_750e;
+    /// How far away blocks can be targeted, in blocks.
+    pub const REACH: f32 = 8.0;
+
+    /// Open the world in `save`. `seed` is only used if the save is new,
+    /// otherwise the seed and camera are restored from its header.
+    pub fn new(
+        gfx: &crate::GfxContext,
+        seed: u64,
+        registry: BlockRegistry,
+        save: WorldSave,
+    ) -> Result<Self> {
+        let header = save.read_header()?;
+        let seed = header.as_ref().map_or(seed, |header| header.seed);
+
+        let registry = Arc::new(registry);
+        let generator = TerrainGenerator::new(seed, &registry);
+
+        let mut camera = Camera::default();
+        match header {
+            Some(WorldHeader { camera: state, .. }) => {
+                camera.set_position(state.position.into());
+                camera.set_orientation(state.pitch, state.yaw);
+            }
+            None => {
+                let spawn_height = generator
+                    .surface_height(0, 0)
+                    .max(TerrainGenerator::SEA_LEVEL as f64);
+                camera.set_position(Vec3::new(0.5, spawn_height as f32 + 10.0, 0.5));
+            }
         }
+
+        let chunks = Chunks::init(gfx, generator, registry, Arc::new(save));
+        camera.projection.far = chunks.render_distance().far_distance();
+        let selected_block = default_selected_block(chunks.registry());
+
+        Ok(Self {
+            chunks,
+            camera,
+            target: None,
+            selected_block,
+            movement_mode: MovementMode::default(),
+            player: Player::default(),
+            outline: BlockOutline::new(gfx),
+        })
     }
 
+    /// Write the header and every modified chunk to disk.
+    pub fn save(&mut self) -> Result<()> {
+        let (pitch, yaw) = self.camera.orientation();
+        let header = WorldHeader {
+            version: WorldHeader::VERSION,
+            seed: self.seed(),
+            camera: CameraState {
+                position: self.camera.position().into(),
+                pitch,
+                yaw,
+            },
+        };
+
+        self.chunks.save().write_header(&header)?;
+        self.chunks.save_all()
+    }
+
+    pub fn registry(&self) -> &BlockRegistry {
+        self.chunks.registry()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.chunks.generator().seed()
+    }
+
+    /// Advance the simulation by `delta` seconds.
     pub fn update(&mut self, delta: f32, input: &InputState) {
-        self.camera.update(delta, input);
+        match self.movement_mode {
+            MovementMode::Fly => self.camera.update(delta, input),
+            MovementMode::Walk => {
+                let (_, yaw) = self.camera.orientation();
+                let controls = PlayerControls::from_input(input, yaw);
+                let (chunks, registry) = (&self.chunks, self.chunks.registry());
+                // Unloaded chunks are solid, so the player waits for the
+                // ground to load instead of falling through it
+                self.player.update(delta, &controls, |block| {
+                    chunks.get_block(block).is_none_or(|id| registry.is_solid(id))
+                });
+                self.camera.follow(self.player.eye_position());
+            }
+        }
+    }
+
+    pub fn movement_mode(&self) -> MovementMode {
+        self.movement_mode
+    }
+
+    /// Switch between flying and walking, keeping the camera where it is.
+    pub fn toggle_movement_mode(&mut self) {
+        self.movement_mode = match self.movement_mode {
+            MovementMode::Fly => {
+                let eye_offset = self.player.eye_position() - self.player.position();
+                self.player.set_position(self.camera.position() - eye_offset);
+                MovementMode::Walk
+            }
+            MovementMode::Walk => MovementMode::Fly,
+        };
+    }
+
+    /// Apply this frame's mouse look, and set how far the frame is between
+    /// the last two simulation steps.
+    pub fn update_frame(&mut self, input: &InputState, alpha: f32) {
+        self.camera.look(input);
+        self.camera.set_interpolation(alpha);
+
+        if input.was_button_clicked(MouseButton::Left) {
+            self.break_block();
+        }
+        if input.was_button_clicked(MouseButton::Right) {
+            self.place_block();
+        }
+        if input.was_button_clicked(MouseButton::Middle) {
+            self.pick_block();
+        }
+    }
+
+    pub fn selected_block(&self) -> BlockId {
+        self.selected_block
+    }
+
+    pub fn select_block(&mut self, block: BlockId) {
+        self.selected_block = block;
+    }
+
+    /// Select the `index`th block of the registry, skipping air.
+    pub fn select_block_index(&mut self, index: usize) {
+        let block = self.registry().iter().map(|b| b.id).filter(|id| *id != AIR).nth(index);
+        if let Some(block) = block {
+            self.selected_block = block;
+        }
+    }
+
+    /// Remove the targeted block.
+    pub fn break_block(&mut self) {
+        if let Some(hit) = self.find_target() {
+            self.chunks.set_block(hit.block, AIR);
+            self.target = self.find_target();
+        }
+    }
+
+    /// Place the selected block against the targeted face, if there's room.
+    pub fn place_block(&mut self) {
+        let Some(hit) = self.find_target() else {
+            return;
+        };
+
+        let position = hit.adjacent();
+        let replaceable = self
+            .chunks
+            .get_block(position)
+            .is_some_and(|id| id == AIR || self.registry().get(id).liquid);
+        let obstructed = match self.movement_mode {
+            MovementMode::Fly => self.camera.position().floor().as_ivec3() == position,
+            MovementMode::Walk => self.player.aabb().intersects(&Aabb::block(position)),
+        };
+
+        if replaceable && !obstructed {
+            self.chunks.set_block(position, self.selected_block);
+            self.target = self.find_target();
+        }
+    }
+
+    /// Select the targeted block for placing.
+    pub fn pick_block(&mut self) {
+        if let Some(hit) = self.find_target() {
+            if let Some(id) = self.chunks.get_block(hit.block) {
+                self.selected_block = id;
+            }
+        }
+    }
+
+    /// Set the block at `pos`, as if the player had placed or broken it.
+    /// Returns false if its chunk isn't loaded.
+    pub fn set_block(&mut self, pos: IVec3, block: BlockId) -> bool {
+        self.chunks.set_block(pos, block)
+    }
+
+    pub fn culling_stats(&self) -> CullingStats {
+        self.chunks.culling_stats()
+    }
+
+    pub fn chunk_stats(&self) -> ChunkStats {
+        self.chunks.stats()
+    }
+
+    /// Chunks in range that are not generated, meshed or uploaded yet.
+    pub fn pending_chunk_count(&self) -> usize {
+        self.chunks.pending_count()
+    }
+
+    pub fn cycle_meshing_mode(&mut self) {
+        let mode = self.chunks.meshing_mode().next();
+        self.chunks.set_meshing_mode(mode);
+    }
+
+    pub fn toggle_ambient_occlusion(&mut self) {
+        let enabled = self.chunks.ambient_occlusion();
+        self.chunks.set_ambient_occlusion(!enabled);
+    }
+
+    /// Grow or shrink the horizontal render distance by `delta` chunks.
+    pub fn change_render_distance(&mut self, delta: i32) {
+        let distance = self.chunks.render_distance();
+        let horizontal = distance.horizontal.saturating_add_signed(delta);
+        self.chunks
+            .set_render_distance(distance.with_horizontal(horizontal));
+        self.camera.projection.far = self.chunks.render_distance().far_distance();
+    }
+
+    /// Switch between perspective and an orthographic projection covering
+    /// the loaded area, for map screenshots.
+    pub fn toggle_orthographic(&mut self) {
+        let distance = self.chunks.render_distance();
+        let height = (2 * distance.horizontal + 1) as f32 * chunks::coords::CHUNK_SIZE as f32;
+        self.camera.toggle_orthographic(height);
+    }
+
+    pub fn target(&self) -> Option<RayHit> {
+        self.target
+    }
+
+    /// Cast a ray from the camera to the first selectable block in reach.
+    fn find_target(&self) -> Option<RayHit> {
+        let registry = self.chunks.registry();
+        raycast(
+            self.camera.render_position(),
+            self.camera.view_direction(),
+            Self::REACH,
+            |block| {
+                self.chunks
+                    .get_block(block)
+                    .is_some_and(|id| registry.is_selectable(id))
+            },
+        )
     }
 
     pub fn prepare_render(&mut self, gfx: &crate::GfxContext) {
-        self.chunks.prepare_render(gfx);
+        self.target = self.find_target();
+        self.outline
+            .set_block(&gfx.queue, self.target.map(|hit| hit.block));
+
+        let frustum = self.camera.frustum(gfx.window_aspect_ratio());
+        self.chunks
+            .prepare_render(gfx, self.camera.position(), &frustum);
+    }
+
+    /// Create the world's GPU resources again on a new device, after the
+    /// old one was lost.
+    pub fn recreate_gpu_resources(&mut self, gfx: &crate::GfxContext) {
+        self.chunks.recreate_gpu_resources(gfx);
+        self.outline = BlockOutline::new(gfx);
+    }
+
+    /// Record GPU work that has to run before the render pass.
+    pub fn encode_compute(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.chunks.encode_culling(encoder);
+    }
+
+    pub fn frame_submitted(&self) {
+        self.chunks.frame_submitted();
     }
 
     pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
         self.chunks.render(render_pass);
+        self.outline.render(render_pass);
     }
 }
+
+/// Stone if the registry has it, for a sensible first block to place.
+fn default_selected_block(registry: &BlockRegistry) -> BlockId {
+    registry
+        .id("stone")
+        .or_else(|| registry.iter().map(|b| b.id).find(|id| *id != AIR))
+        .unwrap_or(AIR)
+}
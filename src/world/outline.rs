@@ -0,0 +1,121 @@
+use glam::IVec3;
+
+use wgpu::util::DeviceExt;
+
+use crate::render::texture::Texture;
+
+/// Wireframe box drawn around the block the camera is looking at.
+pub struct BlockOutline {
+    pipeline: wgpu::RenderPipeline,
+    position_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    block: Option<IVec3>,
+}
+
+impl BlockOutline {
+    pub fn new(gfx: &crate::GfxContext) -> Self {
+        let shader = gfx.device.create_shader_module(wgpu::include_wgsl!("./outline.wgsl"));
+
+        let position_buffer = gfx.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("block outline position buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = gfx.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("block outline bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("block outline bind group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: position_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = gfx.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("block outline pipeline layout"),
+            bind_group_layouts: &[&gfx.global_shader_bindings.layout, &layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = gfx.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("block outline pipeline"),
+            layout: Some(&pipeline_layout),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: gfx.config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::all(),
+                })],
+            }),
+            // Hidden behind other blocks, but never hiding anything itself
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: Texture::DEPTH_COMPARE,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            position_buffer,
+            bind_group,
+            block: None,
+        }
+    }
+
+    /// Outline `block`, or nothing.
+    pub fn set_block(&mut self, queue: &wgpu::Queue, block: Option<IVec3>) {
+        if block == self.block {
+            return;
+        }
+        self.block = block;
+
+        if let Some(block) = block {
+            let position = block.as_vec3().extend(1.0);
+            queue.write_buffer(&self.position_buffer, 0, bytemuck::cast_slice(position.as_ref()));
+        }
+    }
+
+    pub fn render(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.block.is_none() {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..24, 0..1);
+    }
+}
@@ -0,0 +1,28 @@
+@group(0) @binding(0) var<uniform> view_matrix: mat4x4<f32>;
+@group(0) @binding(1) var<uniform> projection_matrix: mat4x4<f32>;
+
+// World position of the outlined block's minimum corner
+@group(1) @binding(0) var<uniform> block_position: vec4<f32>;
+
+// Grown a little past the block so the lines aren't hidden by its faces
+const INFLATE: f32 = 0.002;
+
+// Cube corner of each end of the 12 edges, as bits x, y << 1, z << 2
+const EDGE_CORNERS = array<u32, 24>(
+    0u, 1u, 2u, 3u, 4u, 5u, 6u, 7u, // along X
+    0u, 2u, 1u, 3u, 4u, 6u, 5u, 7u, // along Y
+    0u, 4u, 1u, 5u, 2u, 6u, 3u, 7u, // along Z
+);
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let corner = EDGE_CORNERS[index];
+    let offset = vec3<f32>(vec3<u32>(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u));
+    let position = block_position.xyz - INFLATE + offset * (1.0 + 2.0 * INFLATE);
+    return projection_matrix * view_matrix * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.05, 0.05, 0.05, 1.0);
+}
@@ -0,0 +1,532 @@
+use glam::{IVec3, Quat, Vec3};
+use winit::keyboard::KeyCode;
+
+use crate::input::InputState;
+
+/// Slack for comparing box edges, so boxes resting against a block are not
+/// counted as overlapping it after rounding.
+const EPSILON: f32 = 1e-5;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Box of the block at `block`.
+    pub fn block(block: IVec3) -> Self {
+        let min = block.as_vec3();
+        Self::new(min, min + Vec3::ONE)
+    }
+
+    pub fn offset(&self, offset: Vec3) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    /// Whether the boxes overlap on `axis`, ignoring touching edges.
+    fn overlaps_on(&self, other: &Aabb, axis: usize) -> bool {
+        self.min[axis] < other.max[axis] - EPSILON && self.max[axis] > other.min[axis] + EPSILON
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.overlaps_on(other, axis))
+    }
+
+    /// Coordinates of every block the box touches.
+    fn blocks(&self) -> impl Iterator<Item = IVec3> {
+        let min = self.min.floor().as_ivec3();
+        let max = self.max.floor().as_ivec3();
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+
+    /// How far the box can move along `axis`, up to `amount`, before hitting
+    /// a block. Blocks the box already overlaps don't stop it, so it can
+    /// always get out of them.
+    fn clip(&self, axis: usize, amount: f32, solid: &impl Fn(IVec3) -> bool) -> f32 {
+        let mut swept = *self;
+        if amount > 0.0 {
+            swept.max[axis] += amount;
+        } else {
+            swept.min[axis] += amount;
+        }
+
+        let mut amount = amount;
+        for block in swept.blocks().filter(|block| solid(*block)) {
+            let block = Aabb::block(block);
+            let others = [(axis + 1) % 3, (axis + 2) % 3];
+            if !others.iter().all(|other| self.overlaps_on(&block, *other)) {
+                continue;
+            }
+
+            if amount > 0.0 && block.min[axis] >= self.max[axis] - EPSILON {
+                amount = amount.min(block.min[axis] - self.max[axis]).max(0.0);
+            } else if amount < 0.0 && block.max[axis] <= self.min[axis] + EPSILON {
+                amount = amount.max(block.max[axis] - self.min[axis]).min(0.0);
+            }
+        }
+        amount
+    }
+
+    /// Whether any solid block overlaps the box.
+    fn collides(&self, solid: &impl Fn(IVec3) -> bool) -> bool {
+        self.blocks()
+            .any(|block| solid(block) && self.intersects(&Aabb::block(block)))
+    }
+}
+
+/// Tunable walking physics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerSettings {
+    /// Width of the player's box on X and Z, in blocks.
+    pub width: f32,
+    pub height: f32,
+    /// Height of the eyes above the feet.
+    pub eye_height: f32,
+    /// Walking speed, in blocks per second.
+    pub speed: f32,
+    pub sprint_multiplier: f32,
+    pub sneak_multiplier: f32,
+    /// How fast walking speed changes on the ground, in blocks per second
+    /// squared.
+    pub acceleration: f32,
+    /// How fast walking speed changes while in the air.
+    pub air_acceleration: f32,
+    /// In blocks per second squared.
+    pub gravity: f32,
+    /// Fastest falling speed, in blocks per second.
+    pub terminal_velocity: f32,
+    /// Upwards speed at the start of a jump.
+    pub jump_velocity: f32,
+    /// Highest ledge walked onto without jumping.
+    pub step_height: f32,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        Self {
+            width: 0.6,
+            height: 1.8,
+            eye_height: 1.62,
+            speed: 4.3,
+            sprint_multiplier: 1.3,
+            sneak_multiplier: 0.3,
+            acceleration: 50.0,
+            air_acceleration: 10.0,
+            gravity: 32.0,
+            terminal_velocity: 60.0,
+            // Enough to clear a block and a bit, sqrt(2 * gravity * 1.25)
+            jump_velocity: 8.95,
+            step_height: 1.0,
+        }
+    }
+}
+
+/// How the camera moves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MovementMode {
+    /// Free flight through blocks.
+    #[default]
+    Fly,
+    /// Walking as a [`Player`].
+    Walk,
+}
+
+/// What the player wants to do during a simulation step.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerControls {
+    /// Horizontal walking direction, of length 1 or zero.
+    pub direction: Vec3,
+    pub jump: bool,
+    pub sprint: bool,
+    /// Walk slowly and never off an edge.
+    pub sneak: bool,
+}
+
+impl PlayerControls {
+    pub const JUMP_KEY: KeyCode = KeyCode::Space;
+    pub const SPRINT_KEY: KeyCode = KeyCode::ShiftLeft;
+    pub const SNEAK_KEY: KeyCode = KeyCode::ControlLeft;
+
+    /// Controls from the held keys, walking relative to `yaw`.
+    pub fn from_input(input: &InputState, yaw: f32) -> Self {
+        let rotation = Quat::from_rotation_y(yaw);
+        let (forward, right) = (rotation * Vec3::Z, rotation * Vec3::X);
+
+        let mut direction = Vec3::ZERO;
+        if input.is_key_pressed(KeyCode::KeyW) {
+            direction += forward;
+        }
+        if input.is_key_pressed(KeyCode::KeyS) {
+            direction -= forward;
+        }
+        if input.is_key_pressed(KeyCode::KeyD) {
+            direction += right;
+        }
+        if input.is_key_pressed(KeyCode::KeyA) {
+            direction -= right;
+        }
+
+        Self {
+            direction: direction.normalize_or_zero(),
+            jump: input.is_key_pressed(Self::JUMP_KEY),
+            sprint: input.is_key_pressed(Self::SPRINT_KEY),
+            sneak: input.is_key_pressed(Self::SNEAK_KEY),
+        }
+    }
+}
+
+/// A walking player, colliding with solid blocks.
+///
+/// Movement only depends on the controls, step length and blocks, so
+/// repeating a step gives exactly the same result.
+#[derive(Clone, Debug, Default)]
+pub struct Player {
+    /// Centre of the bottom of the box.
+    position: Vec3,
+    velocity: Vec3,
+    on_ground: bool,
+    sneaking: bool,
+
+    pub settings: PlayerSettings,
+}
+
+impl Player {
+    /// Below the feet checked for ground while sneaking.
+    const GROUND_PROBE: f32 = 0.05;
+    /// Distance sneaking backs off from an edge per attempt.
+    const EDGE_STEP: f32 = 0.05;
+
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+        self.velocity = Vec3::ZERO;
+        self.on_ground = false;
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    pub fn is_on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    /// Position of the eyes, where the camera goes. Lowered while sneaking.
+    pub fn eye_position(&self) -> Vec3 {
+        let sneak_drop = if self.sneaking { 0.15 } else { 0.0 };
+        self.position + Vec3::Y * (self.settings.eye_height - sneak_drop)
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let half = self.settings.width / 2.0;
+        Aabb::new(
+            self.position - Vec3::new(half, 0.0, half),
+            self.position + Vec3::new(half, self.settings.height, half),
+        )
+    }
+
+    /// Simulate `delta` seconds, colliding with blocks for which `solid`
+    /// returns true.
+    pub fn update(&mut self, delta: f32, controls: &PlayerControls, solid: impl Fn(IVec3) -> bool) {
+        let settings = self.settings;
+        self.sneaking = controls.sneak;
+
+        let mut speed = settings.speed;
+        if controls.sneak {
+            speed *= settings.sneak_multiplier;
+        } else if controls.sprint {
+            speed *= settings.sprint_multiplier;
+        }
+        let acceleration = if self.on_ground {
+            settings.acceleration
+        } else {
+            settings.air_acceleration
+        };
+
+        let horizontal = Vec3::new(self.velocity.x, 0.0, self.velocity.z);
+        let target = controls.direction * speed;
+        let horizontal = horizontal + (target - horizontal).clamp_length_max(acceleration * delta);
+        self.velocity.x = horizontal.x;
+        self.velocity.z = horizontal.z;
+
+        if controls.jump && self.on_ground {
+            self.velocity.y = settings.jump_velocity;
+        }
+        self.velocity.y = (self.velocity.y - settings.gravity * delta).max(-settings.terminal_velocity);
+
+        let mut motion = self.velocity * delta;
+        if controls.sneak && self.on_ground {
+            motion = self.hold_edges(motion, &solid);
+        }
+
+        let aabb = self.aabb();
+        let mut moved = Self::sweep(aabb, motion, &solid);
+        if self.on_ground && (moved.x != motion.x || moved.z != motion.z) {
+            let stepped = self.step_up(aabb, motion, &solid);
+            if stepped.x.powi(2) + stepped.z.powi(2) > moved.x.powi(2) + moved.z.powi(2) {
+                moved = stepped;
+            }
+        }
+
+        self.on_ground = motion.y < 0.0 && moved.y > motion.y;
+        for axis in 0..3 {
+            if moved[axis] != motion[axis] {
+                self.velocity[axis] = 0.0;
+            }
+        }
+        self.position += moved;
+    }
+
+    /// Move the box by `motion`, one axis at a time, stopping at blocks.
+    /// Returns the distance actually moved.
+    fn sweep(aabb: Aabb, motion: Vec3, solid: &impl Fn(IVec3) -> bool) -> Vec3 {
+        let mut aabb = aabb;
+        let mut moved = Vec3::ZERO;
+        // Vertical first, so walking along the ground never catches on it
+        for axis in [1, 0, 2] {
+            moved[axis] = aabb.clip(axis, motion[axis], solid);
+            aabb = aabb.offset(Vec3::AXES[axis] * moved[axis]);
+        }
+        moved
+    }
+
+    /// Move as [`Self::sweep`], but first rise by up to the step height, and
+    /// settle back down afterwards.
+    fn step_up(&self, aabb: Aabb, motion: Vec3, solid: &impl Fn(IVec3) -> bool) -> Vec3 {
+        let rise = aabb.clip(1, self.settings.step_height, solid);
+        let raised = aabb.offset(Vec3::Y * rise);
+
+        let horizontal = Self::sweep(raised, Vec3::new(motion.x, 0.0, motion.z), solid);
+        let moved = raised.offset(horizontal);
+        let fall = moved.clip(1, motion.y.min(0.0) - rise, solid);
+
+        Vec3::new(horizontal.x, rise + fall, horizontal.z)
+    }
+
+    /// Shorten horizontal `motion` so the box keeps ground under it.
+    fn hold_edges(&self, motion: Vec3, solid: &impl Fn(IVec3) -> bool) -> Vec3 {
+        let aabb = self.aabb();
+        let supported = |x: f32, z: f32| {
+            aabb.offset(Vec3::new(x, -Self::GROUND_PROBE, z))
+                .collides(solid)
+        };
+        let back_off = |value: f32| {
+            if value.abs() < Self::EDGE_STEP {
+                0.0
+            } else {
+                value - Self::EDGE_STEP.copysign(value)
+            }
+        };
+
+        let (mut x, mut z) = (motion.x, motion.z);
+        while x != 0.0 && !supported(x, 0.0) {
+            x = back_off(x);
+        }
+        while z != 0.0 && !supported(0.0, z) {
+            z = back_off(z);
+        }
+        while x != 0.0 && z != 0.0 && !supported(x, z) {
+            x = back_off(x);
+            z = back_off(z);
+        }
+        Vec3::new(x, motion.y, z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    const STEP: f32 = 1.0 / 60.0;
+
+    /// A flat floor at y = 0 with extra blocks on top.
+    fn world(blocks: &[IVec3]) -> impl Fn(IVec3) -> bool {
+        let blocks: HashSet<IVec3> = blocks.iter().copied().collect();
+        move |block| block.y < 0 || blocks.contains(&block)
+    }
+
+    fn run(player: &mut Player, controls: PlayerControls, steps: u32, solid: &impl Fn(IVec3) -> bool) {
+        for _ in 0..steps {
+            player.update(STEP, &controls, solid);
+        }
+    }
+
+    fn walk(direction: Vec3) -> PlayerControls {
+        PlayerControls {
+            direction,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn falls_and_lands_on_the_ground() {
+        let solid = world(&[]);
+        let mut player = Player::new(Vec3::new(0.5, 10.0, 0.5));
+        run(&mut player, PlayerControls::default(), 120, &solid);
+
+        assert!(player.is_on_ground());
+        assert_eq!(player.position().y, 0.0);
+        assert_eq!(player.velocity().y, 0.0);
+    }
+
+    #[test]
+    fn fast_falls_do_not_pass_through_thin_floors() {
+        // Only a single layer of blocks, far below
+        let solid = |block: IVec3| block.y == -500;
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 0.5));
+        run(&mut player, PlayerControls::default(), 60 * 20, &solid);
+
+        assert!(player.is_on_ground());
+        assert_eq!(player.position().y, -499.0);
+    }
+
+    #[test]
+    fn walls_stop_the_player_flush() {
+        let solid = world(&[IVec3::new(3, 0, 0), IVec3::new(3, 1, 0)]);
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 0.5));
+        run(&mut player, walk(Vec3::X), 120, &solid);
+
+        assert!((player.aabb().max.x - 3.0).abs() < 1e-5);
+        assert_eq!(player.velocity().x, 0.0);
+        assert!(player.is_on_ground());
+    }
+
+    #[test]
+    fn steps_onto_single_blocks_only() {
+        let mut blocks: Vec<_> = (3..20).map(|x| IVec3::new(x, 0, 0)).collect();
+        blocks.extend([IVec3::new(3, 0, 4), IVec3::new(3, 1, 4)]);
+        let solid = world(&blocks);
+
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 0.5));
+        run(&mut player, walk(Vec3::X), 120, &solid);
+        assert_eq!(player.position().y, 1.0);
+        assert!(player.position().x > 3.5);
+
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 4.5));
+        run(&mut player, walk(Vec3::X), 120, &solid);
+        assert_eq!(player.position().y, 0.0);
+        assert!((player.aabb().max.x - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn jumps_only_from_the_ground() {
+        let solid = world(&[]);
+        let jump = PlayerControls {
+            jump: true,
+            ..Default::default()
+        };
+
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 0.5));
+        player.update(STEP, &PlayerControls::default(), &solid);
+        let mut highest = 0.0f32;
+        for _ in 0..30 {
+            player.update(STEP, &jump, &solid);
+            highest = highest.max(player.position().y);
+        }
+        assert!(highest > 1.0 && highest < 1.5, "jumped {highest}");
+
+        // No jumping again mid-air
+        let mut player = Player::new(Vec3::new(0.5, 5.0, 0.5));
+        run(&mut player, jump, 5, &solid);
+        assert!(player.velocity().y < 0.0);
+    }
+
+    #[test]
+    fn ceilings_stop_jumps() {
+        let solid = world(&[IVec3::new(0, 2, 0)]);
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 0.5));
+        player.update(STEP, &PlayerControls::default(), &solid);
+
+        let jump = PlayerControls {
+            jump: true,
+            ..Default::default()
+        };
+        let mut highest = 0.0f32;
+        for _ in 0..30 {
+            player.update(STEP, &jump, &solid);
+            highest = highest.max(player.aabb().max.y);
+        }
+        assert!(highest <= 2.0 + 1e-5);
+    }
+
+    #[test]
+    fn sneaking_does_not_walk_off_edges() {
+        // A platform at y = 10 ending at x = 3
+        let solid = |block: IVec3| block.y == 9 && block.x < 3;
+        let mut player = Player::new(Vec3::new(0.5, 10.0, 0.5));
+        run(&mut player, PlayerControls::default(), 5, &solid);
+        assert!(player.is_on_ground());
+
+        let sneak = PlayerControls {
+            direction: Vec3::X,
+            sneak: true,
+            ..Default::default()
+        };
+        run(&mut player, sneak, 300, &solid);
+        assert!(player.is_on_ground());
+        assert_eq!(player.position().y, 10.0);
+        // Hangs over the edge, but not past it
+        assert!(player.position().x > 3.0 && player.aabb().min.x < 3.0);
+
+        run(&mut player, walk(Vec3::X), 60, &solid);
+        assert!(player.position().y < 10.0);
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let wall: Vec<_> = (-5..5).flat_map(|z| [IVec3::new(2, 0, z), IVec3::new(2, 1, z)]).collect();
+        let solid = world(&wall);
+        let mut player = Player::new(Vec3::new(0.5, 0.0, 0.5));
+        run(&mut player, walk(Vec3::new(1.0, 0.0, 1.0).normalize()), 60, &solid);
+
+        assert!((player.aabb().max.x - 2.0).abs() < 1e-5);
+        assert!(player.position().z > 2.0);
+    }
+
+    /// The game simulates in fixed steps, but steady walking shouldn't
+    /// depend on how long they are.
+    #[test]
+    fn long_steps_match_substeps() {
+        let solid = world(&[IVec3::new(10, 0, 0), IVec3::new(10, 1, 0)]);
+        let mut start = Player::new(Vec3::new(0.5, 3.0, 0.5));
+        // Land and get up to walking speed
+        run(&mut start, walk(Vec3::X), 60, &solid);
+        assert!(start.is_on_ground());
+
+        let (mut long, mut short) = (start.clone(), start);
+        for _ in 0..2 {
+            long.update(1.0, &walk(Vec3::X), &solid);
+            run(&mut short, walk(Vec3::X), 60, &solid);
+            let difference = (long.position() - short.position()).length();
+            assert!(difference < 1e-3, "{} != {}", long.position(), short.position());
+            assert!(long.is_on_ground() && short.is_on_ground());
+        }
+
+        // Standing on the floor against the wall
+        for player in [long, short] {
+            assert!((player.aabb().max.x - 10.0).abs() < 1e-5, "{}", player.position());
+            assert_eq!(player.position().y, 0.0);
+            assert_eq!(player.velocity().x, 0.0);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use glam::{IVec3, Vec3};
+
+use crate::world::chunks::mesher::Face;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// World coordinate of the block hit.
+    pub block: IVec3,
+    /// Face of the block the ray entered through.
+    pub face: Face,
+    /// Distance along the ray to the hit, in blocks.
+    pub distance: f32,
+}
+
+impl RayHit {
+    /// The block in front of the face that was hit, where a block placed
+    /// against it would go.
+    pub fn adjacent(&self) -> IVec3 {
+        self.block + self.face.normal()
+    }
+}
+
+/// Walk the blocks along a ray, in order, until `hits` returns true for one
+/// or `max_distance` is reached. Uses the DDA of Amanatides and Woo, so no
+/// block the ray passes through is skipped.
+///
+/// The block containing `origin` is never reported.
+pub fn raycast(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    mut hits: impl FnMut(IVec3) -> bool,
+) -> Option<RayHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+
+    let mut block = origin.floor().as_ivec3();
+    let mut step = IVec3::ZERO;
+    // Distance along the ray to the next block boundary on each axis
+    let mut t_max = Vec3::INFINITY;
+    // Distance along the ray between boundaries on each axis
+    let mut t_delta = Vec3::INFINITY;
+
+    for axis in 0..3 {
+        let d = direction[axis];
+        if d > 0.0 {
+            step[axis] = 1;
+            t_max[axis] = (block[axis] as f32 + 1.0 - origin[axis]) / d;
+        } else if d < 0.0 {
+            step[axis] = -1;
+            t_max[axis] = (origin[axis] - block[axis] as f32) / -d;
+        }
+        if d != 0.0 {
+            t_delta[axis] = 1.0 / d.abs();
+        }
+    }
+
+    loop {
+        let axis = if t_max.x < t_max.y {
+            if t_max.x < t_max.z { 0 } else { 2 }
+        } else if t_max.y < t_max.z {
+            1
+        } else {
+            2
+        };
+
+        let distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        block[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+
+        if hits(block) {
+            return Some(RayHit {
+                block,
+                face: Face::from_axis(axis, step[axis] < 0),
+                distance,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::{HashMap, HashSet};
+
+    use crate::world::chunks::blocks::{ChunkBlocks, AIR};
+    use crate::world::chunks::coords::{world_to_chunk, world_to_local};
+
+    fn cast(blocks: &[IVec3], origin: Vec3, direction: Vec3, reach: f32) -> Option<RayHit> {
+        let blocks: HashSet<IVec3> = blocks.iter().copied().collect();
+        raycast(origin, direction, reach, |block| blocks.contains(&block))
+    }
+
+    #[test]
+    fn hits_the_nearest_block_along_an_axis() {
+        let blocks = [IVec3::new(5, 0, 0), IVec3::new(3, 0, 0), IVec3::new(-2, 0, 0)];
+
+        let hit = cast(&blocks, Vec3::new(0.5, 0.5, 0.5), Vec3::X, 10.0).unwrap();
+        assert_eq!(hit.block, IVec3::new(3, 0, 0));
+        assert_eq!(hit.face, Face::NegX);
+        assert!((hit.distance - 2.5).abs() < 1e-5);
+        assert_eq!(hit.adjacent(), IVec3::new(2, 0, 0));
+
+        let hit = cast(&blocks, Vec3::new(0.5, 0.5, 0.5), Vec3::NEG_X, 10.0).unwrap();
+        assert_eq!(hit.block, IVec3::new(-2, 0, 0));
+        assert_eq!(hit.face, Face::PosX);
+        assert!((hit.distance - 1.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn respects_the_reach() {
+        let blocks = [IVec3::new(0, -4, 0)];
+        let origin = Vec3::new(0.5, 0.5, 0.5);
+        assert!(cast(&blocks, origin, Vec3::NEG_Y, 3.4).is_none());
+
+        let hit = cast(&blocks, origin, Vec3::NEG_Y, 3.5).unwrap();
+        assert_eq!(hit.face, Face::PosY);
+    }
+
+    #[test]
+    fn diagonal_rays_do_not_skip_blocks() {
+        // Reaches x = 1 before it rises out of y = 0
+        let blocks = [IVec3::new(1, 0, 0)];
+        let direction = Vec3::new(1.0, 0.4, 0.0);
+
+        let hit = cast(&blocks, Vec3::new(0.5, 0.2, 0.5), direction, 5.0).unwrap();
+        assert_eq!(hit.block, IVec3::new(1, 0, 0));
+        assert_eq!(hit.face, Face::NegX);
+
+        // Entering through the top
+        let hit = cast(&blocks, Vec3::new(0.7, 1.5, 0.5), Vec3::new(1.0, -1.0, 0.0), 5.0).unwrap();
+        assert_eq!(hit.block, IVec3::new(1, 0, 0));
+        assert_eq!(hit.face, Face::PosY);
+        assert!((hit.distance - 0.5 * 2f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn visits_every_block_in_order() {
+        let mut visited = vec![];
+        raycast(Vec3::new(0.2, 0.7, 0.1), Vec3::new(3.0, -1.0, 2.0), 6.0, |block| {
+            visited.push(block);
+            false
+        });
+
+        assert!(visited.len() > 6);
+        for pair in visited.windows(2) {
+            // Each step moves to a face neighbour
+            assert_eq!((pair[1] - pair[0]).abs().element_sum(), 1);
+        }
+    }
+
+    #[test]
+    fn crosses_chunk_boundaries() {
+        let mut chunks: HashMap<IVec3, ChunkBlocks> = HashMap::new();
+        for block in [IVec3::new(-3, 10, -2), IVec3::new(33, 32, 4)] {
+            chunks
+                .entry(world_to_chunk(block))
+                .or_default()
+                .set(world_to_local(block), 1);
+        }
+        let solid = |block: IVec3| {
+            chunks
+                .get(&world_to_chunk(block))
+                .is_some_and(|chunk| chunk.get(world_to_local(block)) != AIR)
+        };
+
+        // From chunk (0, 0, 0) into chunk (-1, 0, -1)
+        let hit = raycast(Vec3::new(1.5, 10.5, 2.2), Vec3::new(-1.0, 0.0, -1.0), 8.0, solid).unwrap();
+        assert_eq!(hit.block, IVec3::new(-3, 10, -2));
+        assert_eq!(hit.face, Face::PosX);
+
+        // From chunk (0, 0, 0) through (0, 1, 0) into (1, 1, 0)
+        let origin = Vec3::new(30.5, 30.2, 4.5);
+        let hit = raycast(origin, Vec3::new(1.0, 1.0, 0.0), 8.0, solid).unwrap();
+        assert_eq!(hit.block, IVec3::new(33, 32, 4));
+        assert_eq!(hit.face, Face::NegX);
+        assert!((hit.distance - 2.5 * 2f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ignores_the_starting_block_and_zero_directions() {
+        let blocks = [IVec3::ZERO, IVec3::new(0, 0, 2)];
+        let hit = cast(&blocks, Vec3::splat(0.5), Vec3::Z, 5.0).unwrap();
+        assert_eq!(hit.block, IVec3::new(0, 0, 2));
+        assert!(cast(&blocks, Vec3::splat(0.5), Vec3::ZERO, 5.0).is_none());
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::world::chunks::{
+    blocks::{BlockId, AIR},
+    mesher::Face,
+};
+
+/// Block definitions shipped with the binary, used when there is no
+/// `assets/blocks.ron` in the working directory.
+const BUILTIN_BLOCKS: &str = include_str!("../../assets/blocks.ron");
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum BlockTextures {
+    All(String),
+    Column {
+        top: String,
+        bottom: String,
+        side: String,
+    },
+    Faces {
+        px: String,
+        nx: String,
+        py: String,
+        ny: String,
+        pz: String,
+        nz: String,
+    },
+}
+
+impl BlockTextures {
+    pub fn get(&self, face: Face) -> &str {
+        match self {
+            BlockTextures::All(all) => all,
+            BlockTextures::Column { top, bottom, side } => match face {
+                Face::PosY => top,
+                Face::NegY => bottom,
+                _ => side,
+            },
+            BlockTextures::Faces {
+                px,
+                nx,
+                py,
+                ny,
+                pz,
+                nz,
+            } => match face {
+                Face::PosX => px,
+                Face::NegX => nx,
+                Face::PosY => py,
+                Face::NegY => ny,
+                Face::PosZ => pz,
+                Face::NegZ => nz,
+            },
+        }
+    }
+}
+
+/// Which chunk render pass draws a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RenderLayer {
+    /// Fully covers what's behind it.
+    Opaque,
+    /// Either opaque or fully see-through per texel, like leaves.
+    Cutout,
+    /// Blended with what's behind it, like water and tinted glass. Drawn
+    /// after everything else, back to front.
+    Translucent,
+}
+
+impl RenderLayer {
+    pub const ALL: [RenderLayer; 3] =
+        [RenderLayer::Opaque, RenderLayer::Cutout, RenderLayer::Translucent];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockDefinition {
+    pub id: BlockId,
+    pub name: String,
+    /// Whether entities collide with the block.
+    #[serde(default = "yes")]
+    pub solid: bool,
+    /// Whether blocks behind it can be seen through it.
+    #[serde(default)]
+    pub transparent: bool,
+    #[serde(default)]
+    pub liquid: bool,
+    /// Whether a transparent block's texture is partly see-through, rather
+    /// than only having holes. Liquids always are.
+    #[serde(default)]
+    pub translucent: bool,
+    pub textures: BlockTextures,
+    /// Block light level emitted, 0 to 15.
+    #[serde(default)]
+    pub light_emission: u8,
+    /// Seconds to break the block by hand.
+    #[serde(default)]
+    pub hardness: f32,
+    /// Average colour, for untextured rendering.
+    pub color: (f32, f32, f32),
+}
+
+impl BlockDefinition {
+    fn air() -> Self {
+        Self {
+            id: AIR,
+            name: "air".to_owned(),
+            solid: false,
+            transparent: true,
+            liquid: false,
+            translucent: false,
+            textures: BlockTextures::All(String::new()),
+            light_emission: 0,
+            hardness: 0.0,
+            color: (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+/// Maps block ids to their definitions. Every face texture name is also
+/// given a texture layer, in order of first use.
+pub struct BlockRegistry {
+    blocks: Vec<Option<BlockDefinition>>,
+    by_name: HashMap<String, BlockId>,
+
+    texture_names: Vec<String>,
+    /// Texture layer of each face of each block, indexed by id then face.
+    face_layers: Vec<[u32; 6]>,
+}
+
+impl BlockRegistry {
+    pub const DEFAULT_PATH: &'static str = "assets/blocks.ron";
+
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_BLOCKS).expect("Built-in block definitions are invalid")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Read block definitions from {}", path.display()))?;
+        Self::parse(&source).with_context(|| format!("Parse {}", path.display()))
+    }
+
+    /// Load [`Self::DEFAULT_PATH`], falling back to the built-in definitions
+    /// if it doesn't exist.
+    pub fn load_default() -> Result<Self> {
+        if Path::new(Self::DEFAULT_PATH).exists() {
+            Self::load(Self::DEFAULT_PATH)
+        } else {
+            Ok(Self::builtin())
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Self> {
+        let definitions: Vec<BlockDefinition> = ron::from_str(source)?;
+        Self::from_definitions(definitions)
+    }
+
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<Self> {
+        let mut registry = Self {
+            blocks: vec![],
+            by_name: HashMap::new(),
+            texture_names: vec![],
+            face_layers: vec![],
+        };
+
+        registry.insert(BlockDefinition::air())?;
+        for definition in definitions {
+            if definition.id == AIR {
+                bail!("Block id {AIR} is reserved for air, used by {:?}", definition.name);
+            }
+            if definition.light_emission > 15 {
+                bail!("Light emission of {:?} is above 15", definition.name);
+            }
+            registry.insert(definition)?;
+        }
+
+        registry.assign_texture_layers();
+        Ok(registry)
+    }
+
+    fn insert(&mut self, definition: BlockDefinition) -> Result<()> {
+        let id = definition.id as usize;
+        if self.blocks.len() <= id {
+            self.blocks.resize(id + 1, None);
+        }
+        if let Some(existing) = &self.blocks[id] {
+            bail!(
+                "Block id {id} used by both {:?} and {:?}",
+                existing.name,
+                definition.name
+            );
+        }
+        if self.by_name.insert(definition.name.clone(), definition.id).is_some() {
+            bail!("Block name {:?} defined twice", definition.name);
+        }
+
+        self.blocks[id] = Some(definition);
+        Ok(())
+    }
+
+    fn assign_texture_layers(&mut self) {
+        let mut layers: HashMap<String, u32> = HashMap::new();
+        self.face_layers = self
+            .blocks
+            .iter()
+            .map(|definition| match definition {
+                Some(definition) if definition.id != AIR => Face::ALL.map(|face| {
+                    let name = definition.textures.get(face);
+                    *layers.entry(name.to_owned()).or_insert_with(|| {
+                        self.texture_names.push(name.to_owned());
+                        self.texture_names.len() as u32 - 1
+                    })
+                }),
+                _ => [0; 6],
+            })
+            .collect();
+    }
+
+    /// Definition of a block id. Unknown ids, for example from a save made
+    /// with a newer block list, are reported as air.
+    pub fn get(&self, id: BlockId) -> &BlockDefinition {
+        match self.blocks.get(id as usize) {
+            Some(Some(definition)) => definition,
+            _ => self.blocks[AIR as usize].as_ref().unwrap(),
+        }
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockDefinition> {
+        self.blocks.iter().flatten()
+    }
+
+    /// Whether the block hides every face touching it.
+    pub fn is_opaque(&self, id: BlockId) -> bool {
+        let block = self.get(id);
+        id != AIR && !block.transparent && !block.liquid
+    }
+
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.get(id).solid
+    }
+
+    pub fn render_layer(&self, id: BlockId) -> RenderLayer {
+        let block = self.get(id);
+        if block.liquid || (block.transparent && block.translucent) {
+            RenderLayer::Translucent
+        } else if block.transparent {
+            RenderLayer::Cutout
+        } else {
+            RenderLayer::Opaque
+        }
+    }
+
+    /// Whether the block can be targeted for breaking or placing against.
+    pub fn is_selectable(&self, id: BlockId) -> bool {
+        id != AIR && !self.get(id).liquid
+    }
+
+    pub fn texture_layer(&self, id: BlockId, face: Face) -> u32 {
+        self.face_layers
+            .get(id as usize)
+            .map_or(0, |layers| layers[face.index()])
+    }
+
+    /// Texture names, indexed by texture layer.
+    pub fn texture_names(&self) -> &[String] {
+        &self.texture_names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_definitions_load() {
+        let registry = BlockRegistry::builtin();
+
+        let stone = registry.id("stone").unwrap();
+        assert_eq!(registry.get(stone).name, "stone");
+        assert!(registry.is_opaque(stone));
+        assert!(registry.is_solid(stone));
+
+        let water = registry.id("water").unwrap();
+        assert!(registry.get(water).liquid);
+        assert!(!registry.is_opaque(water));
+        assert!(!registry.is_solid(water));
+
+        assert_eq!(registry.get(AIR).name, "air");
+        assert!(!registry.is_opaque(AIR));
+    }
+
+    /// What `assets/blocks.ron` should define. Ids are stored in saves, so
+    /// a block changing id or name has to be deliberate; colours and
+    /// hardness are left out.
+    const EXPECTED_BLOCKS: &str = r#"[
+        (id: 1, name: "stone", textures: All("stone"), color: (0.0, 0.0, 0.0)),
+        (id: 2, name: "dirt", textures: All("dirt"), color: (0.0, 0.0, 0.0)),
+        (
+            id: 3,
+            name: "grass",
+            textures: Column(top: "grass_top", bottom: "dirt", side: "grass_side"),
+            color: (0.0, 0.0, 0.0),
+        ),
+        (
+            id: 4,
+            name: "water",
+            solid: false,
+            transparent: true,
+            liquid: true,
+            textures: All("water"),
+            color: (0.0, 0.0, 0.0),
+        ),
+        (id: 5, name: "sand", textures: All("sand"), color: (0.0, 0.0, 0.0)),
+        (
+            id: 6,
+            name: "glass",
+            transparent: true,
+            translucent: true,
+            textures: All("glass"),
+            color: (0.0, 0.0, 0.0),
+        ),
+        (
+            id: 7,
+            name: "leaves",
+            transparent: true,
+            textures: All("leaves"),
+            color: (0.0, 0.0, 0.0),
+        ),
+        (
+            id: 8,
+            name: "log",
+            textures: Column(top: "log_top", bottom: "log_top", side: "log_side"),
+            color: (0.0, 0.0, 0.0),
+        ),
+        (id: 9, name: "planks", textures: All("planks"), color: (0.0, 0.0, 0.0)),
+        (id: 10, name: "cobblestone", textures: All("cobblestone"), color: (0.0, 0.0, 0.0)),
+        (
+            id: 11,
+            name: "lamp",
+            textures: All("lamp"),
+            light_emission: 15,
+            color: (0.0, 0.0, 0.0),
+        ),
+    ]"#;
+
+    #[test]
+    fn assets_match_expected_definitions() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/", "assets/blocks.ron");
+        let loaded = BlockRegistry::load(path).unwrap();
+        let expected = BlockRegistry::parse(EXPECTED_BLOCKS).unwrap();
+
+        let ids = |registry: &BlockRegistry| registry.iter().map(|b| b.id).collect::<Vec<_>>();
+        assert_eq!(ids(&loaded), ids(&expected));
+
+        for block in expected.iter() {
+            let (id, name) = (block.id, &block.name);
+            let actual = loaded.get(id);
+            assert_eq!(&actual.name, name, "block {id}");
+            assert_eq!(
+                (actual.solid, actual.transparent, actual.liquid, actual.translucent),
+                (block.solid, block.transparent, block.liquid, block.translucent),
+                "{name}"
+            );
+            assert_eq!(actual.light_emission, block.light_emission, "{name}");
+            assert_eq!(loaded.render_layer(id), expected.render_layer(id), "{name}");
+            for face in Face::ALL {
+                assert_eq!(actual.textures.get(face), block.textures.get(face), "{name} {face:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn render_layers_follow_transparency() {
+        let registry = BlockRegistry::builtin();
+        let layer = |name| registry.render_layer(registry.id(name).unwrap());
+        assert_eq!(layer("stone"), RenderLayer::Opaque);
+        assert_eq!(layer("leaves"), RenderLayer::Cutout);
+        assert_eq!(layer("glass"), RenderLayer::Translucent);
+        assert_eq!(layer("water"), RenderLayer::Translucent);
+    }
+
+    #[test]
+    fn unknown_ids_are_air() {
+        let registry = BlockRegistry::builtin();
+        assert_eq!(registry.get(60000).name, "air");
+        assert!(!registry.is_solid(60000));
+    }
+
+    #[test]
+    fn texture_layers_are_shared_between_blocks() {
+        let registry = BlockRegistry::builtin();
+        let dirt = registry.id("dirt").unwrap();
+        let grass = registry.id("grass").unwrap();
+
+        let dirt_layer = registry.texture_layer(dirt, Face::PosY);
+        assert_eq!(registry.texture_layer(grass, Face::NegY), dirt_layer);
+        assert_ne!(registry.texture_layer(grass, Face::PosY), dirt_layer);
+        assert_eq!(
+            registry.texture_layer(grass, Face::PosX),
+            registry.texture_layer(grass, Face::NegZ)
+        );
+
+        let names = registry.texture_names();
+        assert_eq!(names[dirt_layer as usize], "dirt");
+        assert_eq!(names[registry.texture_layer(grass, Face::PosZ) as usize], "grass_side");
+    }
+
+    #[test]
+    fn defaults_for_omitted_fields() {
+        let registry = BlockRegistry::parse(
+            r#"[(id: 3, name: "thing", textures: All("thing"), color: (1.0, 0.0, 0.0))]"#,
+        )
+        .unwrap();
+        let thing = registry.get(3);
+        assert!(thing.solid);
+        assert!(!thing.transparent && !thing.liquid);
+        assert_eq!(thing.light_emission, 0);
+        assert_eq!(registry.get(1).name, "air");
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let duplicate_id = r#"[
+            (id: 1, name: "a", textures: All("a"), color: (0.0, 0.0, 0.0)),
+            (id: 1, name: "b", textures: All("b"), color: (0.0, 0.0, 0.0)),
+        ]"#;
+        assert!(BlockRegistry::parse(duplicate_id).is_err());
+
+        let duplicate_name = r#"[
+            (id: 1, name: "a", textures: All("a"), color: (0.0, 0.0, 0.0)),
+            (id: 2, name: "a", textures: All("b"), color: (0.0, 0.0, 0.0)),
+        ]"#;
+        assert!(BlockRegistry::parse(duplicate_name).is_err());
+
+        let air = r#"[(id: 0, name: "void", textures: All("a"), color: (0.0, 0.0, 0.0))]"#;
+        assert!(BlockRegistry::parse(air).is_err());
+
+        let too_bright =
+            r#"[(id: 1, name: "sun", textures: All("a"), light_emission: 16, color: (0.0, 0.0, 0.0))]"#;
+        assert!(BlockRegistry::parse(too_bright).is_err());
+    }
+}
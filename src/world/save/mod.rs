@@ -0,0 +1,235 @@
+pub mod region;
+
+use anyhow::{bail, Context, Result};
+use glam::IVec3;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::world::chunks::blocks::ChunkBlocks;
+
+use region::{region_file_name, region_of, RegionFile};
+
+/// Contents of `world.ron` in a save directory.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorldHeader {
+    pub version: u32,
+    pub seed: u64,
+    pub camera: CameraState,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: (f32, f32, f32),
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl WorldHeader {
+    pub const VERSION: u32 = 1;
+}
+
+/// A world on disk: a header plus region files with the chunks that were
+/// modified. Unmodified chunks are never saved, they are generated again
+/// from the seed.
+///
+/// Shared between the render thread, which saves chunks, and the chunk
+/// workers, which load them.
+pub struct WorldSave {
+    dir: PathBuf,
+    regions: Mutex<HashMap<IVec3, RegionFile>>,
+}
+
+impl WorldSave {
+    pub const DEFAULT_DIR: &'static str = "saves/world";
+
+    const HEADER_FILE: &'static str = "world.ron";
+    const REGION_DIR: &'static str = "regions";
+
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join(Self::REGION_DIR))
+            .with_context(|| format!("Create save directory {}", dir.display()))?;
+
+        Ok(Self {
+            dir,
+            regions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The saved header, or `None` for a new world.
+    pub fn read_header(&self) -> Result<Option<WorldHeader>> {
+        let path = self.dir.join(Self::HEADER_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Read {}", path.display()))?;
+        let header: WorldHeader =
+            ron::from_str(&source).with_context(|| format!("Parse {}", path.display()))?;
+
+        if header.version != WorldHeader::VERSION {
+            bail!(
+                "{} has format version {}, expected {}",
+                path.display(),
+                header.version,
+                WorldHeader::VERSION
+            );
+        }
+
+        Ok(Some(header))
+    }
+
+    pub fn write_header(&self, header: &WorldHeader) -> Result<()> {
+        let path = self.dir.join(Self::HEADER_FILE);
+        let source = ron::ser::to_string_pretty(header, ron::ser::PrettyConfig::default())?;
+
+        // Write then rename, so a crash never leaves a truncated header
+        let temp = path.with_extension("ron.tmp");
+        std::fs::write(&temp, source).with_context(|| format!("Write {}", temp.display()))?;
+        std::fs::rename(&temp, &path).with_context(|| format!("Write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn load_chunk(&self, coord: IVec3) -> Result<Option<ChunkBlocks>> {
+        let (region, index) = region_of(coord);
+        self.with_region(region, |file| file.read(index))
+            .with_context(|| format!("Load chunk {coord}"))
+    }
+
+    pub fn save_chunk(&self, coord: IVec3, blocks: &ChunkBlocks) -> Result<()> {
+        let (region, index) = region_of(coord);
+        self.with_region(region, |file| file.write(index, blocks))
+            .with_context(|| format!("Save chunk {coord}"))
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let mut regions = self.regions.lock().unwrap();
+        for region in regions.values_mut() {
+            region.flush()?;
+        }
+        Ok(())
+    }
+
+    fn with_region<T>(
+        &self,
+        region: IVec3,
+        f: impl FnOnce(&mut RegionFile) -> Result<T>,
+    ) -> Result<T> {
+        let mut regions = self.regions.lock().unwrap();
+        let file = match regions.entry(region) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let path = self
+                    .dir
+                    .join(Self::REGION_DIR)
+                    .join(region_file_name(region));
+                entry.insert(RegionFile::open(&path)?)
+            }
+        };
+        f(file)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use glam::UVec3;
+
+    /// Directory under the system temp dir, removed again on drop.
+    pub struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "shallow-stone-{name}-{}",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let dir = TempDir::new("header_round_trip");
+        let save = WorldSave::open(dir.path()).unwrap();
+        assert_eq!(save.read_header().unwrap(), None);
+
+        let header = WorldHeader {
+            version: WorldHeader::VERSION,
+            seed: 0xdead_beef_cafe,
+            camera: CameraState {
+                position: (1.5, 70.25, -3.0),
+                pitch: -0.4,
+                yaw: 2.0,
+            },
+        };
+        save.write_header(&header).unwrap();
+
+        let reopened = WorldSave::open(dir.path()).unwrap();
+        assert_eq!(reopened.read_header().unwrap(), Some(header));
+    }
+
+    #[test]
+    fn rejects_unknown_header_versions() {
+        let dir = TempDir::new("header_version");
+        let save = WorldSave::open(dir.path()).unwrap();
+        save.write_header(&WorldHeader {
+            version: WorldHeader::VERSION + 1,
+            seed: 1,
+            camera: CameraState::default(),
+        })
+        .unwrap();
+        assert!(save.read_header().is_err());
+    }
+
+    #[test]
+    fn chunks_round_trip_across_regions() {
+        let dir = TempDir::new("chunk_round_trip");
+
+        let coords = [
+            IVec3::new(0, 0, 0),
+            IVec3::new(-1, 0, 0),
+            IVec3::new(31, -2, 31),
+            IVec3::new(32, 1, -64),
+        ];
+
+        {
+            let save = WorldSave::open(dir.path()).unwrap();
+            for (i, coord) in coords.iter().enumerate() {
+                let mut blocks = ChunkBlocks::default();
+                blocks.set(UVec3::new(i as u32, 2, 3), 10 + i as u16);
+                save.save_chunk(*coord, &blocks).unwrap();
+            }
+            save.flush().unwrap();
+        }
+
+        let save = WorldSave::open(dir.path()).unwrap();
+        for (i, coord) in coords.iter().enumerate() {
+            let blocks = save.load_chunk(*coord).unwrap().unwrap();
+            assert_eq!(blocks.get(UVec3::new(i as u32, 2, 3)), 10 + i as u16);
+        }
+        assert!(save.load_chunk(IVec3::new(1, 0, 0)).unwrap().is_none());
+        assert!(save.load_chunk(IVec3::new(0, 5, 0)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{bail, Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::world::chunks::{
+    blocks::{BlockId, ChunkBlocks},
+    buffers::RangeAllocator,
+    coords::{index_to_local, CHUNK_VOLUME},
+};
+
+/// Chunks per region along X and Z. A region is one chunk tall.
+pub const REGION_SIZE: i32 = 32;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"SSRG";
+const VERSION: u32 = 1;
+
+const SECTOR_SIZE: u64 = 4096;
+/// Magic, version and one (first sector, sector count) pair per chunk.
+const HEADER_SIZE: u64 = 8 + REGION_CHUNKS as u64 * 8;
+const HEADER_SECTORS: u32 = HEADER_SIZE.div_ceil(SECTOR_SIZE) as u32;
+
+/// Region containing a chunk, and the chunk's slot in that region.
+pub fn region_of(chunk: IVec3) -> (IVec3, usize) {
+    let region = IVec3::new(
+        chunk.x.div_euclid(REGION_SIZE),
+        chunk.y,
+        chunk.z.div_euclid(REGION_SIZE),
+    );
+    let x = chunk.x.rem_euclid(REGION_SIZE);
+    let z = chunk.z.rem_euclid(REGION_SIZE);
+    (region, (x + z * REGION_SIZE) as usize)
+}
+
+pub fn region_file_name(region: IVec3) -> String {
+    format!("r.{}.{}.{}.region", region.x, region.y, region.z)
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    sector: u32,
+    sectors: u32,
+}
+
+impl Slot {
+    /// Sectors holding the chunk, `None` if the entry runs past the largest
+    /// sector number.
+    fn range(&self) -> Option<Range<u32>> {
+        Some(self.sector..self.sector.checked_add(self.sectors)?)
+    }
+}
+
+/// A file holding the compressed blocks of up to 32×32 chunks.
+///
+/// The file is split into 4 KiB sectors. The header sectors hold a table with
+/// the first sector and sector count of each stored chunk. Chunk data is a
+/// little-endian u32 length followed by the zlib-compressed block ids.
+/// Chunks are always written to sectors no table entry points at, and the
+/// sectors they replace are only reused once the table points at the new
+/// data, so a write cut short leaves the previous version readable.
+pub struct RegionFile {
+    file: File,
+    slots: Box<[Slot; REGION_CHUNKS]>,
+    /// Sectors not used by the header or any chunk. Grows past the end of
+    /// the file when no free run is large enough.
+    free_sectors: RangeAllocator,
+}
+
+impl RegionFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Open region file {}", path.display()))?;
+
+        let mut slots = Box::new([Slot::default(); REGION_CHUNKS]);
+
+        if file.metadata()?.len() == 0 {
+            let mut header = vec![0; (HEADER_SECTORS as u64 * SECTOR_SIZE) as usize];
+            header[..4].copy_from_slice(MAGIC);
+            header[4..8].copy_from_slice(&VERSION.to_le_bytes());
+            file.write_all(&header)?;
+        } else {
+            let mut header = vec![0; HEADER_SIZE as usize];
+            file.read_exact(&mut header)
+                .with_context(|| format!("Read region header of {}", path.display()))?;
+
+            if &header[..4] != MAGIC {
+                bail!("{} is not a region file", path.display());
+            }
+            let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            if version != VERSION {
+                bail!("{} has unsupported region version {version}", path.display());
+            }
+
+            for (slot, entry) in slots.iter_mut().zip(header[8..].chunks_exact(8)) {
+                slot.sector = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                slot.sectors = u32::from_le_bytes(entry[4..].try_into().unwrap());
+            }
+        }
+
+        let file_sectors = file.metadata()?.len().div_ceil(SECTOR_SIZE) as u32;
+        drop_invalid_slots(&mut slots[..], file_sectors, path);
+        let free_sectors = free_sectors(&slots[..], file_sectors);
+
+        Ok(Self {
+            file,
+            slots,
+            free_sectors,
+        })
+    }
+
+    pub fn read(&mut self, index: usize) -> Result<Option<ChunkBlocks>> {
+        let slot = self.slots[index];
+        if slot.sectors == 0 {
+            return Ok(None);
+        }
+
+        self.file
+            .seek(SeekFrom::Start(slot.sector as u64 * SECTOR_SIZE))?;
+        let mut length = [0; 4];
+        self.file.read_exact(&mut length)?;
+        let length = u32::from_le_bytes(length) as u64;
+
+        if length + 4 > slot.sectors as u64 * SECTOR_SIZE {
+            bail!("Chunk {index} overruns its sectors");
+        }
+
+        let mut compressed = vec![0; length as usize];
+        self.file.read_exact(&mut compressed)?;
+
+        decode_blocks(&compressed).map(Some)
+    }
+
+    pub fn write(&mut self, index: usize, blocks: &ChunkBlocks) -> Result<()> {
+        let compressed = encode_blocks(blocks)?;
+        let sectors = (compressed.len() as u64 + 4).div_ceil(SECTOR_SIZE) as u32;
+
+        let range = match self.free_sectors.allocate(sectors) {
+            Some(range) => range,
+            None => {
+                let capacity = self.free_sectors.capacity();
+                self.free_sectors.grow(capacity + sectors);
+                self.free_sectors.allocate(sectors).unwrap()
+            }
+        };
+        let slot = Slot {
+            sector: range.start,
+            sectors,
+        };
+
+        let mut data = Vec::with_capacity((sectors as u64 * SECTOR_SIZE) as usize);
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        data.resize((sectors as u64 * SECTOR_SIZE) as usize, 0);
+
+        self.file
+            .seek(SeekFrom::Start(slot.sector as u64 * SECTOR_SIZE))?;
+        self.file.write_all(&data)?;
+
+        // Only point the table at the new data once it's written
+        let mut entry = [0; 8];
+        entry[..4].copy_from_slice(&slot.sector.to_le_bytes());
+        entry[4..].copy_from_slice(&slot.sectors.to_le_bytes());
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&entry)?;
+
+        let old = std::mem::replace(&mut self.slots[index], slot);
+        if let Some(range) = old.range().filter(|range| !range.is_empty()) {
+            self.free_sectors.free(range);
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Forget the table entries of a file `file_sectors` long that point into
+/// the header, past the end of the file or at sectors another entry uses.
+/// Their chunks read as missing, and are generated again, instead of being
+/// read from or written over another chunk's data.
+fn drop_invalid_slots(slots: &mut [Slot], file_sectors: u32, path: &Path) {
+    let mut invalid = vec![false; slots.len()];
+    for (i, slot) in slots.iter().enumerate().filter(|(_, slot)| slot.sectors > 0) {
+        invalid[i] = slot.sector < HEADER_SECTORS
+            || slot.range().is_none_or(|range| range.end > file_sectors);
+    }
+
+    let mut order: Vec<_> = (0..slots.len())
+        .filter(|&i| slots[i].sectors > 0 && !invalid[i])
+        .collect();
+    order.sort_by_key(|&i| slots[i].sector);
+    // The slot reaching furthest so far, which any overlapping slot overlaps
+    let mut furthest: Option<(usize, u32)> = None;
+    for i in order {
+        let range = slots[i].range().unwrap();
+        if let Some((j, end)) = furthest {
+            if range.start < end {
+                invalid[i] = true;
+                invalid[j] = true;
+            }
+        }
+        if furthest.is_none_or(|(_, end)| range.end > end) {
+            furthest = Some((i, range.end));
+        }
+    }
+
+    for (i, slot) in slots.iter_mut().enumerate().filter(|(i, _)| invalid[*i]) {
+        tracing::warn!(
+            region = %path.display(),
+            chunk = i,
+            sector = slot.sector,
+            sectors = slot.sectors,
+            "Ignoring invalid region table entry"
+        );
+        *slot = Slot::default();
+    }
+}
+
+/// Sectors of a file `file_sectors` long that neither the header nor any
+/// of `slots` use.
+fn free_sectors(slots: &[Slot], file_sectors: u32) -> RangeAllocator {
+    let mut used: Vec<_> = slots
+        .iter()
+        .filter(|slot| slot.sectors > 0)
+        .filter_map(Slot::range)
+        .collect();
+    used.sort_by_key(|range| range.start);
+
+    let end = used.iter().map(|range| range.end).fold(file_sectors, u32::max);
+    let mut free = RangeAllocator::new(end);
+    if end > 0 {
+        free.allocate(end);
+    }
+
+    let mut gap_start = HEADER_SECTORS;
+    for range in used {
+        if range.start > gap_start {
+            free.free(gap_start..range.start);
+        }
+        gap_start = gap_start.max(range.end);
+    }
+    if end > gap_start {
+        free.free(gap_start..end);
+    }
+    free
+}
+
+fn encode_blocks(blocks: &ChunkBlocks) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    for block in blocks.as_slice() {
+        encoder.write_all(&block.to_le_bytes())?;
+    }
+    Ok(encoder.finish()?)
+}
+
+fn decode_blocks(compressed: &[u8]) -> Result<ChunkBlocks> {
+    let mut raw = Vec::with_capacity(CHUNK_VOLUME * 2);
+    ZlibDecoder::new(compressed)
+        .read_to_end(&mut raw)
+        .context("Decompress chunk")?;
+
+    if raw.len() != CHUNK_VOLUME * 2 {
+        bail!("Chunk data is {} bytes, expected {}", raw.len(), CHUNK_VOLUME * 2);
+    }
+
+    let mut blocks = ChunkBlocks::default();
+    for (i, bytes) in raw.chunks_exact(2).enumerate() {
+        let block = BlockId::from_le_bytes([bytes[0], bytes[1]]);
+        blocks.set(index_to_local(i), block);
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::UVec3;
+
+    use crate::world::save::tests::TempDir;
+
+    fn pattern(seed: u32) -> ChunkBlocks {
+        let mut blocks = ChunkBlocks::default();
+        for i in 0..CHUNK_VOLUME {
+            let local = index_to_local(i);
+            if (local.x * 7 + local.y * 3 + local.z + seed) % 5 < 2 {
+                blocks.set(local, (seed % 9 + 1) as BlockId);
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn region_coordinates() {
+        assert_eq!(region_of(IVec3::new(0, 5, 0)), (IVec3::new(0, 5, 0), 0));
+        assert_eq!(region_of(IVec3::new(31, 0, 1)), (IVec3::ZERO, 31 + 32));
+        assert_eq!(region_of(IVec3::new(-1, -2, -33)), (IVec3::new(-1, -2, -2), 31 + 31 * 32));
+    }
+
+    #[test]
+    fn blocks_survive_compression() {
+        let blocks = pattern(3);
+        let decoded = decode_blocks(&encode_blocks(&blocks).unwrap()).unwrap();
+        assert_eq!(decoded.as_slice(), blocks.as_slice());
+    }
+
+    #[test]
+    fn chunks_round_trip_through_a_region_file() {
+        let dir = TempDir::new("region_round_trip");
+        let path = dir.path().join("r.0.0.0.region");
+
+        {
+            let mut region = RegionFile::open(&path).unwrap();
+            assert!(region.read(0).unwrap().is_none());
+            region.write(0, &pattern(0)).unwrap();
+            region.write(1023, &pattern(1)).unwrap();
+            region.write(40, &ChunkBlocks::filled(7)).unwrap();
+        }
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_eq!(region.read(0).unwrap().unwrap().as_slice(), pattern(0).as_slice());
+        assert_eq!(region.read(1023).unwrap().unwrap().as_slice(), pattern(1).as_slice());
+        assert_eq!(region.read(40).unwrap().unwrap().get(UVec3::new(1, 2, 3)), 7);
+        assert!(region.read(41).unwrap().is_none());
+    }
+
+    #[test]
+    fn rewritten_chunks_replace_old_data() {
+        let dir = TempDir::new("region_rewrite");
+        let path = dir.path().join("r.0.0.0.region");
+
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(5, &ChunkBlocks::filled(1)).unwrap();
+        region.write(6, &ChunkBlocks::filled(2)).unwrap();
+        // Much less compressible, so it has to move to the end of the file
+        region.write(5, &pattern(4)).unwrap();
+        region.write(6, &ChunkBlocks::filled(3)).unwrap();
+        drop(region);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        assert_eq!(region.read(5).unwrap().unwrap().as_slice(), pattern(4).as_slice());
+        assert_eq!(region.read(6).unwrap().unwrap().as_slice(), ChunkBlocks::filled(3).as_slice());
+    }
+
+    #[test]
+    fn rewrites_never_overwrite_the_data_in_use() {
+        let dir = TempDir::new("region_rewrite_in_place");
+        let path = dir.path().join("r.0.0.0.region");
+
+        let mut region = RegionFile::open(&path).unwrap();
+        region.write(5, &pattern(4)).unwrap();
+        let mut previous = region.slots[5].range().unwrap();
+        for i in 0..6 {
+            // Smaller than before, so it would fit in the old sectors
+            region.write(5, &ChunkBlocks::filled(i + 1)).unwrap();
+            let current = region.slots[5].range().unwrap();
+            assert!(current.start >= previous.end || current.end <= previous.start);
+            previous = current;
+        }
+        assert_eq!(region.read(5).unwrap().unwrap().as_slice(), ChunkBlocks::filled(6).as_slice());
+
+        // The replaced sectors are reused instead of growing the file forever
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert!(len <= (HEADER_SECTORS as u64 + 8) * SECTOR_SIZE, "{len}");
+    }
+
+    #[test]
+    fn free_sectors_are_found_when_reopening() {
+        let dir = TempDir::new("region_reopen_free");
+        let path = dir.path().join("r.0.0.0.region");
+
+        {
+            let mut region = RegionFile::open(&path).unwrap();
+            region.write(0, &ChunkBlocks::filled(1)).unwrap();
+            region.write(1, &ChunkBlocks::filled(2)).unwrap();
+            // Frees the sectors chunk 0 used at first
+            region.write(0, &ChunkBlocks::filled(3)).unwrap();
+        }
+
+        let mut region = RegionFile::open(&path).unwrap();
+        let used = [region.slots[0].range().unwrap(), region.slots[1].range().unwrap()];
+        region.write(2, &ChunkBlocks::filled(4)).unwrap();
+        let new = region.slots[2].range().unwrap();
+        assert_eq!(new.start, HEADER_SECTORS);
+        assert!(used.iter().all(|range| new.end <= range.start || new.start >= range.end));
+        assert_eq!(region.read(0).unwrap().unwrap().as_slice(), ChunkBlocks::filled(3).as_slice());
+        assert_eq!(region.read(1).unwrap().unwrap().as_slice(), ChunkBlocks::filled(2).as_slice());
+    }
+
+    #[test]
+    fn corrupt_table_entries_read_as_missing() {
+        let dir = TempDir::new("region_corrupt_table");
+        let path = dir.path().join("r.0.0.0.region");
+
+        {
+            let mut region = RegionFile::open(&path).unwrap();
+            for i in 0..6 {
+                region.write(i, &ChunkBlocks::filled(i as BlockId + 1)).unwrap();
+            }
+        }
+
+        let set_entry = |index: usize, sector: u32, sectors: u32| {
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(8 + index as u64 * 8)).unwrap();
+            file.write_all(&sector.to_le_bytes()).unwrap();
+            file.write_all(&sectors.to_le_bytes()).unwrap();
+        };
+        let slot = |index: usize| {
+            let region = RegionFile::open(&path).unwrap();
+            region.slots[index]
+        };
+        // Overflows the sector number
+        set_entry(0, u32::MAX - 1, 5);
+        // Inside the header
+        set_entry(1, 0, 1);
+        // Past the end of the file
+        set_entry(2, 1000, 1);
+        // Chunk 4 grown over chunk 5's data
+        let five = slot(5);
+        set_entry(4, slot(4).sector, five.sector + five.sectors - slot(4).sector);
+
+        let mut region = RegionFile::open(&path).unwrap();
+        for i in [0, 1, 2, 4, 5] {
+            assert!(region.read(i).unwrap().is_none(), "chunk {i}");
+        }
+        assert_eq!(region.read(3).unwrap().unwrap().as_slice(), ChunkBlocks::filled(4).as_slice());
+
+        // Rewriting the dropped chunks never touches the ones still in use
+        for i in [0, 1, 2, 4, 5] {
+            region.write(i, &pattern(i as u32)).unwrap();
+        }
+        assert_eq!(region.read(3).unwrap().unwrap().as_slice(), ChunkBlocks::filled(4).as_slice());
+        assert_eq!(region.read(4).unwrap().unwrap().as_slice(), pattern(4).as_slice());
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_regions() {
+        let dir = TempDir::new("region_garbage");
+        let path = dir.path().join("garbage.region");
+        std::fs::write(&path, vec![1u8; 9000]).unwrap();
+        assert!(RegionFile::open(&path).is_err());
+    }
+}